@@ -6,7 +6,7 @@ use tracing::*;
 
 /// trait to authorize `reqwest::Request`, might add more authorization method in the future
 #[async_trait]
-pub trait AuthenticatorTrait {
+pub trait AuthenticatorTrait: Send + Sync {
     async fn authorize(&self, req: &mut Request) -> Result<()>;
 }
 
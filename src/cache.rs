@@ -0,0 +1,125 @@
+use crate::proto::chat::ChatCompletionResponse;
+use http::{HeaderName, HeaderValue};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// fnv-1a of `bytes`, unlike `DefaultHasher` stable across rust versions and processes
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// cache key of a request, the hash picks candidates, the kept bytes must match as well,
+/// so a hash collision is never served another request's response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    hash: u64,
+    bytes: Vec<u8>,
+}
+
+impl CacheKey {
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// LRU cache of chat completion response, keyed by serialized request and its extra headers
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    entries: Mutex<VecDeque<(CacheKey, ChatCompletionResponse)>>,
+}
+
+impl ResponseCache {
+    /// create a cache holding at most `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// cache key of serialized request body and extra http headers of the call
+    pub fn key(body: &[u8], headers: &[(HeaderName, HeaderValue)]) -> CacheKey {
+        let mut bytes = body.to_vec();
+        // compact json has no raw newline, headers could not be confused with body
+        for (name, value) in headers {
+            bytes.push(b'\n');
+            bytes.extend_from_slice(name.as_str().as_bytes());
+            bytes.push(b':');
+            bytes.extend_from_slice(value.as_bytes());
+        }
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        CacheKey {
+            hash: hasher.finish(),
+            bytes,
+        }
+    }
+
+    /// get cached response and mark it as most recently used
+    pub fn get(&self, key: &CacheKey) -> Option<ChatCompletionResponse> {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        let pos = entries.iter().position(|(k, _)| k == key)?;
+        let entry = entries.remove(pos)?;
+        let rep = entry.1.clone();
+        entries.push_back(entry);
+        Some(rep)
+    }
+
+    /// put response into cache, evict least recently used one if full
+    pub fn put(&self, key: CacheKey, rep: ChatCompletionResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        entries.retain(|(k, _)| *k != key);
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((key, rep));
+    }
+
+    /// number of cached responses
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("cache lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// drop all cached responses
+    pub fn clear(&self) {
+        self.entries.lock().expect("cache lock poisoned").clear();
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_response_cache_key_ok() {
+    let cache = ResponseCache::new(2);
+    let key = ResponseCache::key(b"{}", &[]);
+    cache.put(key.clone(), ChatCompletionResponse::default());
+    assert!(cache.get(&key).is_some());
+
+    // same hash but different bytes is a miss
+    let collision = CacheKey {
+        hash: key.hash,
+        bytes: b"{\"model\":\"m\"}".to_vec(),
+    };
+    assert!(cache.get(&collision).is_none());
+
+    let beta = ResponseCache::key(
+        b"{}",
+        &[(
+            HeaderName::from_static("openai-beta"),
+            HeaderValue::from_static("assistants=v2"),
+        )],
+    );
+    assert_ne!(beta, key);
+    assert!(cache.get(&beta).is_none());
+}
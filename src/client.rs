@@ -1,15 +1,22 @@
 use crate::auth::*;
+use crate::cache::*;
 use crate::error::*;
+use crate::proto::form::FormBuilder;
 use crate::proto::*;
+use crate::usage::*;
 use http::HeaderName;
 use http::HeaderValue;
 use http::Method;
+use http::StatusCode;
 use reqwest::multipart::Form;
 use reqwest::Body;
 use reqwest::Response;
 use smart_default::SmartDefault;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use sys::ModelListResponse;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use url::Url;
 
@@ -27,7 +34,83 @@ use url::Url;
 #[derive(SmartDefault)]
 pub struct ClientBuilder {
     pub base_url: Option<Url>,
-    pub authenticator: Option<Box<dyn AuthenticatorTrait>>,
+    pub authenticator: Option<Arc<dyn AuthenticatorTrait>>,
+    pub retry: RetryPolicy,
+    pub cache: Option<Arc<ResponseCache>>,
+    pub timeouts: Timeouts,
+    pub proxy: Option<Url>,
+    pub organization: Option<String>,
+    pub project: Option<String>,
+    pub default_model: Option<String>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub http2_prior_knowledge: bool,
+    #[cfg(feature = "record")]
+    pub recorder: Option<Arc<crate::record::Recorder>>,
+    #[cfg(feature = "record")]
+    pub replayer: Option<Arc<crate::record::Replayer>>,
+}
+
+/// timeouts of a call, `None` means no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// timeout to establish connection, only applied via `ClientBuilder::with_timeouts`
+    pub connect: Option<Duration>,
+    /// timeout of the whole request, including reading response body
+    pub total: Option<Duration>,
+    /// max idle time between two chunks of a stream
+    pub read_idle: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn with_connect(mut self, connect: Duration) -> Self {
+        self.connect = Some(connect);
+        self
+    }
+
+    pub fn with_total(mut self, total: Duration) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    pub fn with_read_idle(mut self, read_idle: Duration) -> Self {
+        self.read_idle = Some(read_idle);
+        self
+    }
+
+    /// fill unset timeouts from `defaults`
+    pub fn or(self, defaults: Timeouts) -> Self {
+        Self {
+            connect: self.connect.or(defaults.connect),
+            total: self.total.or(defaults.total),
+            read_idle: self.read_idle.or(defaults.read_idle),
+        }
+    }
+}
+
+/// retry policy for transient failures, e.g. `429`, `5xx` or connection drop
+#[derive(Debug, Clone, SmartDefault)]
+pub struct RetryPolicy {
+    /// max retries after first attempt, `0` means never retry
+    pub max_retries: usize,
+    /// backoff before first retry, doubled on each following retry
+    #[default(Duration::from_millis(500))]
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+        }
+    }
+
+    /// delay before the `attempt`-th retry, `attempt` start from 1
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = attempt.saturating_sub(1).min(16) as u32;
+        self.backoff.saturating_mul(2u32.pow(exp))
+    }
 }
 
 impl ClientBuilder {
@@ -38,7 +121,7 @@ impl ClientBuilder {
         Ok(self)
     }
 
-    /// config version
+    /// config version, optional, skip it for gateways serving api at root, e.g. `https://host/chat/completions`
     pub fn with_version(mut self, version: impl AsRef<str>) -> Result<Self> {
         let base_url = self
             .base_url
@@ -59,7 +142,88 @@ impl ClientBuilder {
         mut self,
         authenticator: impl AuthenticatorTrait + 'static,
     ) -> Result<Self> {
-        self.authenticator = Some(Box::new(authenticator));
+        self.authenticator = Some(Arc::new(authenticator));
+        Ok(self)
+    }
+
+    /// config retry policy, currently applied to streaming requests before first frame
+    /// and multipart uploads
+    pub fn with_retry(mut self, max_retries: usize, backoff: Duration) -> Result<Self> {
+        self.retry = RetryPolicy::new(max_retries, backoff);
+        Ok(self)
+    }
+
+    /// config in-memory LRU cache for deterministic chat completion requests
+    pub fn with_cache(mut self, capacity: usize) -> Result<Self> {
+        self.cache = Some(Arc::new(ResponseCache::new(capacity)));
+        Ok(self)
+    }
+
+    /// config max idle time between two chunks of a stream, stream is terminated once exceeded
+    pub fn with_stream_idle_timeout(mut self, timeout: Duration) -> Result<Self> {
+        self.timeouts.read_idle = Some(timeout);
+        Ok(self)
+    }
+
+    /// config default timeouts, per call `timeout` overrides `total`
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Result<Self> {
+        self.timeouts = timeouts;
+        Ok(self)
+    }
+
+    /// config proxy for all traffic of this client, e.g. `http://127.0.0.1:8080`
+    pub fn with_proxy(mut self, proxy: impl AsRef<str>) -> Result<Self> {
+        self.proxy = Some(Url::parse(proxy.as_ref())?);
+        Ok(self)
+    }
+
+    /// config organization, sent as `OpenAI-Organization` header
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Result<Self> {
+        self.organization = Some(organization.into());
+        Ok(self)
+    }
+
+    /// config project, sent as `OpenAI-Project` header
+    pub fn with_project(mut self, project: impl Into<String>) -> Result<Self> {
+        self.project = Some(project.into());
+        Ok(self)
+    }
+
+    /// config model used by requests which don't name one, see `Client::default_model`
+    pub fn with_default_model(mut self, model: impl Into<String>) -> Result<Self> {
+        self.default_model = Some(model.into());
+        Ok(self)
+    }
+
+    /// keep idle pooled connections for `timeout`, default of `reqwest` is 90 seconds
+    pub fn with_pool_idle_timeout(mut self, timeout: Duration) -> Result<Self> {
+        self.pool_idle_timeout = Some(timeout);
+        Ok(self)
+    }
+
+    /// max idle connections kept per host
+    pub fn with_pool_max_idle_per_host(mut self, max: usize) -> Result<Self> {
+        self.pool_max_idle_per_host = Some(max);
+        Ok(self)
+    }
+
+    /// talk http/2 without upgrade negotiation, only for servers known to support it, e.g. h2c gateways
+    pub fn with_http2_prior_knowledge(mut self, enabled: bool) -> Result<Self> {
+        self.http2_prior_knowledge = enabled;
+        Ok(self)
+    }
+
+    /// record every call into a json file, for golden-file tests
+    #[cfg(feature = "record")]
+    pub fn with_recorder(mut self, recorder: Arc<crate::record::Recorder>) -> Result<Self> {
+        self.recorder = Some(recorder);
+        Ok(self)
+    }
+
+    /// serve calls from a recording, api is never called
+    #[cfg(feature = "record")]
+    pub fn with_replayer(mut self, replayer: Arc<crate::record::Replayer>) -> Result<Self> {
+        self.replayer = Some(replayer);
         Ok(self)
     }
 
@@ -68,28 +232,106 @@ impl ClientBuilder {
         let Self {
             base_url,
             authenticator,
+            retry,
+            cache,
+            timeouts,
+            proxy,
+            organization,
+            project,
+            default_model,
+            pool_idle_timeout,
+            pool_max_idle_per_host,
+            http2_prior_knowledge,
+            #[cfg(feature = "record")]
+            recorder,
+            #[cfg(feature = "record")]
+            replayer,
         } = self;
 
         let base_url = base_url.ok_or(Error::ClientBuild)?;
 
         let authenticator = authenticator.ok_or(Error::ClientBuild)?;
 
+        let mut client = reqwest::Client::builder();
+
+        if let Some(connect) = timeouts.connect {
+            client = client.connect_timeout(connect);
+        }
+
+        if let Some(timeout) = pool_idle_timeout {
+            client = client.pool_idle_timeout(timeout);
+        }
+
+        if let Some(max) = pool_max_idle_per_host {
+            client = client.pool_max_idle_per_host(max);
+        }
+
+        if http2_prior_knowledge {
+            client = client.http2_prior_knowledge();
+        }
+
+        if let Some(proxy) = proxy.as_ref() {
+            client = client.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+
+        let mut headers = http::HeaderMap::new();
+        if let Some(organization) = organization.as_ref() {
+            headers.insert("openai-organization", HeaderValue::from_str(organization)?);
+        }
+        if let Some(project) = project.as_ref() {
+            headers.insert("openai-project", HeaderValue::from_str(project)?);
+        }
+        client = client.default_headers(headers);
+
+        let client = client.build()?;
+
         Ok(Client {
             base_url,
             authenticator,
-            client: reqwest::Client::new(),
+            client,
+            retry,
+            cache,
+            timeouts,
+            organization,
+            project,
+            default_model,
+            models: Arc::default(),
+            usage: Arc::default(),
+            #[cfg(feature = "record")]
+            recorder,
+            #[cfg(feature = "record")]
+            replayer,
         })
     }
 }
 
-/// OpenAI API client
+/// OpenAI API client, cheap to clone, clones share connection pool, authenticator and cache
+#[derive(Clone)]
 pub struct Client {
     base_url: Url,
-    authenticator: Box<dyn AuthenticatorTrait>,
+    authenticator: Arc<dyn AuthenticatorTrait>,
     client: reqwest::Client,
+    retry: RetryPolicy,
+    cache: Option<Arc<ResponseCache>>,
+    timeouts: Timeouts,
+    organization: Option<String>,
+    project: Option<String>,
+    default_model: Option<String>,
+    models: Arc<RwLock<Option<(Instant, ModelListResponse)>>>,
+    usage: Arc<UsageTracker>,
+    #[cfg(feature = "record")]
+    recorder: Option<Arc<crate::record::Recorder>>,
+    #[cfg(feature = "record")]
+    replayer: Option<Arc<crate::record::Replayer>>,
 }
 
 impl Client {
+    /// official openai base url
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.openai.com";
+
+    /// official openai api version
+    pub const DEFAULT_VERSION: &'static str = "v1";
+
     /// create client from customized env file, convenient for development, use `dotenv` crate
     pub fn from_env_file(env: impl AsRef<str>) -> Result<Self> {
         let _ = dotenv::from_filename(env.as_ref());
@@ -103,15 +345,75 @@ impl Client {
     }
 
     /// create client from environment variables
+    ///
+    /// - `OPENAI_API_URL`: all in one url with key as user, e.g. `https://sk-xxx@api.openai.com/v1`
+    /// - `OPENAI_API_KEY`: required if `OPENAI_API_URL` is not set
+    /// - `OPENAI_API_BASE_URL`: default to `https://api.openai.com`
+    /// - `OPENAI_API_VERSION`: default to `v1` with default base url, otherwise skipped when unset
+    ///
+    /// optional, skipped when unset:
+    ///
+    /// - `OPENAI_API_PROXY`: proxy url of all traffic
+    /// - `OPENAI_API_ORG`: organization id, see `ClientBuilder::with_organization`
+    /// - `OPENAI_API_PROJECT`: project id, see `ClientBuilder::with_project`
+    /// - `OPENAI_API_TIMEOUT_SECS`: default total timeout of a call in seconds
+    /// - `OPENAI_API_DEFAULT_MODEL`: see `ClientBuilder::with_default_model`
     pub fn from_env() -> Result<Self> {
-        let base_url = std::env::var("OPENAI_API_BASE_URL")?;
+        if let Ok(url) = std::env::var("OPENAI_API_URL") {
+            let mut url = Url::parse(&url)?;
+            let key = match url.username() {
+                "" => std::env::var("OPENAI_API_KEY")?,
+                key => key.to_string(),
+            };
+            url.set_username("").map_err(|_| Error::ClientBuild)?;
+            url.set_password(None).map_err(|_| Error::ClientBuild)?;
+            let builder = Self::builder()
+                .with_base_url(url)?
+                .with_authenticator(Bearer::new(key))?;
+            return Self::with_env_options(builder)?.build();
+        }
+
         let key = std::env::var("OPENAI_API_KEY")?;
-        let version = std::env::var("OPENAI_API_VERSION")?;
-        Self::builder()
-            .with_base_url(base_url)?
-            .with_version(version)?
-            .with_authenticator(Bearer::new(key))?
-            .build()
+        let (base_url, version) = match std::env::var("OPENAI_API_BASE_URL") {
+            Ok(base_url) => (base_url, std::env::var("OPENAI_API_VERSION").ok()),
+            Err(_) => (
+                Self::DEFAULT_BASE_URL.to_string(),
+                Some(
+                    std::env::var("OPENAI_API_VERSION")
+                        .unwrap_or(Self::DEFAULT_VERSION.to_string()),
+                ),
+            ),
+        };
+        let mut builder = Self::builder().with_base_url(base_url)?;
+        if let Some(version) = version {
+            builder = builder.with_version(version)?;
+        }
+        Self::with_env_options(builder.with_authenticator(Bearer::new(key))?)?.build()
+    }
+
+    /// apply optional env vars of `from_env`
+    fn with_env_options(mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        if let Some(proxy) = var("OPENAI_API_PROXY") {
+            builder = builder.with_proxy(proxy)?;
+        }
+        if let Some(organization) = var("OPENAI_API_ORG") {
+            builder = builder.with_organization(organization)?;
+        }
+        if let Some(project) = var("OPENAI_API_PROJECT") {
+            builder = builder.with_project(project)?;
+        }
+        if let Some(secs) = var("OPENAI_API_TIMEOUT_SECS") {
+            let secs = secs
+                .parse::<u64>()
+                .map_err(|_| Error::InvalidEnvVar("OPENAI_API_TIMEOUT_SECS", secs.clone()))?;
+            builder.timeouts.total = Some(Duration::from_secs(secs));
+        }
+        if let Some(model) = var("OPENAI_API_DEFAULT_MODEL") {
+            builder = builder.with_default_model(model)?;
+        }
+        Ok(builder)
     }
 
     /// create a client builder
@@ -119,32 +421,206 @@ impl Client {
         ClientBuilder::default()
     }
 
+    /// base url of this client, version included
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// retry policy of this client
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// response cache of this client, if configured
+    pub fn cache(&self) -> Option<&ResponseCache> {
+        self.cache.as_deref()
+    }
+
+    /// underlying http client, shares connection pool with api calls
+    pub fn http(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// default timeouts of this client
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// organization sent with every call, if configured
+    pub fn organization(&self) -> Option<&str> {
+        self.organization.as_deref()
+    }
+
+    /// project sent with every call, if configured
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    /// model used by requests which don't name one, if configured
+    pub fn default_model(&self) -> Option<&str> {
+        self.default_model.as_deref()
+    }
+
+    /// max idle time between two stream chunks, if configured
+    pub fn stream_idle_timeout(&self) -> Option<Duration> {
+        self.timeouts.read_idle
+    }
+
+    /// usage totals of this client and its clones
+    pub fn usage_tracker(&self) -> &Arc<UsageTracker> {
+        &self.usage
+    }
+
+    /// current usage totals, see `UsageTracker::snapshot`
+    pub fn usage_snapshot(&self) -> UsageSnapshot {
+        self.usage.snapshot()
+    }
+
+    /// send a single user prompt and return reply text of first choice
+    pub async fn chat(
+        &self,
+        model: impl Into<String>,
+        prompt: impl Into<String>,
+        timeout: Option<Duration>,
+    ) -> Result<String> {
+        chat::ChatCompletionRequest::simple(model, prompt.into())?
+            .call_once(self, timeout)
+            .await?
+            .text()
+            .ok_or(Error::NoContent)
+    }
+
+    /// prime connection pool with a `HEAD` of base url, so the next call skips tcp and tls handshake,
+    /// any status is fine, only failing to connect is an error
+    pub async fn warmup(&self, timeout: Option<Duration>) -> Result<()> {
+        let started = Instant::now();
+        let mut builder = self.client.head(self.base_url.clone());
+        if let Some(timeout) = timeout.or(self.timeouts.total) {
+            builder = builder.timeout(timeout);
+        }
+        let rep = builder.send().await?;
+        debug!(status = %rep.status(), elapsed = ?started.elapsed(), "connection warmed up");
+        Ok(())
+    }
+
     /// list all models available
     pub async fn models(&self, timeout: Option<Duration>) -> Result<ModelListResponse> {
         let rep = self
-            .call_impl(Method::GET, "models", [], None, None, timeout)
+            .request(
+                Method::GET,
+                "models",
+                [(
+                    http::header::ACCEPT,
+                    HeaderValue::from_static("application/json"),
+                )],
+                Payload::None,
+                timeout,
+            )
             .await?;
 
-        let status = rep.status();
+        read_json(rep).await
+    }
 
-        let rep: serde_json::Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
+    /// same as `models`, but memoized for `ttl` and shared by clones of this client,
+    /// concurrent calls on an empty or expired cache wait for a single upstream request
+    pub async fn models_cached(
+        &self,
+        ttl: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<ModelListResponse> {
+        let fresh = |cached: &Option<(Instant, ModelListResponse)>| {
+            cached
+                .as_ref()
+                .filter(|(at, _)| at.elapsed() < ttl)
+                .map(|(_, models)| models.clone())
+        };
 
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_client_error() || status.is_server_error() {
-                error!("REP: {}", l);
-            } else {
-                trace!("REP: {}", l);
-            }
+        if let Some(models) = fresh(&*self.models.read().await) {
+            return Ok(models);
         }
 
-        if !status.is_success() {
-            return Err(Error::ApiError(status.as_u16()));
+        let mut cached = self.models.write().await;
+
+        // someone else may have refreshed while waiting for the lock
+        if let Some(models) = fresh(&cached) {
+            return Ok(models);
         }
 
-        Ok(serde_json::from_value(rep)?)
+        let models = self.models(timeout).await?;
+        *cached = Some((Instant::now(), models.clone()));
+        Ok(models)
+    }
+
+    /// fetch models and replace the cache of `models_cached`
+    pub async fn models_refresh(&self, timeout: Option<Duration>) -> Result<ModelListResponse> {
+        let mut cached = self.models.write().await;
+        let models = self.models(timeout).await?;
+        *cached = Some((Instant::now(), models.clone()));
+        Ok(models)
+    }
+
+    /// check model `id` is available, consult `models_cached` with `ttl`
+    pub async fn has_model(
+        &self,
+        id: impl AsRef<str>,
+        ttl: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let models = self.models_cached(ttl, timeout).await?;
+        Ok(models.data.iter().any(|m| m.id == id.as_ref()))
+    }
+
+    /// embed `input` with `model` in one call, vectors are in the order of `input`,
+    /// use `EmbeddingRequest` for more options
+    pub async fn embeddings(
+        &self,
+        model: impl Into<String>,
+        input: impl IntoIterator<Item = impl Into<String>>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let rep = embedding::EmbeddingRequest::new(model, input)
+            .call(self, timeout)
+            .await?;
+        Ok(rep.into_vectors())
+    }
+
+    /// send `body` as json to an endpoint not modeled by this crate, e.g. `rerank`,
+    /// and decode the json reply, non-2xx status is returned as error, see `read_json`
+    pub async fn call_json<Req, Rep>(
+        &self,
+        method: Method,
+        uri: impl AsRef<str>,
+        body: &Req,
+        timeout: Option<Duration>,
+    ) -> Result<Rep>
+    where
+        Req: serde::Serialize,
+        Rep: serde::de::DeserializeOwned,
+    {
+        let rep = self
+            .request(
+                method,
+                uri,
+                [
+                    (
+                        http::header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    ),
+                    (
+                        http::header::ACCEPT,
+                        HeaderValue::from_static("application/json"),
+                    ),
+                ],
+                Payload::Json(Body::from(crate::json::to_vec(body)?)),
+                timeout,
+            )
+            .await?;
+
+        read_json(rep).await
     }
 
-    /// do the actual call
+    /// do the actual call, `timeout` falls back to client default `Timeouts::total`
+    #[deprecated(note = "use `Client::request` with `Payload`")]
     pub async fn call_impl(
         &self,
         method: Method,
@@ -154,6 +630,103 @@ impl Client {
         form: Option<Form>,
         timeout: Option<Duration>,
     ) -> Result<Response> {
+        let payload = match (body, form) {
+            (Some(_), Some(_)) => {
+                error!("both body and multipart form are given");
+                return Err(Error::ConflictingBody);
+            }
+            (Some(body), None) => Payload::Json(body),
+            (None, Some(form)) => Payload::Multipart(form),
+            (None, None) => Payload::None,
+        };
+        self.request(method, uri, headers, payload, timeout).await
+    }
+
+    /// do the actual call, `timeout` falls back to client default `Timeouts::total`
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: impl AsRef<str>,
+        headers: impl IntoIterator<Item = (HeaderName, HeaderValue)>,
+        payload: Payload,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        let timeout = timeout.or(self.timeouts.total);
+        self.send(method, uri, headers, payload, timeout, None)
+            .await
+    }
+
+    /// same as `request`, but without any total timeout, for long running body streams,
+    /// caller should bound reading with an idle timeout instead
+    pub async fn request_streaming(
+        &self,
+        method: Method,
+        uri: impl AsRef<str>,
+        headers: impl IntoIterator<Item = (HeaderName, HeaderValue)>,
+        payload: Payload,
+    ) -> Result<Response> {
+        self.send(method, uri, headers, payload, None, None).await
+    }
+
+    /// post multipart form to `uri`, retried by `retry_policy` on retryable errors,
+    /// a streamed form body can not be replayed, so `form` is called again for every attempt,
+    /// non-2xx reply of last attempt is returned as error
+    pub(crate) async fn request_multipart<F, Fut>(
+        &self,
+        uri: &str,
+        timeout: Option<Duration>,
+        mut form: F,
+    ) -> Result<Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<FormBuilder>>,
+    {
+        let timeout = timeout.or(self.timeouts.total);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let form = form().await?;
+            let key = form.record_key();
+            let res = match self
+                .send(
+                    Method::POST,
+                    uri,
+                    [],
+                    Payload::Multipart(form.build()),
+                    timeout,
+                    Some(key),
+                )
+                .await
+            {
+                Ok(rep) if rep.status().is_success() => return Ok(rep),
+                Ok(rep) => Err(read_error(rep).await),
+                Err(e) => Err(e),
+            };
+            match res {
+                Err(e) if attempt <= self.retry.max_retries && e.is_retryable() => {
+                    let delay = self.retry.delay(attempt);
+                    warn!(%uri, %attempt, ?delay, "multipart upload failed, retry: {:?}", e);
+                    tokio::time::sleep(delay).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// `form_key` stands for a multipart body in recordings, which has no bytes to hash,
+    /// see `FormBuilder::record_key`
+    async fn send(
+        &self,
+        method: Method,
+        uri: impl AsRef<str>,
+        headers: impl IntoIterator<Item = (HeaderName, HeaderValue)>,
+        payload: Payload,
+        timeout: Option<Duration>,
+        form_key: Option<String>,
+    ) -> Result<Response> {
+        #[cfg(not(feature = "record"))]
+        let _ = form_key;
+
         let path = std::path::PathBuf::from(self.base_url.path()).join(uri.as_ref());
 
         let url = self.base_url.join(path.to_str().expect("?"))?;
@@ -168,20 +741,807 @@ impl Client {
             builder = builder.header(k, v);
         }
 
-        if let Some(body) = body {
-            builder = builder.body(body);
-        }
-
-        if let Some(form) = form {
-            builder = builder.multipart(form);
-        }
+        builder = match payload {
+            Payload::Json(body) => builder.body(body),
+            Payload::Multipart(form) => builder.multipart(form),
+            Payload::None => builder,
+        };
 
         let mut req = builder.build()?;
 
+        #[cfg(feature = "record")]
+        let (method, body) = (
+            req.method().clone(),
+            match form_key {
+                Some(key) => key.into_bytes(),
+                None => req
+                    .body()
+                    .and_then(|b| b.as_bytes())
+                    .unwrap_or_default()
+                    .to_vec(),
+            },
+        );
+
+        #[cfg(feature = "record")]
+        if let Some(replayer) = self.replayer.as_ref() {
+            return replayer.replay(&method, uri.as_ref(), &body);
+        }
+
         self.authenticator.authorize(&mut req).await?;
 
         let rep = self.client.execute(req).await?; //.error_for_status()?;
 
+        #[cfg(feature = "record")]
+        if let Some(recorder) = self.recorder.as_ref() {
+            return recorder.record(&method, uri.as_ref(), &body, rep);
+        }
+
         Ok(rep)
     }
 }
+
+/// max bytes of a non-json body, of any status, kept in `Error::NonJsonResponse`
+pub const ERROR_BODY_LIMIT: usize = 512;
+
+/// read json body of `rep` into `T`, see `parse_response`
+pub(crate) async fn read_json<T: serde::de::DeserializeOwned>(rep: Response) -> Result<T> {
+    if !rep.status().is_success() {
+        return Err(read_error(rep).await);
+    }
+
+    let status = rep.status();
+    let endpoint = rep.url().path().to_string();
+    let content_type = content_type_of(&rep);
+
+    let bytes = rep.bytes().await?;
+
+    parse_response(bytes.as_ref(), status, &content_type, &endpoint)
+}
+
+fn content_type_of(rep: &Response) -> String {
+    rep.headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// body which is not json at all, e.g. html page of a gateway, with first bytes as snippet
+fn non_json_response(bytes: &[u8], status: StatusCode, content_type: &str, url: &str) -> Error {
+    let snippet = String::from_utf8_lossy(&bytes[..bytes.len().min(ERROR_BODY_LIMIT)])
+        .trim()
+        .to_string();
+    error!(%url, %status, %content_type, body = %snippet, "non-json response");
+    Error::NonJsonResponse {
+        status: status.as_u16(),
+        content_type: content_type.to_string(),
+        snippet,
+    }
+}
+
+/// decode body of a call to `endpoint` into `T` in a single pass, non-2xx is turned into
+/// error as `read_error` does, a body not matching `T` is `Error::ResponseParse`,
+/// unless it is not json at all and not declared as json, which is `Error::NonJsonResponse`
+///
+/// the pretty printed `REP:` trace is only built when trace level is enabled,
+/// large responses are not parsed twice otherwise
+pub(crate) fn parse_response<T: serde::de::DeserializeOwned>(
+    bytes: &[u8],
+    status: StatusCode,
+    content_type: &str,
+    endpoint: &str,
+) -> Result<T> {
+    if !status.is_success() {
+        return Err(error_of_body(bytes, status, content_type, endpoint));
+    }
+
+    if tracing::enabled!(Level::TRACE) {
+        if let Ok(mut rep) = crate::json::from_slice::<serde_json::Value>(bytes) {
+            crate::json::redact(&mut rep);
+            for l in serde_json::to_string_pretty(&rep)?.lines() {
+                trace!("REP: {}", l);
+            }
+        }
+    }
+
+    crate::json::from_slice::<T>(bytes).map_err(|source| {
+        // only on failure, tell a schema mismatch from a body which is not json at all
+        if !content_type.contains("json")
+            && crate::json::from_slice::<serde::de::IgnoredAny>(bytes).is_err()
+        {
+            return non_json_response(bytes, status, content_type, endpoint);
+        }
+        error!(%endpoint, %status, "failed to parse json body: {:?}", source);
+        Error::ResponseParse {
+            endpoint: endpoint.to_string(),
+            status: status.as_u16(),
+            source,
+        }
+    })
+}
+
+/// consume body of a non-2xx `rep` into error, see `read_json`
+pub(crate) async fn read_error(rep: Response) -> Error {
+    let status = rep.status();
+    let url = rep.url().path().to_string();
+    let content_type = content_type_of(&rep);
+
+    match rep.bytes().await {
+        Ok(bytes) => error_of_body(bytes.as_ref(), status, &content_type, &url),
+        Err(e) => {
+            error!(%url, %status, "failed to read error body: {:?}", e);
+            Error::ApiError(status.as_u16())
+        }
+    }
+}
+
+/// error of a non-2xx reply with `bytes` as body
+fn error_of_body(bytes: &[u8], status: StatusCode, content_type: &str, url: &str) -> Error {
+    match crate::json::from_slice::<serde_json::Value>(bytes) {
+        Ok(rep) => {
+            if let Ok(pretty) = serde_json::to_string_pretty(&rep) {
+                for l in pretty.lines() {
+                    error!("REP: {}", l);
+                }
+            }
+            match ApiErrorResponse::parse(&rep) {
+                Some(e) => error!(%url, %status, message = %e.error.message(), "call failed"),
+                None => error!(%url, %status, "call failed"),
+            }
+            Error::ApiError(status.as_u16())
+        }
+        Err(_) => non_json_response(bytes, status, content_type, url),
+    }
+}
+
+/// request body of `Client::request`, either raw body or multipart form, never both
+#[derive(Debug, Default)]
+pub enum Payload {
+    /// raw body, content type is set by caller, e.g. `application/json`
+    Json(Body),
+    Multipart(Form),
+    #[default]
+    None,
+}
+
+/// run `fut` until finished or `token` fired, the latter return `Error::Cancelled`
+pub async fn cancellable<T>(
+    token: &CancellationToken,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => {
+            warn!("call cancelled");
+            Err(Error::Cancelled)
+        }
+        r = fut => r,
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_chat_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(
+        200,
+        "application/json",
+        r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#.to_string(),
+    )])
+    .await;
+
+    let reply = server.client()?.chat("m", "answer", None).await?;
+    assert_eq!(reply, "42");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_from_env_without_version_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(
+        200,
+        "application/json",
+        r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#.to_string(),
+    )])
+    .await;
+
+    let _env = crate::tests::ENV_LOCK.lock().await;
+    let _saved = crate::tests::EnvGuard::save(&[
+        "OPENAI_API_URL",
+        "OPENAI_API_BASE_URL",
+        "OPENAI_API_KEY",
+        "OPENAI_API_VERSION",
+    ]);
+
+    std::env::remove_var("OPENAI_API_URL");
+    std::env::set_var("OPENAI_API_BASE_URL", &server.base_url);
+    std::env::set_var("OPENAI_API_KEY", "mock key");
+    std::env::remove_var("OPENAI_API_VERSION");
+
+    let client = Client::from_env()?;
+    assert_eq!(client.chat("m", "answer", None).await?, "42");
+    assert_eq!(server.paths(), ["/chat/completions"]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_from_env_defaults_ok() -> Result<()> {
+    let _env = crate::tests::ENV_LOCK.lock().await;
+
+    std::env::remove_var("OPENAI_API_URL");
+    std::env::remove_var("OPENAI_API_BASE_URL");
+    std::env::remove_var("OPENAI_API_VERSION");
+    std::env::set_var("OPENAI_API_KEY", "sk-key");
+
+    let client = Client::from_env()?;
+    assert_eq!(client.base_url().as_str(), "https://api.openai.com/v1");
+
+    std::env::remove_var("OPENAI_API_KEY");
+    std::env::set_var("OPENAI_API_URL", "https://sk-embedded@gateway.local/v2");
+
+    let client = Client::from_env()?;
+    assert_eq!(client.base_url().as_str(), "https://gateway.local/v2");
+
+    std::env::remove_var("OPENAI_API_URL");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_from_env_options_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let _env = crate::tests::ENV_LOCK.lock().await;
+
+    let options = [
+        "OPENAI_API_PROXY",
+        "OPENAI_API_ORG",
+        "OPENAI_API_PROJECT",
+        "OPENAI_API_TIMEOUT_SECS",
+        "OPENAI_API_DEFAULT_MODEL",
+    ];
+    for name in options {
+        std::env::remove_var(name);
+    }
+    std::env::remove_var("OPENAI_API_URL");
+    std::env::remove_var("OPENAI_API_VERSION");
+    std::env::set_var("OPENAI_API_BASE_URL", "http://api.invalid/v1");
+    std::env::set_var("OPENAI_API_KEY", "mock key");
+
+    let client = Client::from_env()?;
+    assert_eq!(client.organization(), None);
+    assert_eq!(client.project(), None);
+    assert_eq!(client.default_model(), None);
+    assert_eq!(client.timeouts().total, None);
+
+    std::env::set_var("OPENAI_API_ORG", "org-1");
+    assert_eq!(Client::from_env()?.organization(), Some("org-1"));
+
+    std::env::set_var("OPENAI_API_PROJECT", "proj-1");
+    assert_eq!(Client::from_env()?.project(), Some("proj-1"));
+
+    std::env::set_var("OPENAI_API_TIMEOUT_SECS", "7");
+    assert_eq!(
+        Client::from_env()?.timeouts().total,
+        Some(Duration::from_secs(7))
+    );
+    std::env::set_var("OPENAI_API_TIMEOUT_SECS", "soon");
+    assert!(matches!(
+        Client::from_env(),
+        Err(Error::InvalidEnvVar("OPENAI_API_TIMEOUT_SECS", ref v)) if v == "soon"
+    ));
+    std::env::remove_var("OPENAI_API_TIMEOUT_SECS");
+
+    std::env::set_var("OPENAI_API_DEFAULT_MODEL", "gpt-4o-mini");
+    let client = Client::from_env()?;
+    assert_eq!(client.default_model(), Some("gpt-4o-mini"));
+    let req = chat::ChatCompletionRequest::builder()
+        .with_default_model_from(&client)
+        .add_message(
+            chat::Message::builder()
+                .with_role(chat::Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .build()?;
+    assert_eq!(req.model, "gpt-4o-mini");
+    let req = chat::ChatCompletionRequest::builder()
+        .with_model("o3")
+        .with_default_model_from(&client)
+        .add_message(
+            chat::Message::builder()
+                .with_role(chat::Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .build()?;
+    assert_eq!(req.model, "o3");
+
+    let proxy = MockServer::start(vec![(
+        200,
+        "application/json",
+        r#"{"object":"list","data":[]}"#.to_string(),
+    )])
+    .await;
+    std::env::set_var("OPENAI_API_PROXY", &proxy.base_url);
+    let client = Client::from_env()?;
+    assert!(client.models(None).await?.data.is_empty());
+    assert_eq!(proxy.paths(), ["http://api.invalid/v1/models"]);
+
+    for name in options {
+        std::env::remove_var(name);
+    }
+    std::env::remove_var("OPENAI_API_BASE_URL");
+    std::env::remove_var("OPENAI_API_KEY");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_default_model_ok() -> Result<()> {
+    let builder = || {
+        Client::builder()
+            .with_base_url("http://api.invalid")?
+            .with_key("mock key")
+    };
+    let client = builder()?.with_default_model("gpt-4o-mini")?.build()?;
+    let bare = builder()?.build()?;
+
+    let message = || {
+        chat::Message::builder()
+            .with_role(chat::Role::user)
+            .with_content("hi")
+            .build()
+    };
+
+    let req = chat::ChatCompletionRequest::builder_for(&client)
+        .add_message(message())
+        .build()?;
+    assert_eq!(req.model, "gpt-4o-mini");
+
+    let req = chat::ChatCompletionRequest::builder_for(&client)
+        .with_model("o3")
+        .add_message(message())
+        .build()?;
+    assert_eq!(req.model, "o3");
+
+    let res = chat::ChatCompletionRequest::builder()
+        .add_message(message())
+        .build();
+    assert!(matches!(res, Err(Error::ChatCompletionRequestBuild)));
+
+    let res = chat::ChatCompletionRequest::builder_for(&bare)
+        .add_message(message())
+        .build();
+    assert!(matches!(res, Err(Error::ChatCompletionRequestBuild)));
+
+    let req = image::GenerationRequest::builder_for(&client)
+        .with_prompt("a cat")
+        .build()?;
+    assert_eq!(req.model, "gpt-4o-mini");
+
+    let req = embedding::EmbeddingRequest::new_for(&client, ["hi"])?;
+    assert_eq!(req.model, "gpt-4o-mini");
+    assert!(matches!(
+        embedding::EmbeddingRequest::new_for(&bare, ["hi"]),
+        Err(Error::NoDefaultModel)
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_warmup_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(404, "text/plain", String::new())]).await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_pool_idle_timeout(Duration::from_secs(300))?
+        .with_pool_max_idle_per_host(4)?
+        .build()?;
+
+    client.warmup(None).await?;
+    assert_eq!(server.hits(), 1);
+    assert_eq!(server.paths(), ["/v1"]);
+
+    let client = Client::builder()
+        .with_base_url("http://127.0.0.1:9")?
+        .with_key("mock key")?
+        .build()?;
+    assert!(client.warmup(Some(Duration::from_secs(1))).await.is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_http2_prior_knowledge_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let models = r#"{"object":"list","data":[{"id":"gpt-4o","object":"model","created":1,"owned_by":"openai"}]}"#;
+    let server = MockServer::start_h2(vec![
+        (200, "application/json", models.to_string()),
+        (200, "application/json", models.to_string()),
+    ])
+    .await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_http2_prior_knowledge(true)?
+        .build()?;
+
+    assert_eq!(client.models(None).await?.data[0].id, "gpt-4o");
+    // both calls multiplexed on the single h2 connection of mock
+    assert_eq!(client.models(None).await?.data.len(), 1);
+    assert_eq!(server.paths(), ["/v1/models", "/v1/models"]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_clone_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let rep = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#;
+
+    let server = MockServer::start(vec![
+        (200, "application/json", rep.to_string()),
+        (200, "application/json", rep.to_string()),
+    ])
+    .await;
+
+    let client = server.client()?;
+    let cloned = client.clone();
+
+    let task = tokio::spawn(async move { cloned.chat("m", "answer", None).await });
+
+    assert_eq!(task.await??, "42");
+    assert_eq!(client.chat("m", "answer", None).await?, "42");
+    assert_eq!(server.hits(), 2);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_total_timeout_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start_stalled(
+        "application/json",
+        r#"{"id":"1""#.to_string(),
+        Duration::from_secs(5),
+    )
+    .await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_timeouts(
+            Timeouts::default()
+                .with_connect(Duration::from_secs(2))
+                .with_total(Duration::from_millis(200)),
+        )?
+        .build()?;
+
+    let res = client.chat("m", "answer", None).await;
+    assert!(matches!(res, Err(Error::RequestBuild(ref e)) if e.is_timeout()));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_models_cached_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let models = r#"{"object":"list","data":[{"id":"gpt-4o","object":"model","created":1,"owned_by":"openai"}]}"#;
+
+    let server = MockServer::start(vec![
+        (200, "application/json", models.to_string()),
+        (200, "application/json", models.to_string()),
+        (200, "application/json", models.to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+    let ttl = Duration::from_secs(60);
+
+    let calls = (0..8).map(|_| {
+        let client = client.clone();
+        async move { client.models_cached(ttl, None).await }
+    });
+    for rep in futures::future::join_all(calls).await {
+        assert_eq!(rep?.data.len(), 1);
+    }
+    assert_eq!(server.hits(), 1);
+
+    assert!(client.has_model("gpt-4o", ttl, None).await?);
+    assert!(!client.has_model("gpt-5", ttl, None).await?);
+    assert_eq!(server.hits(), 1);
+
+    client.models_refresh(None).await?;
+    assert_eq!(server.hits(), 2);
+
+    client.models_cached(Duration::ZERO, None).await?;
+    assert_eq!(server.hits(), 3);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_embeddings_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(
+        200,
+        "application/json",
+        r#"{"object":"list","model":"text-embedding-3-small","data":[{"object":"embedding","index":1,"embedding":[0.5,-0.5]},{"object":"embedding","index":0,"embedding":[0.25,0.75]}],"usage":{"prompt_tokens":4,"total_tokens":4}}"#
+            .to_string(),
+    ), (
+        400,
+        "application/json",
+        r#"{"error":{"message":"input too long"}}"#.to_string(),
+    )])
+    .await;
+    let client = server.client()?;
+
+    let vectors = client
+        .embeddings("text-embedding-3-small", ["hello", "world"], None)
+        .await?;
+    assert_eq!(vectors, [vec![0.25, 0.75], vec![0.5, -0.5]]);
+    assert_eq!(server.paths(), ["/v1/embeddings"]);
+
+    let res = client
+        .embeddings("text-embedding-3-small", ["hello"], None)
+        .await;
+    assert!(matches!(res, Err(Error::ApiError(400))));
+
+    let snapshot = client.usage_snapshot();
+    assert_eq!(snapshot.requests, 1);
+    assert_eq!(snapshot.errors, 1);
+    assert_eq!(snapshot.usage.prompt_tokens, 4);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+#[allow(deprecated)]
+async fn test_call_impl_conflicting_body_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![]).await;
+    let res = server
+        .client()?
+        .call_impl(
+            Method::POST,
+            "files",
+            [],
+            Some(Body::from("{}")),
+            Some(Form::new().text("purpose", "batch")),
+            None,
+        )
+        .await;
+    assert!(matches!(res, Err(Error::ConflictingBody)));
+    assert_eq!(server.hits(), 0);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_call_json_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    #[derive(serde::Serialize)]
+    struct RerankRequest<'a> {
+        model: &'a str,
+        query: &'a str,
+        documents: Vec<&'a str>,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct RerankResult {
+        index: usize,
+        relevance_score: f64,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct RerankResponse {
+        results: Vec<RerankResult>,
+    }
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "application/json",
+            r#"{"results":[{"index":1,"relevance_score":0.9},{"index":0,"relevance_score":0.1}]}"#
+                .to_string(),
+        ),
+        (
+            400,
+            "application/json",
+            r#"{"error":{"message":"bad model","type":"invalid_request_error"}}"#.to_string(),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = RerankRequest {
+        model: "rerank",
+        query: "rust",
+        documents: vec!["python", "rust"],
+    };
+
+    let rep: RerankResponse = client.call_json(Method::POST, "rerank", &req, None).await?;
+    assert_eq!(
+        rep.results[0],
+        RerankResult {
+            index: 1,
+            relevance_score: 0.9
+        }
+    );
+
+    let res = client
+        .call_json::<_, RerankResponse>(Method::POST, "rerank", &req, None)
+        .await;
+    assert!(matches!(res, Err(Error::ApiError(400))));
+    assert_eq!(server.paths(), ["/v1/rerank", "/v1/rerank"]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+#[tracing_test::traced_test]
+async fn test_read_json_typed_ok() -> Result<()> {
+    use crate::proto::chat::ChatCompletionRequest;
+    use crate::tests::MockServer;
+
+    let content = "lorem ipsum ".repeat(10_000);
+    let rep = serde_json::json!({
+        "id": "1", "object": "chat.completion", "created": 1, "model": "m",
+        "choices": [{"index": 0, "message": {"role": "assistant", "content": content}, "finish_reason": "stop"}]
+    });
+
+    let server = MockServer::start(vec![
+        (200, "application/json", rep.to_string()),
+        (200, "text/plain", r#"{"object":"list"}"#.to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let rep = ChatCompletionRequest::simple("m", "hi")?
+        .call_once(&client, None)
+        .await?;
+    assert_eq!(rep.text().as_deref(), Some(content.as_str()));
+    // pretty printed body is still traced when trace level is on
+    assert!(logs_contain("REP:"));
+
+    // json of wrong shape is a decode error, even with a non-json content type
+    let res = client.models(None).await;
+    assert!(matches!(
+        res,
+        Err(Error::ResponseParse { ref endpoint, status: 200, .. }) if endpoint == "/v1/models"
+    ));
+
+    let ok: sys::ModelListResponse = parse_response(
+        br#"{"object":"list","data":[]}"#,
+        StatusCode::OK,
+        "application/json",
+        "/v1/models",
+    )?;
+    assert!(ok.data.is_empty());
+
+    let res = parse_response::<sys::ModelListResponse>(
+        br#"{"error":{"message":"bad key"}}"#,
+        StatusCode::UNAUTHORIZED,
+        "application/json",
+        "/v1/models",
+    );
+    assert!(matches!(res, Err(Error::ApiError(401))));
+
+    let err = parse_response::<sys::ModelListResponse>(
+        b"{\"data\":",
+        StatusCode::OK,
+        "application/json",
+        "/v1/models",
+    )
+    .expect_err("truncated body");
+    assert!(err
+        .to_string()
+        .starts_with("failed to parse response of /v1/models, status=200"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_client_non_json_body_ok() -> Result<()> {
+    use crate::proto::chat::ChatCompletionRequest;
+    use crate::tests::MockServer;
+
+    let html = format!(
+        "<html><head><title>502 Bad Gateway</title></head><body>{}</body></html>",
+        "x".repeat(ERROR_BODY_LIMIT)
+    );
+
+    let server = MockServer::start(vec![
+        (502, "text/html", html.clone()),
+        (502, "text/html", html),
+        (503, "text/plain", String::new()),
+        (200, "text/html", "<html>login</html>".to_string()),
+        (200, "text/plain", String::new()),
+        (200, "application/json", "{not json".to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = ChatCompletionRequest::simple("m", "hi")?;
+    let mut stream_req = req.clone();
+    stream_req.stream = Some(true);
+
+    let res = req.call_once(&client, None).await;
+    let Err(Error::NonJsonResponse {
+        status: 502,
+        content_type,
+        snippet,
+    }) = res
+    else {
+        panic!("unexpected result: {:?}", res.map(|_| ()));
+    };
+    assert_eq!(content_type, "text/html");
+    assert!(snippet.starts_with("<html><head><title>502 Bad Gateway"));
+    assert_eq!(snippet.len(), ERROR_BODY_LIMIT);
+
+    let res = stream_req.call_stream(&client, None).await;
+    assert!(matches!(
+        res,
+        Err(Error::NonJsonResponse { status: 502, .. })
+    ));
+    assert!(res.is_err_and(|e| e.is_retryable()));
+
+    let res = client.models(None).await;
+    assert!(
+        matches!(res, Err(Error::NonJsonResponse { status: 503, ref snippet, .. }) if snippet.is_empty())
+    );
+    assert!(res.is_err_and(|e| e.is_retryable()));
+
+    // 2xx html, e.g. a captive portal login page, is reported with its status and snippet
+    let res = req.call_once(&client, None).await;
+    assert!(matches!(
+        res,
+        Err(Error::NonJsonResponse { status: 200, ref content_type, ref snippet })
+            if content_type == "text/html" && snippet == "<html>login</html>"
+    ));
+    assert!(res.is_err_and(|e| !e.is_retryable()));
+
+    let res = client.models(None).await;
+    assert!(matches!(
+        res,
+        Err(Error::NonJsonResponse { status: 200, ref content_type, .. }) if content_type == "text/plain"
+    ));
+
+    let res = client.models(None).await;
+    assert!(matches!(res, Err(Error::ResponseParse { .. })));
+
+    Ok(())
+}
@@ -19,8 +19,13 @@ pub enum Error {
     #[cfg(feature = "opencv")]
     #[error("failed to process image with opencv: {0}")]
     Opencv(#[from] opencv::Error),
+    #[cfg(feature = "opencv")]
+    #[error("failed to decode/encode image: {0}")]
+    ImageCodec(String),
     #[error("chat completion builder without model")]
     ChatCompletionRequestBuild,
+    #[error("no model given and client has no default model")]
+    NoDefaultModel,
     #[error("failed to decode utf-8: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
     #[error("failed to send message to chat reciever")]
@@ -43,10 +48,159 @@ pub enum Error {
     GenerationRequestBuild,
     #[error("api server error code={0}")]
     ApiError(u16),
+    /// body is not json, e.g. html page of a reverse proxy, load balancer or captive portal,
+    /// 2xx included, `snippet` is the first `ERROR_BODY_LIMIT` bytes of it
+    #[error("non-json response status={status} content_type=`{content_type}`: {snippet}")]
+    NonJsonResponse {
+        status: u16,
+        content_type: String,
+        snippet: String,
+    },
+    /// 2xx body does not match expected type
+    #[error("failed to parse response of {endpoint}, status={status}: {source}")]
+    ResponseParse {
+        endpoint: String,
+        status: u16,
+        source: serde_json::Error,
+    },
     #[error("failed to build file request")]
     FileRequestBuild,
+    #[error("file purpose `{0}` requires a .jsonl file, got `{1}`")]
+    FilePurposeMismatch(String, String),
     #[error("failed to find env var")]
     Var(#[from] std::env::VarError),
+    #[error("invalid env var {0}={1}")]
+    InvalidEnvVar(&'static str, String),
+    #[error("stream closed before first data frame")]
+    StreamInterrupted,
+    #[error("no stream data received in {0:?}")]
+    StreamIdleTimeout(std::time::Duration),
+    #[error("failed to parse stream frame: {source}, raw={raw}")]
+    StreamParse {
+        raw: String,
+        source: serde_json::Error,
+    },
+    /// `event: error` frame of a stream, carrying its data
+    #[error("stream error event: {0}")]
+    StreamErrorEvent(String),
+    #[error("message {0} has neither content nor tool_calls")]
+    EmptyMessage(usize),
+    #[error("tool message {0} without tool_call_id")]
+    MissingToolCallId(usize),
+    #[error("too many stop sequences: {0}, limit {1}")]
+    TooManyStops(usize, usize),
+    #[error("tool_choice function `{0}` is not among declared tools")]
+    UnknownToolChoice(String),
+    #[error("temperature {0} out of range 0.0 ~ 2.0")]
+    InvalidTemperature(f64),
+    #[error("prompt too large: {0} chars, limit {1} chars")]
+    PromptTooLarge(usize, usize),
+    #[error("image too large: {0} bytes, limit {1} bytes")]
+    ImageTooLarge(usize, usize),
+    #[error("unsupported image format: {0}")]
+    UnsupportedImageFormat(String),
+    #[error("failed to decode base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("unknown {0} variant: {1}")]
+    UnknownVariant(&'static str, String),
+    #[error("content flagged by moderation")]
+    ContentFlagged(Box<crate::proto::moderation::ModerationResult>),
+    #[error("unsupported envelope version {0}")]
+    UnsupportedVersion(u64),
+    #[error("call cancelled")]
+    Cancelled,
+    #[error("missing template variable `{0}`")]
+    MissingTemplateVariable(String),
+    #[error("unclosed placeholder in template: {0}")]
+    TemplateSyntax(String),
+    #[error("unknown template `{0}`")]
+    UnknownTemplate(String),
+    #[cfg(feature = "record")]
+    #[error("failed to build recorded response")]
+    Record,
+    #[cfg(feature = "record")]
+    #[error("no recorded exchange for {0} {1}")]
+    ReplayUnmatched(String, String),
+    #[error("no content found in response")]
+    NoContent,
+    #[error("model refused: {0}")]
+    Refusal(String),
+    #[error("both body and multipart form are given")]
+    ConflictingBody,
 }
 
+impl Error {
+    /// transient error which is safe to retry
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ApiError(code) | Error::NonJsonResponse { status: code, .. } => {
+                *code == 429 || (500..600).contains(code)
+            }
+            Error::RequestBuild(e) => !e.is_builder(),
+            Error::StreamInterrupted => true,
+            _ => false,
+        }
+    }
+
+    /// convert into boxed trait object, convenient to work with `anyhow`, `eyre` etc.
+    pub fn into_boxed(self) -> Box<dyn std::error::Error + Send + Sync> {
+        Box::new(self)
+    }
+}
+
+// make sure error can be sent across threads and boxed
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<Error>();
+};
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// error body returned by api, e.g. `{"error": {"message": "..."}}`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ApiErrorResponse {
+    pub error: ApiErrorDetail,
+}
+
+/// some providers, e.g. openrouter, may return `error` as plain string
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ApiErrorDetail {
+    Message(String),
+    Object {
+        message: String,
+        #[serde(rename = "type")]
+        typ: Option<String>,
+        code: Option<serde_json::Value>,
+        metadata: Option<serde_json::Value>,
+    },
+}
+
+impl ApiErrorResponse {
+    /// parse error body, `None` if not in a known form
+    pub fn parse(value: &serde_json::Value) -> Option<Self> {
+        serde_json::from_value(value.clone()).ok()
+    }
+}
+
+impl ApiErrorDetail {
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Message(message) => message,
+            Self::Object { message, .. } => message,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_error_into_boxed_ok() -> anyhow::Result<()> {
+    let boxed = Error::ApiError(429).into_boxed();
+    assert_eq!(boxed.to_string(), "api server error code=429");
+    assert!(boxed.downcast_ref::<Error>().is_some());
+
+    let err: anyhow::Error = Error::ApiError(500).into();
+    assert_eq!(format!("{}", err), "api server error code=500");
+
+    Ok(())
+}
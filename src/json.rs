@@ -0,0 +1,130 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+// errors of other backends are converted into `serde_json::Error`, so callers see a single error type
+#[cfg(feature = "simd-json")]
+fn convert(e: simd_json::Error) -> serde_json::Error {
+    <serde_json::Error as serde::de::Error>::custom(e)
+}
+
+pub(crate) fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> serde_json::Result<T> {
+    #[cfg(feature = "simd-json")]
+    {
+        // simd-json parses in place
+        let mut bytes = bytes.to_vec();
+        simd_json::serde::from_slice(&mut bytes).map_err(convert)
+    }
+    #[cfg(not(feature = "simd-json"))]
+    {
+        serde_json::from_slice(bytes)
+    }
+}
+
+pub(crate) fn from_str<T: DeserializeOwned>(s: &str) -> serde_json::Result<T> {
+    from_slice(s.as_bytes())
+}
+
+pub(crate) fn to_vec<T: Serialize>(value: &T) -> serde_json::Result<Vec<u8>> {
+    #[cfg(feature = "simd-json")]
+    {
+        simd_json::serde::to_vec(value).map_err(convert)
+    }
+    #[cfg(not(feature = "simd-json"))]
+    {
+        serde_json::to_vec(value)
+    }
+}
+
+pub(crate) fn to_string<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    #[cfg(feature = "simd-json")]
+    {
+        simd_json::serde::to_string(value).map_err(convert)
+    }
+    #[cfg(not(feature = "simd-json"))]
+    {
+        serde_json::to_string(value)
+    }
+}
+
+/// strings longer than this are checked for data urls and base64 blobs before logging
+const REDACT_MIN_LEN: usize = 256;
+
+/// chars kept in front of a redacted string
+const REDACT_PREFIX_LEN: usize = 32;
+
+/// truncate data urls and base64 blobs of `value` in place, e.g. images and file contents,
+/// only meant for logging, the body sent on the wire is never redacted
+pub(crate) fn redact(value: &mut Value) {
+    match value {
+        Value::String(s) if should_redact(s) => {
+            let prefix = s.chars().take(REDACT_PREFIX_LEN).collect::<String>();
+            *s = format!("{}...({} bytes)", prefix, s.len());
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        Value::Object(values) => values.values_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn should_redact(s: &str) -> bool {
+    s.len() > REDACT_MIN_LEN
+        && (s.starts_with("data:")
+            || s.bytes().all(|b| {
+                b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=' | b'-' | b'_')
+            }))
+}
+
+#[cfg(test)]
+#[test]
+fn test_json_backend_same_as_serde_json_ok() -> crate::error::Result<()> {
+    use crate::proto::chat::*;
+    use crate::tests::*;
+
+    let lhs: ChatCompletionResponse = from_str(STEPFUN_CHAT_TOOLS_RESPONSE_JSON)?;
+    let rhs: ChatCompletionResponse = serde_json::from_str(STEPFUN_CHAT_TOOLS_RESPONSE_JSON)?;
+    assert_eq!(lhs, rhs);
+
+    let lhs: ChatCompletionStreamData = from_slice(STEPFUN_CHAT_STREAM_RESPONSE_JSON.as_bytes())?;
+    let rhs: ChatCompletionStreamData = serde_json::from_str(STEPFUN_CHAT_STREAM_RESPONSE_JSON)?;
+    assert_eq!(lhs, rhs);
+
+    let lhs: serde_json::Value = from_str(STEPFUN_CHAT_COMPLETION_REQUEST_JSON)?;
+    let rhs: serde_json::Value = serde_json::from_str(STEPFUN_CHAT_COMPLETION_REQUEST_JSON)?;
+    assert_eq!(lhs, rhs);
+
+    let req: ChatCompletionRequest = serde_json::from_str(STEPFUN_CHAT_COMPLETION_REQUEST_JSON)?;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&to_vec(&req)?)?,
+        serde_json::to_value(&req)?
+    );
+    assert_eq!(from_str::<ChatCompletionRequest>(&to_string(&req)?)?, req);
+
+    assert!(from_str::<ChatCompletionResponse>("{").is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_redact_ok() {
+    let blob = "A".repeat(1000);
+    let prompt = "a long prompt ".repeat(100);
+    let mut value = serde_json::json!({
+        "image_url": {"url": format!("data:image/png;base64,{}", blob)},
+        "data": [{"b64_json": blob}],
+        "prompt": prompt,
+        "id": "short",
+    });
+    redact(&mut value);
+
+    assert_eq!(
+        value["image_url"]["url"],
+        "data:image/png;base64,AAAAAAAAAA...(1022 bytes)"
+    );
+    assert_eq!(
+        value["data"][0]["b64_json"],
+        format!("{}...(1000 bytes)", &blob[..32])
+    );
+    assert_eq!(value["prompt"], prompt);
+    assert_eq!(value["id"], "short");
+}
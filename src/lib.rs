@@ -3,21 +3,38 @@
 /// authorization module
 pub mod auth;
 
+/// in-memory response cache
+pub mod cache;
+
 /// client to execute RESTful API
 pub mod client;
 
 /// error module
 pub mod error;
 
+/// json backend, `serde_json` by default, `simd-json` with feature `simd-json`
+pub(crate) mod json;
+
 /// proto module
 pub mod proto;
+
+/// request/response recording and replay for deterministic tests
+#[cfg(feature = "record")]
+pub mod record;
+/// token usage and call statistics
+pub mod usage;
+
 #[cfg(test)]
 pub mod tests;
 
 /// convinent prelude to import module
 pub mod prelude {
     pub use crate::auth::*;
+    pub use crate::cache::*;
     pub use crate::client::*;
     pub use crate::error::*;
     pub use crate::proto::*;
+    #[cfg(feature = "record")]
+    pub use crate::record::*;
+    pub use crate::usage::*;
 }
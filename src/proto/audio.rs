@@ -0,0 +1,504 @@
+use futures::{Stream, StreamExt};
+use http::{header::HeaderValue, Method};
+use reqwest::Body;
+use serde_with::skip_serializing_none;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::bytes::Bytes;
+use tracing::*;
+
+use crate::client::{read_error, read_json, Client, Payload};
+use crate::error::*;
+use crate::proto::form::FormBuilder;
+
+/// text to speech, reply is raw audio in `response_format`
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SpeechRequest {
+    pub model: String,
+    pub input: String,
+    pub voice: String,
+    /// `mp3` by default, `pcm` or `opus` suit streaming playback
+    pub response_format: Option<String>,
+    /// `0.25` ~ `4.0`, default `1.0`
+    pub speed: Option<f64>,
+    /// tone or style of voice, only supported by newer models
+    pub instructions: Option<String>,
+}
+
+impl SpeechRequest {
+    pub fn new(
+        model: impl Into<String>,
+        input: impl Into<String>,
+        voice: impl Into<String>,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            input: input.into(),
+            voice: voice.into(),
+            response_format: None,
+            speed: None,
+            instructions: None,
+        }
+    }
+
+    pub fn with_response_format(mut self, format: impl Into<String>) -> Self {
+        self.response_format = Some(format.into());
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// whole audio at once
+    pub async fn call(&self, client: &Client, timeout: Option<Duration>) -> Result<Bytes> {
+        let rep = client
+            .request(
+                Method::POST,
+                "audio/speech",
+                [(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                )],
+                Payload::Json(Body::from(crate::json::to_vec(self)?)),
+                timeout,
+            )
+            .await?;
+
+        if !rep.status().is_success() {
+            return Err(read_error(rep).await);
+        }
+
+        Ok(rep.bytes().await?)
+    }
+
+    /// audio chunks as synthesized, playback could start before the whole input is spoken;
+    /// no total timeout applied, stream ends with `Error::StreamIdleTimeout` once no chunk
+    /// arrives in `idle_timeout`, falls back to `Client::stream_idle_timeout`;
+    /// waiting for response headers is bounded by `idle_timeout` as well
+    pub async fn call_stream(
+        &self,
+        client: &Client,
+        idle_timeout: Option<Duration>,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let idle_timeout = idle_timeout.or(client.stream_idle_timeout());
+        let send = client.request_streaming(
+            Method::POST,
+            "audio/speech",
+            [(
+                http::header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            Payload::Json(Body::from(crate::json::to_vec(self)?)),
+        );
+        let rep = match idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, send).await.map_err(|_| {
+                error!(?idle, "speech stream headers idle timeout");
+                Error::StreamIdleTimeout(idle)
+            })??,
+            None => send.await?,
+        };
+
+        if !rep.status().is_success() {
+            return Err(read_error(rep).await);
+        }
+
+        let chunks = rep.bytes_stream().boxed();
+
+        Ok(futures::stream::unfold(
+            Some(chunks),
+            move |chunks| async move {
+                let mut chunks = chunks?;
+                let next = match idle_timeout {
+                    Some(idle) => match tokio::time::timeout(idle, chunks.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            error!(?idle, "speech stream idle timeout");
+                            return Some((Err(Error::StreamIdleTimeout(idle)), None));
+                        }
+                    },
+                    None => chunks.next().await,
+                };
+                next.map(|r| (r.map_err(Error::from), Some(chunks)))
+            },
+        ))
+    }
+
+    /// write audio chunks into `writer` as they arrive, return bytes written,
+    /// see `call_stream` for `idle_timeout`
+    pub async fn pipe_to<W: AsyncWrite + Unpin>(
+        &self,
+        client: &Client,
+        writer: &mut W,
+        idle_timeout: Option<Duration>,
+    ) -> Result<u64> {
+        let mut chunks = std::pin::pin!(self.call_stream(client, idle_timeout).await?);
+        let mut written = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            writer.write_all(chunk.as_ref()).await?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        trace!(%written, "speech piped");
+        Ok(written)
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize,
+)]
+#[allow(non_camel_case_types)]
+pub enum TranscriptionFormat {
+    #[default]
+    json,
+    text,
+    srt,
+    verbose_json,
+    vtt,
+}
+
+impl_wire_str!(
+    TranscriptionFormat,
+    json => "json",
+    text => "text",
+    srt => "srt",
+    verbose_json => "verbose_json",
+    vtt => "vtt",
+);
+
+/// timestamps in `verbose_json` reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum TimestampGranularity {
+    word,
+    segment,
+}
+
+impl_wire_str!(
+    TimestampGranularity,
+    word => "word",
+    segment => "segment",
+);
+
+/// speech to text, sent as multipart form
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptionRequest {
+    pub model: String,
+    pub file_name: String,
+    pub audio: Vec<u8>,
+    pub language: Option<String>,
+    pub prompt: Option<String>,
+    pub response_format: Option<TranscriptionFormat>,
+    pub temperature: Option<f64>,
+    /// requires `TranscriptionFormat::verbose_json`
+    pub timestamp_granularities: Vec<TimestampGranularity>,
+}
+
+impl TranscriptionRequest {
+    /// `file_name` tells server the audio format by its extension, e.g. `speech.mp3`
+    pub fn new(
+        model: impl Into<String>,
+        file_name: impl Into<String>,
+        audio: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            file_name: file_name.into(),
+            audio: audio.into(),
+            language: None,
+            prompt: None,
+            response_format: None,
+            temperature: None,
+            timestamp_granularities: vec![],
+        }
+    }
+
+    pub fn from_local_file(model: impl Into<String>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or(Error::NoFileName)?;
+        Ok(Self::new(model, file_name, std::fs::read(path)?))
+    }
+
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn with_response_format(mut self, format: TranscriptionFormat) -> Self {
+        self.response_format = Some(format);
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_timestamp_granularities(
+        mut self,
+        granularities: impl IntoIterator<Item = TimestampGranularity>,
+    ) -> Self {
+        self.timestamp_granularities = granularities.into_iter().collect();
+        self
+    }
+
+    /// text fields of multipart form, file part excluded,
+    /// arrays are sent as repeated `name[]` fields
+    pub(crate) fn form_fields(&self) -> FormBuilder {
+        FormBuilder::new()
+            .text("model", &self.model)
+            .text_opt("language", self.language.as_ref())
+            .text_opt("prompt", self.prompt.as_ref())
+            .text_opt("response_format", self.response_format)
+            .text_opt("temperature", self.temperature)
+            .texts("timestamp_granularities[]", &self.timestamp_granularities)
+    }
+
+    /// reply is decoded by requested `response_format`
+    pub async fn call(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<TranscriptionResponse> {
+        let rep = client
+            .request_multipart("audio/transcriptions", timeout, || async {
+                self.form_fields().file_from_bytes(
+                    "file",
+                    self.file_name.clone(),
+                    self.audio.clone(),
+                )
+            })
+            .await?;
+
+        let format = self.response_format.unwrap_or_default();
+        trace!(%format, "transcription reply");
+
+        match format {
+            TranscriptionFormat::json => Ok(TranscriptionResponse::Json {
+                text: read_json::<TranscriptionText>(rep).await?.text,
+            }),
+            TranscriptionFormat::verbose_json => {
+                Ok(TranscriptionResponse::VerboseJson(read_json(rep).await?))
+            }
+            TranscriptionFormat::text | TranscriptionFormat::srt | TranscriptionFormat::vtt => {
+                if !rep.status().is_success() {
+                    return Err(read_error(rep).await);
+                }
+                Ok(TranscriptionResponse::Text(rep.text().await?))
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TranscriptionText {
+    text: String,
+}
+
+/// reply of `TranscriptionRequest`, variant follows requested `response_format`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranscriptionResponse {
+    Json {
+        text: String,
+    },
+    VerboseJson(VerboseTranscription),
+    /// `text`, `srt` or `vtt` as is
+    Text(String),
+}
+
+impl TranscriptionResponse {
+    /// transcribed text, subtitle document for `srt` and `vtt`
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Json { text } => text,
+            Self::VerboseJson(verbose) => &verbose.text,
+            Self::Text(text) => text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VerboseTranscription {
+    pub language: String,
+    /// seconds of input audio
+    pub duration: f64,
+    pub text: String,
+    /// only present when `TimestampGranularity::word` requested
+    #[serde(default)]
+    pub words: Vec<TranscriptionWord>,
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TranscriptionWord {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TranscriptionSegment {
+    pub id: u64,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub avg_logprob: Option<f64>,
+    pub no_speech_prob: Option<f64>,
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_transcription_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let verbose = r#"{"task":"transcribe","language":"english","duration":1.5,"text":"hello world","words":[{"word":"hello","start":0.0,"end":0.6},{"word":"world","start":0.7,"end":1.4}],"segments":[{"id":0,"seek":0,"start":0.0,"end":1.5,"text":" hello world","tokens":[1,2],"temperature":0.0,"avg_logprob":-0.2,"compression_ratio":0.8,"no_speech_prob":0.01}]}"#;
+    let srt = "1\n00:00:00,000 --> 00:00:01,500\nhello world\n";
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "application/json",
+            r#"{"text":"hello world"}"#.to_string(),
+        ),
+        (200, "application/json", verbose.to_string()),
+        (200, "text/plain", srt.to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = TranscriptionRequest::new("whisper-1", "hello.mp3", b"ID3".to_vec());
+    assert_eq!(
+        req.form_fields().text_fields(),
+        [("model", "whisper-1".to_string())]
+    );
+    let rep = req.call(&client, None).await?;
+    assert_eq!(
+        rep,
+        TranscriptionResponse::Json {
+            text: "hello world".to_string()
+        }
+    );
+
+    let req = req
+        .with_language("en")
+        .with_response_format(TranscriptionFormat::verbose_json)
+        .with_timestamp_granularities([TimestampGranularity::word, TimestampGranularity::segment]);
+    assert_eq!(
+        req.form_fields().text_fields(),
+        [
+            ("model", "whisper-1".to_string()),
+            ("language", "en".to_string()),
+            ("response_format", "verbose_json".to_string()),
+            ("timestamp_granularities[]", "word".to_string()),
+            ("timestamp_granularities[]", "segment".to_string()),
+        ]
+    );
+    match req.call(&client, None).await? {
+        TranscriptionResponse::VerboseJson(verbose) => {
+            assert_eq!(verbose.language, "english");
+            assert_eq!(verbose.words.len(), 2);
+            assert_eq!(verbose.words[1].word, "world");
+            assert_eq!(verbose.segments[0].end, 1.5);
+        }
+        rep => panic!("expect verbose json, got {:?}", rep),
+    }
+
+    let rep = req
+        .with_response_format(TranscriptionFormat::srt)
+        .call(&client, None)
+        .await?;
+    assert_eq!(rep, TranscriptionResponse::Text(srt.to_string()));
+    assert_eq!(server.paths(), ["/v1/audio/transcriptions"; 3]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_speech_stream_ok() -> Result<()> {
+    use crate::client::Timeouts;
+    use crate::tests::MockServer;
+
+    let chunks = vec![
+        "ID3".to_string(),
+        "frame-1".to_string(),
+        "frame-2".to_string(),
+    ];
+    let server = MockServer::start_chunked("audio/mpeg", chunks.clone()).await;
+
+    let req = SpeechRequest::new("tts-1", "hello there", "alloy").with_response_format("mp3");
+    assert_eq!(
+        serde_json::to_value(&req)?,
+        serde_json::json!({"model": "tts-1", "input": "hello there", "voice": "alloy", "response_format": "mp3"})
+    );
+
+    let mut audio = vec![];
+    let written = req.pipe_to(&server.client()?, &mut audio, None).await?;
+    assert_eq!(audio, chunks.concat().as_bytes());
+    assert_eq!(written, audio.len() as u64);
+    assert_eq!(server.paths(), ["/v1/audio/speech"]);
+
+    // total timeout of client is not applied, a slow but steady stream completes
+    let server = MockServer::start_stalled(
+        "audio/mpeg",
+        "frame-1".to_string(),
+        Duration::from_millis(300),
+    )
+    .await;
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_timeouts(Timeouts::default().with_total(Duration::from_millis(100)))?
+        .build()?;
+    let parts = req
+        .call_stream(&client, Some(Duration::from_secs(5)))
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+    assert!(parts.iter().all(|p| p.is_ok()));
+
+    let server =
+        MockServer::start_stalled("audio/mpeg", "frame-1".to_string(), Duration::from_secs(5))
+            .await;
+    let idle = Duration::from_millis(100);
+    let parts = req
+        .call_stream(&server.client()?, Some(idle))
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(parts.len(), 2);
+    assert_eq!(
+        parts[0].as_ref().ok().map(|b| b.as_ref()),
+        Some(&b"frame-1"[..])
+    );
+    assert!(matches!(parts[1], Err(Error::StreamIdleTimeout(d)) if d == idle));
+
+    // a server which never replies fails on headers within idle timeout
+    let server = MockServer::start_silent().await;
+    let started = std::time::Instant::now();
+    let rs = req.call_stream(&server.client()?, Some(idle)).await;
+    assert!(matches!(rs, Err(Error::StreamIdleTimeout(d)) if d == idle));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(server.hits(), 1);
+
+    Ok(())
+}
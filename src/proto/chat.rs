@@ -1,30 +1,36 @@
-use crate::client::Client;
+use crate::cache::ResponseCache;
+use crate::client::{cancellable, read_error, read_json, Client, Payload, Timeouts};
 use crate::error::*;
 use crate::proto::tool::*;
+use crate::usage::UsageTracker;
 
 use base64::Engine;
-use futures::StreamExt;
 use http::{
-    header::{self, HeaderValue},
+    header::{self, HeaderName, HeaderValue},
     Method,
 };
-use reqwest::Body;
+use reqwest::{Body, Response};
 use serde::de::{Deserialize, IntoDeserializer};
 use serde_with::skip_serializing_none;
 use smart_default::SmartDefault;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<Message>,
     #[serde(default)]
     pub tools: Vec<ToolCall>,
+    pub tool_choice: Option<ToolChoice>,
     pub max_tokens: Option<u64>,
+    pub max_completion_tokens: Option<u64>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
     pub n: Option<u64>,
@@ -32,6 +38,22 @@ pub struct ChatCompletionRequest {
     pub stop: Option<Stop>,
     pub frequency_penalty: Option<f64>,
     pub response_format: Option<ResponseFormat>,
+    pub seed: Option<i64>,
+    /// return log probabilities of output tokens in `Choice::logprobs`
+    pub logprobs: Option<bool>,
+    /// most likely tokens returned at each position, 0 ~ 20, requires `logprobs`
+    pub top_logprobs: Option<u8>,
+    /// enable built-in web search, e.g. `gpt-4o-search-preview`
+    pub web_search_options: Option<WebSearchOptions>,
+    /// provider specific fields, e.g. openrouter `provider`, `transforms`, `route`, flattened into request body
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// used when `call` is given no timeout, never sent
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+    /// extra http headers of every call, e.g. `OpenAI-Beta`, never in body
+    #[serde(skip)]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 pub enum ChatCompletionResult {
@@ -44,42 +66,101 @@ impl ChatCompletionRequest {
         &self,
         client: &Client,
         timeout: Option<Duration>,
+    ) -> Result<ChatCompletionResponse> {
+        let timeout = timeout.or(self.timeout);
+
+        let body = crate::json::to_vec(&self)?;
+
+        let cache = client.cache().filter(|_| self.is_cacheable());
+        let key = ResponseCache::key(&body, &self.headers);
+
+        if let Some(rep) = cache.and_then(|c| c.get(&key)) {
+            trace!(key = %key.hash(), "chat completion cache hit");
+            return Ok(rep);
+        }
+
+        let rep = self.send_once(client, body, timeout).await;
+        client.usage_tracker().track(&rep, |r| r.usage.as_ref());
+        let rep = rep?;
+        log_usage(&rep.model, rep.usage.as_ref());
+
+        if let Some(cache) = cache {
+            cache.put(key, rep.clone());
+        }
+
+        Ok(rep)
+    }
+
+    async fn send_once(
+        &self,
+        client: &Client,
+        body: Vec<u8>,
+        timeout: Option<Duration>,
     ) -> Result<ChatCompletionResponse> {
         let uri = "chat/completions";
 
         let rep = client
-            .call_impl(
+            .request(
                 Method::POST,
                 uri,
-                vec![(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_str("application/json")?,
-                )],
-                Some(Body::from(serde_json::to_vec(&self)?)),
-                None,
+                self.headers_accepting("application/json"),
+                Payload::Json(Body::from(body)),
                 timeout,
             )
             .await?;
 
-        let status = rep.status();
+        read_json(rep).await
+    }
+
+    /// `content-type` and `accept` followed by `headers`, strict gateways reply html otherwise
+    fn headers_accepting(&self, accept: &'static str) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = vec![
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (header::ACCEPT, HeaderValue::from_static(accept)),
+        ];
+        headers.extend(self.headers.iter().cloned());
+        headers
+    }
+
+    /// send request and wait for first frame, both bounded by `idle_timeout` if any,
+    /// a server sending headers then nothing would hang otherwise without total timeout
+    async fn open_stream(
+        &self,
+        client: &Client,
+        body: &[u8],
+        timeout: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Result<(Response, SseDecoder, Vec<SseEvent>)> {
+        let uri = "chat/completions";
 
-        let rep = serde_json::from_slice::<serde_json::Value>(rep.bytes().await?.as_ref())?;
+        let send = client.request(
+            Method::POST,
+            uri,
+            self.headers_accepting("text/event-stream"),
+            Payload::Json(Body::from(body.to_vec())),
+            timeout,
+        );
+        let mut rep = idle_bound(idle_timeout, send).await??;
 
-        for l in serde_json::to_string_pretty(&rep)?.split("\n") {
-            if status.is_success() {
-                tracing::trace!("REP: {}", l);
-            } else {
-                tracing::error!("REP: {}", l);
-            }
+        if !rep.status().is_success() {
+            return Err(read_error(rep).await);
         }
 
-        if status.is_success() {
-            let rep: ChatCompletionResponse = serde_json::from_value(rep)?;
-            Ok(rep)
-        } else {
-            error!("chat completion failed");
-            Err(Error::ApiError(status.as_u16()))
+        let mut decoder = SseDecoder::default();
+
+        // hold the stream until first frame arrived, so it is still safe to retry
+        while let Some(chunk) = idle_bound(idle_timeout, rep.chunk()).await?? {
+            trace!("recv chunk {} bytes", chunk.len());
+            let frames = decoder.feed_events(chunk.as_ref());
+            if !frames.is_empty() {
+                return Ok((rep, decoder, frames));
+            }
         }
+
+        Err(Error::StreamInterrupted)
     }
 
     pub async fn call_stream(
@@ -87,85 +168,134 @@ impl ChatCompletionRequest {
         client: &Client,
         timeout: Option<Duration>,
     ) -> Result<Receiver<Result<ChatCompletionStreamData>>> {
-        let uri = "chat/completions";
+        self.call_stream_with(client, timeout, StreamConfig::default())
+            .await
+    }
 
-        let rep = client
-            .call_impl(
-                Method::POST,
-                uri,
-                vec![(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_str("application/json")?,
-                )],
-                Some(Body::from(serde_json::to_vec(&self)?)),
-                None,
-                timeout,
-            )
-            .await?;
+    /// same as `call_stream`, with extra stream options, e.g. raw frame capture
+    pub async fn call_stream_with(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+        config: StreamConfig,
+    ) -> Result<Receiver<Result<ChatCompletionStreamData>>> {
+        self.spawn_stream(client, timeout, config, StreamEvent::into_delta)
+            .await
+    }
+
+    /// same as `call_stream_with`, but also deliver auxiliary sse events named by `event:`,
+    /// e.g. search progress or citations, as `StreamEvent::Other`;
+    /// `event: error` is sent as `Error::StreamErrorEvent` by both methods
+    pub async fn call_stream_events(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+        config: StreamConfig,
+    ) -> Result<Receiver<Result<StreamEvent>>> {
+        self.spawn_stream(client, timeout, config, Some).await
+    }
+
+    /// open stream, then forward every event passing `wrap` to receiver in a spawned task
+    async fn spawn_stream<T: Send + 'static>(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+        config: StreamConfig,
+        wrap: fn(StreamEvent) -> Option<T>,
+    ) -> Result<Receiver<Result<T>>> {
+        let started = Instant::now();
+        let body = crate::json::to_vec(&self)?;
+        let policy = client.retry_policy();
+        let timeout = timeout.or(config.timeouts.total).or(self.timeout);
+
+        let idle_timeout = config.timeouts.read_idle.or(client.stream_idle_timeout());
+        let mut attempt = 0;
+
+        let (mut rep, mut decoder, frames) = loop {
+            attempt += 1;
+            match self.open_stream(client, &body, timeout, idle_timeout).await {
+                Ok(r) => {
+                    debug!(%attempt, "stream opened");
+                    break r;
+                }
+                Err(e) if attempt <= policy.max_retries && e.is_retryable() => {
+                    let delay = policy.delay(attempt);
+                    warn!(%attempt, ?delay, "stream failed before first frame, retry: {:?}", e);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    error!(%attempt, "stream failed: {:?}", e);
+                    client.usage_tracker().record_error();
+                    return Err(e);
+                }
+            }
+        };
 
         let (tx, rx) = tokio::sync::mpsc::channel(1);
 
+        let usage_tracker = client.usage_tracker().clone();
+        let model = self.model.clone();
+
         tokio::spawn(async move {
-            let mut stack = vec![];
-            let mut stream = rep.bytes_stream();
+            // fire `on_complete` on drop, whatever way this task ends
+            let mut tracker =
+                StreamTracker::new(model, started, config.on_complete.clone(), usage_tracker);
+
+            for event in frames {
+                send_stream_event(&tx, event, &config, &mut tracker, wrap).await?;
+            }
+
+            loop {
+                let next = async {
+                    match idle_timeout {
+                        Some(idle) => tokio::time::timeout(idle, rep.chunk())
+                            .await
+                            .map_err(|_| Error::StreamIdleTimeout(idle))?
+                            .map_err(Error::from),
+                        None => rep.chunk().await.map_err(Error::from),
+                    }
+                };
+
+                let next = async {
+                    match config.cancel.as_ref() {
+                        Some(token) => cancellable(token, next).await,
+                        None => next.await,
+                    }
+                };
 
-            let s_tag = "data: ".as_bytes();
-            let s_tag_len = s_tag.len();
-            let e_tag = "\n\n".as_bytes();
-            let e_tag_len = e_tag.len();
+                let chunk = tokio::select! {
+                    biased;
+                    _ = tx.closed() => {
+                        debug!("chat receiver dropped, stop stream");
+                        break;
+                    }
+                    chunk = next => chunk,
+                };
 
-            while let Some(r) = stream.next().await {
-                let chunk = match r {
-                    Ok(r) => r,
+                let chunk = match chunk {
+                    Ok(Some(r)) => r,
+                    Ok(None) => break,
                     Err(e) => {
                         error!("stream return with error: {:?}", e);
+                        tracker.fail(&e);
+                        tx.send(Err(e)).await.map_err(|_| {
+                            error!("failed to send error message to chat receiver");
+                            Error::SendMessage
+                        })?;
                         break;
                     }
                 };
 
                 trace!("recv chunk {} bytes", chunk.len());
 
-                for b in chunk.as_ref() {
-                    stack.push(*b);
-                    if stack.len() >= e_tag_len + s_tag_len {
-                        let slice = &stack[stack.len() - e_tag_len..];
-
-                        if slice == e_tag {
-                            let mut data = vec![];
-                            std::mem::swap(&mut data, &mut stack);
-
-                            let data =
-                                String::from_utf8_lossy(&data[s_tag_len..data.len() - e_tag_len]);
-
-                            if data.find("[DONE]").is_some() {
-                                trace!("met [DONE], data={}", data);
-                                continue;
-                            }
-
-                            match serde_json::from_str::<ChatCompletionStreamData>(&data) {
-                                Err(e) => {
-                                    error!("failed to parse data: error={:?}, data={}", e, data);
-                                    tx.send(Err(e.into())).await.map_err(|_| {
-                                        error!("failed to send error message to chat receiver");
-                                        Error::SendMessage
-                                    })?;
-                                }
-                                Ok(data) => {
-                                    trace!("found data event from stream");
-                                    for l in serde_json::to_string_pretty(&data)?.lines() {
-                                        trace!("DATA: {}", l);
-                                    }
-                                    tx.send(Ok(data)).await.map_err(|_| {
-                                        error!("failed to send data message to chat receiver");
-                                        Error::SendMessage
-                                    })?;
-                                }
-                            }
-                        }
-                    }
+                for event in decoder.feed_events(chunk.as_ref()) {
+                    send_stream_event(&tx, event, &config, &mut tracker, wrap).await?;
                 }
             }
-            trace!("stream thread quit, with stack.len()={}", stack.len());
+            trace!(
+                "stream thread quit, with stack.len()={}",
+                decoder.remaining()
+            );
             Result::Ok(())
         });
 
@@ -186,19 +316,505 @@ impl ChatCompletionRequest {
             )),
         }
     }
+
+    /// same as `call`, but abort with `Error::Cancelled` once `token` fired,
+    /// a stream keeps watching `token` after opened
+    pub async fn call_cancellable(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+        token: CancellationToken,
+    ) -> Result<ChatCompletionResult> {
+        match self.stream {
+            Some(true) => {
+                let config = StreamConfig::default().with_cancellation(token.clone());
+                let rx =
+                    cancellable(&token, self.call_stream_with(client, timeout, config)).await?;
+                Ok(ChatCompletionResult::Delta(rx))
+            }
+            _ => Ok(ChatCompletionResult::Response(
+                cancellable(&token, self.call_once(client, timeout)).await?,
+            )),
+        }
+    }
+}
+
+/// raw sse frame callback
+pub type RawFrameCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// stream summary callback, shared by clones of `StreamConfig` and taken on first use
+pub type CompleteCallback = Arc<Mutex<Option<Box<dyn FnOnce(StreamSummary) + Send>>>>;
+
+/// `fut` bounded by `idle` if any, e.g. while waiting for the first frame of a stream
+async fn idle_bound<T>(
+    idle: Option<Duration>,
+    fut: impl std::future::Future<Output = T>,
+) -> Result<T> {
+    match idle {
+        Some(idle) => tokio::time::timeout(idle, fut).await.map_err(|_| {
+            error!(?idle, "stream idle timeout before first frame");
+            Error::StreamIdleTimeout(idle)
+        }),
+        None => Ok(fut.await),
+    }
+}
+
+/// what a stream has delivered when it terminated
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StreamSummary {
+    /// last usage reported by stream, if any
+    pub usage: Option<ChatComplitionUsage>,
+    /// choice index -> finish reason
+    pub finish_reasons: BTreeMap<usize, String>,
+    /// number of parsed data chunks
+    pub chunks: usize,
+    /// elapsed since the call started, including retries before first frame
+    pub duration: Duration,
+    /// first error sent to receiver, the call is then counted as failed by `UsageTracker`
+    pub error: Option<String>,
+}
+
+/// collect `StreamSummary` inside stream task, report it on drop
+struct StreamTracker {
+    /// model of request, replaced by model reported in stream
+    model: String,
+    started: Instant,
+    summary: StreamSummary,
+    on_complete: Option<CompleteCallback>,
+    usage_tracker: Arc<UsageTracker>,
+}
+
+impl StreamTracker {
+    fn new(
+        model: String,
+        started: Instant,
+        on_complete: Option<CompleteCallback>,
+        usage_tracker: Arc<UsageTracker>,
+    ) -> Self {
+        Self {
+            model,
+            started,
+            summary: StreamSummary::default(),
+            on_complete,
+            usage_tracker,
+        }
+    }
+
+    fn observe(&mut self, data: &ChatCompletionStreamData) {
+        self.summary.chunks += 1;
+
+        if let Some(model) = data.model.as_ref().filter(|m| !m.is_empty()) {
+            self.model.clone_from(model);
+        }
+
+        if let Some(usage) = data.usage.as_ref() {
+            self.summary.usage = Some(usage.clone());
+        }
+
+        for choice in data.choices.iter() {
+            if let Some(usage) = choice.usage.as_ref() {
+                self.summary.usage = Some(usage.clone());
+            }
+            if let Some(reason) = choice.finish_reason.as_ref() {
+                self.summary
+                    .finish_reasons
+                    .insert(choice.index, reason.clone());
+            }
+        }
+    }
+
+    fn fail(&mut self, e: &Error) {
+        if self.summary.error.is_none() {
+            self.summary.error = Some(e.to_string());
+        }
+    }
+}
+
+/// merge tool call fragments of stream into `lhs`, fragments are matched by `index`,
+/// then by `id`, fragment without both continues the last tool call
+fn merge_tool_calls(lhs: &mut Vec<ToolCall>, rhs: Vec<ToolCall>) {
+    for fragment in rhs {
+        let pos = match (fragment.index, fragment.id.as_deref()) {
+            (Some(index), _) => lhs.iter().position(|t| t.index == Some(index)),
+            (None, Some(id)) => lhs.iter().position(|t| t.id.as_deref() == Some(id)),
+            (None, None) => lhs.len().checked_sub(1),
+        };
+
+        match pos {
+            Some(pos) => lhs[pos].merge(fragment),
+            None => lhs.push(fragment),
+        }
+    }
+}
+
+/// single structured event per finished call, for cost tracking
+fn log_usage(model: &str, usage: Option<&ChatComplitionUsage>) {
+    if let Some(usage) = usage {
+        info!(
+            prompt_tokens = usage.prompt_tokens,
+            completion_tokens = usage.completion_tokens,
+            total_tokens = usage.total_tokens,
+            model,
+            "usage"
+        );
+    }
+}
+
+impl Drop for StreamTracker {
+    fn drop(&mut self) {
+        log_usage(&self.model, self.summary.usage.as_ref());
+        match self.summary.error {
+            Some(_) => self.usage_tracker.record_error(),
+            None => self.usage_tracker.record(self.summary.usage.as_ref()),
+        }
+
+        let Some(on_complete) = self.on_complete.take() else {
+            return;
+        };
+
+        let f = match on_complete.lock() {
+            Ok(mut f) => f.take(),
+            Err(e) => e.into_inner().take(),
+        };
+
+        if let Some(f) = f {
+            let mut summary = std::mem::take(&mut self.summary);
+            summary.duration = self.started.elapsed();
+            trace!(chunks = %summary.chunks, ?summary.duration, "stream complete");
+            f(summary);
+        }
+    }
+}
+
+/// options of streaming chat completion
+#[derive(Clone, Default)]
+pub struct StreamConfig {
+    capture_raw: bool,
+    on_raw: Option<RawFrameCallback>,
+    on_complete: Option<CompleteCallback>,
+    timeouts: Timeouts,
+    cancel: Option<CancellationToken>,
+}
+
+impl StreamConfig {
+    /// attach raw frame to parse error, see `Error::StreamParse`
+    pub fn capture_raw(mut self, capture_raw: bool) -> Self {
+        self.capture_raw = capture_raw;
+        self
+    }
+
+    /// per call timeouts, `total` and `read_idle` override client defaults, `connect` is ignored
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// stop the stream with `Error::Cancelled` once `token` fired
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// receive every raw sse frame before parsing
+    pub fn on_raw(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_raw = Some(Arc::new(f));
+        self
+    }
+
+    /// receive `StreamSummary` exactly once when the opened stream terminates for any reason,
+    /// including the receiver being dropped; not called if opening the stream failed
+    pub fn on_complete(mut self, f: impl FnOnce(StreamSummary) + Send + 'static) -> Self {
+        self.on_complete = Some(Arc::new(Mutex::new(Some(Box::new(f)))));
+        self
+    }
+}
+
+impl std::fmt::Debug for StreamConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamConfig")
+            .field("capture_raw", &self.capture_raw)
+            .field("on_raw", &self.on_raw.is_some())
+            .field("on_complete", &self.on_complete.is_some())
+            .field("timeouts", &self.timeouts)
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
+
+async fn send_stream_event<T>(
+    tx: &Sender<Result<T>>,
+    event: SseEvent,
+    config: &StreamConfig,
+    tracker: &mut StreamTracker,
+    wrap: fn(StreamEvent) -> Option<T>,
+) -> Result<()> {
+    let SseEvent { event, data } = event;
+
+    if let Some(on_raw) = config.on_raw.as_ref() {
+        on_raw(&data);
+    }
+
+    let event = match event.as_deref() {
+        None | Some("message") => {
+            if data.contains("[DONE]") {
+                trace!("met [DONE], data={}", data);
+                return Ok(());
+            }
+
+            match crate::json::from_str::<ChatCompletionStreamData>(&data) {
+                Err(e) => {
+                    error!("failed to parse data: error={:?}, data={}", e, data);
+                    let e = match config.capture_raw {
+                        true => Error::StreamParse {
+                            raw: data.to_string(),
+                            source: e,
+                        },
+                        false => e.into(),
+                    };
+                    tracker.fail(&e);
+                    tx.send(Err(e)).await.map_err(|_| {
+                        error!("failed to send error message to chat receiver");
+                        Error::SendMessage
+                    })?;
+                    return Ok(());
+                }
+                Ok(data) => {
+                    trace!("found data event from stream");
+                    tracker.observe(&data);
+                    for l in serde_json::to_string_pretty(&data)?.lines() {
+                        trace!("DATA: {}", l);
+                    }
+                    StreamEvent::Delta(data)
+                }
+            }
+        }
+        Some("error") => {
+            error!(%data, "found error event from stream");
+            let e = Error::StreamErrorEvent(data);
+            tracker.fail(&e);
+            tx.send(Err(e)).await.map_err(|_| {
+                error!("failed to send error message to chat receiver");
+                Error::SendMessage
+            })?;
+            return Ok(());
+        }
+        Some(event) => {
+            trace!(%event, "found auxiliary event from stream");
+            let data = serde_json::from_str(&data).unwrap_or(serde_json::Value::String(data));
+            StreamEvent::Other {
+                event: event.to_string(),
+                data,
+            }
+        }
+    };
+
+    if let Some(item) = wrap(event) {
+        tx.send(Ok(item)).await.map_err(|_| {
+            error!("failed to send data message to chat receiver");
+            Error::SendMessage
+        })?;
+    }
+
+    Ok(())
+}
+
+/// item of `call_stream_events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// chat completion chunk, `event:` absent or `message`
+    Delta(ChatCompletionStreamData),
+    /// provider specific event, `data` is kept as string if not json
+    Other {
+        event: String,
+        data: serde_json::Value,
+    },
+}
+
+impl StreamEvent {
+    pub fn into_delta(self) -> Option<ChatCompletionStreamData> {
+        match self {
+            StreamEvent::Delta(data) => Some(data),
+            StreamEvent::Other { .. } => None,
+        }
+    }
+}
+
+/// a single sse frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SseEvent {
+    pub(crate) event: Option<String>,
+    pub(crate) data: String,
+}
+
+/// split `data: ...\n\n` frames out of sse byte chunks
+#[derive(Debug, Default)]
+pub(crate) struct SseDecoder {
+    stack: Vec<u8>,
+}
+
+impl SseDecoder {
+    const S_TAG: &[u8] = b"data: ";
+    const E_TAG: &[u8] = b"\n\n";
+
+    /// split complete frames, without trailing `\n\n`
+    fn split(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+
+        for b in chunk {
+            self.stack.push(*b);
+            if self.stack.len() >= Self::S_TAG.len() + Self::E_TAG.len()
+                && self.stack.ends_with(Self::E_TAG)
+            {
+                let mut frame = std::mem::take(&mut self.stack);
+                frame.truncate(frame.len() - Self::E_TAG.len());
+                frames.push(frame);
+            }
+        }
+
+        frames
+    }
+
+    /// parse `event:` and multi-line `data:` fields of complete frames, comments are skipped
+    pub(crate) fn feed_events(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.split(chunk)
+            .into_iter()
+            .filter_map(|frame| {
+                let frame = String::from_utf8_lossy(&frame).to_string();
+                let mut event = None;
+                let mut data = vec![];
+                for line in frame.lines() {
+                    let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                    let value = value.strip_prefix(' ').unwrap_or(value);
+                    match field {
+                        "event" => event = Some(value.to_string()),
+                        "data" => data.push(value),
+                        _ => {}
+                    }
+                }
+                match (event, data.is_empty()) {
+                    (None, true) => None,
+                    (event, _) => Some(SseEvent {
+                        event,
+                        data: data.join("\n"),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+/// `web_search_options` of request, all fields optional
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct WebSearchOptions {
+    pub search_context_size: Option<SearchContextSize>,
+    pub user_location: Option<UserLocation>,
+}
+
+impl WebSearchOptions {
+    pub fn with_search_context_size(mut self, size: SearchContextSize) -> Self {
+        self.search_context_size = Some(size);
+        self
+    }
+
+    pub fn with_user_location(mut self, location: ApproximateLocation) -> Self {
+        self.user_location = Some(UserLocation {
+            typ: "approximate".into(),
+            approximate: location,
+        });
+        self
+    }
+}
+
+/// how much context is retrieved by web search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum SearchContextSize {
+    low,
+    medium,
+    high,
+}
+
+impl_wire_str!(
+    SearchContextSize,
+    low => "low",
+    medium => "medium",
+    high => "high",
+);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UserLocation {
+    /// always `approximate`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub approximate: ApproximateLocation,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ApproximateLocation {
+    /// two-letter iso country code, e.g. `GB`
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    /// iana timezone, e.g. `Europe/London`
+    pub timezone: Option<String>,
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ResponseFormat {
     #[serde(rename = "type")]
     typ: ResponseType,
+    /// set with `ResponseType::json_schema`
+    json_schema: Option<JsonSchemaFormat>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum ResponseType {
     json_object,
+    json_schema,
+    text,
+}
+
+/// `json_schema` of `response_format`, reply is constrained to `schema`
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub description: Option<String>,
+    pub schema: serde_json::Value,
+    pub strict: Option<bool>,
+}
+
+/// rewrite `schema` for openai strict mode: every object closed with all properties required,
+/// `$schema` and `format` removed, optional field stays nullable
+fn strict_schema(schema: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match schema {
+        Value::Object(map) => {
+            map.remove("$schema");
+            if map.get("format").is_some_and(|f| f.is_string()) {
+                map.remove("format");
+            }
+
+            if let Some(Value::Object(properties)) = map.get("properties") {
+                let required = properties.keys().cloned().map(Value::String).collect();
+                map.insert("required".into(), Value::Array(required));
+                map.insert("additionalProperties".into(), Value::Bool(false));
+            }
+
+            map.values_mut().for_each(strict_schema);
+        }
+        Value::Array(values) => values.iter_mut().for_each(strict_schema),
+        _ => {}
+    }
 }
 
 #[derive(Debug, Clone, SmartDefault)]
@@ -206,7 +822,9 @@ pub struct ChatCompletionRequestBuilder {
     model: Option<String>,
     messages: Vec<Message>,
     tools: Vec<ToolCall>,
+    tool_choice: Option<ToolChoice>,
     max_tokens: Option<u64>,
+    max_completion_tokens: Option<u64>,
     temperature: Option<f64>,
     top_p: Option<f64>,
     n: Option<u64>,
@@ -214,19 +832,70 @@ pub struct ChatCompletionRequestBuilder {
     stop: Option<Stop>,
     frequency_penalty: Option<f64>,
     response_format: Option<ResponseFormat>,
+    seed: Option<i64>,
+    logprobs: Option<bool>,
+    top_logprobs: Option<u8>,
+    web_search_options: Option<WebSearchOptions>,
+    skip_validation: bool,
+    content_style: ContentStyle,
+    trim_messages: bool,
+    keep_response_fields: bool,
+    extra: serde_json::Map<String, serde_json::Value>,
+    timeout: Option<Duration>,
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl ChatCompletionRequestBuilder {
     pub fn with_reponse_format(mut self, format: ResponseType) -> Self {
-        self.response_format = Some(ResponseFormat { typ: format });
+        self.response_format = Some(ResponseFormat {
+            typ: format,
+            json_schema: None,
+        });
+        self
+    }
+
+    /// constrain reply to json `schema`, `strict` rewrites schema for openai strict mode
+    pub fn with_json_schema(
+        mut self,
+        name: impl Into<String>,
+        mut schema: serde_json::Value,
+        strict: bool,
+    ) -> Self {
+        if strict {
+            strict_schema(&mut schema);
+        }
+        self.response_format = Some(ResponseFormat {
+            typ: ResponseType::json_schema,
+            json_schema: Some(JsonSchemaFormat {
+                name: name.into(),
+                description: None,
+                schema,
+                strict: Some(strict),
+            }),
+        });
         self
     }
 
+    /// strict json schema generated from `T`, parse reply with `ChatCompletionResponse::parse_structured`
+    #[cfg(feature = "schemars")]
+    pub fn with_structured_output<T: schemars::JsonSchema>(self, name: impl Into<String>) -> Self {
+        let schema = schemars::schema_for!(T).to_value();
+        self.with_json_schema(name, schema, true)
+    }
+
     pub fn with_model(mut self, model: impl Into<String>) -> Self {
         self.model = Some(model.into());
         self
     }
 
+    /// use `Client::default_model` unless a model is already set
+    pub fn with_default_model_from(mut self, client: &Client) -> Self {
+        if self.model.is_none() {
+            self.model = client.default_model().map(|m| m.to_string());
+        }
+        self
+    }
+
     pub fn with_messages(mut self, messages: impl IntoIterator<Item = Message>) -> Self {
         self.messages.extend(messages);
         self
@@ -237,6 +906,21 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
+    /// append message of first choice, tool_calls included, e.g. in an agent loop
+    pub fn add_assistant_response(mut self, rep: &ChatCompletionResponse) -> Self {
+        let Some(choice) = rep.choices.first() else {
+            warn!(id = %rep.id, "response without choice, nothing appended");
+            return self;
+        };
+
+        let mut message = choice.message.clone();
+        if message.role.is_none() {
+            message.role = Some(Role::assistant);
+        }
+        self.messages.push(message);
+        self
+    }
+
     pub fn with_tool(mut self, tool: impl Into<ToolCall>) -> Self {
         self.tools.push(tool.into());
         self
@@ -254,8 +938,33 @@ impl ChatCompletionRequestBuilder {
         self.with_tool(tool)
     }
 
-    pub fn with_max_tokens(mut self, max_tokens: u64) -> Self {
-        self.max_tokens = Some(max_tokens);
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    /// declare `tool` unless a tool of same name is declared, and force model to call it
+    pub fn force_tool(mut self, tool: impl Into<ToolCall>) -> Self {
+        let tool = tool.into();
+        let name = tool.function.name.clone().unwrap_or_default();
+        if !self
+            .tools
+            .iter()
+            .any(|t| t.function.name == tool.function.name)
+        {
+            self.tools.push(tool);
+        }
+        self.tool_choice = Some(ToolChoice::Function(name));
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u64) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_max_completion_tokens(mut self, max_completion_tokens: u64) -> Self {
+        self.max_completion_tokens = Some(max_completion_tokens);
         self
     }
 
@@ -294,12 +1003,105 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_logprobs(mut self, logprobs: bool) -> Self {
+        self.logprobs = Some(logprobs);
+        self
+    }
+
+    /// also turns on `logprobs`, which openai requires for `top_logprobs`
+    pub fn with_top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.logprobs = Some(true);
+        self.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    pub fn with_web_search(mut self, options: WebSearchOptions) -> Self {
+        self.web_search_options = Some(options);
+        self
+    }
+
+    /// add provider specific field, key must not collide with typed fields
+    pub fn with_extra(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+
+    /// openrouter provider routing preferences, e.g. `{"order": ["OpenAI"], "allow_fallbacks": false}`
+    pub fn with_openrouter_provider(self, provider: serde_json::Value) -> Self {
+        self.with_extra("provider", provider)
+    }
+
+    /// openrouter prompt transforms, e.g. `["middle-out"]`
+    pub fn with_openrouter_transforms(
+        self,
+        transforms: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        let transforms = transforms
+            .into_iter()
+            .map(|t| t.into())
+            .collect::<Vec<String>>();
+        self.with_extra("transforms", transforms)
+    }
+
+    /// openrouter model routing, e.g. `fallback`
+    pub fn with_openrouter_route(self, route: impl Into<String>) -> Self {
+        self.with_extra("route", route.into())
+    }
+
+    /// normalize all messages with `style` in `build`
+    pub fn with_content_style(mut self, style: ContentStyle) -> Self {
+        self.content_style = style;
+        self
+    }
+
+    /// trim text content of every message in `build`, see `Content::trim`, off by default
+    pub fn with_trim_messages(mut self, trim_messages: bool) -> Self {
+        self.trim_messages = trim_messages;
+        self
+    }
+
+    /// keep `RESPONSE_ONLY_FIELDS` of messages in `build`, e.g. an assistant reply pushed back
+    /// into `messages`, off by default as deepseek rejects `reasoning_content` in a request
+    pub fn with_keep_response_fields(mut self, keep_response_fields: bool) -> Self {
+        self.keep_response_fields = keep_response_fields;
+        self
+    }
+
+    /// default timeout of `call`, explicit timeout of `call` still wins
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// add http header to every call of the request, e.g. `OpenAI-Beta: assistants=v2`
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// skip `ChatCompletionRequest::validate` in `build`, for providers with non-standard limits
+    pub fn with_skip_validation(mut self, skip_validation: bool) -> Self {
+        self.skip_validation = skip_validation;
+        self
+    }
+
     pub fn build(self) -> Result<ChatCompletionRequest> {
         let Self {
             model,
-            messages,
+            mut messages,
             tools,
+            tool_choice,
             max_tokens,
+            max_completion_tokens,
             temperature,
             top_p,
             n,
@@ -307,6 +1109,17 @@ impl ChatCompletionRequestBuilder {
             stop,
             frequency_penalty,
             response_format,
+            seed,
+            logprobs,
+            top_logprobs,
+            web_search_options,
+            skip_validation,
+            content_style,
+            trim_messages,
+            keep_response_fields,
+            extra,
+            timeout,
+            headers,
         } = self;
 
         let model = model.ok_or(Error::ChatCompletionRequestBuild)?;
@@ -315,11 +1128,40 @@ impl ChatCompletionRequestBuilder {
             return Err(Error::ChatCompletionRequestBuild);
         }
 
+        if messages.iter().any(|m| m.role.is_none()) {
+            error!("message without role is not allowed in request");
+            return Err(Error::MessageBuild);
+        }
+
+        if let Some(key) = extra
+            .keys()
+            .find(|k| ChatCompletionRequest::FIELDS.contains(&k.as_str()))
+        {
+            error!("extra key `{}` collides with typed field", key);
+            return Err(Error::ChatCompletionRequestBuild);
+        }
+
+        warn_max_tokens(&model, max_tokens, max_completion_tokens);
+
+        for message in messages.iter_mut() {
+            if trim_messages {
+                if let Some(content) = message.content.as_mut() {
+                    content.trim();
+                }
+            }
+            if !keep_response_fields {
+                message.remove_response_fields();
+            }
+            message.normalize_for(content_style);
+        }
+
         let r = ChatCompletionRequest {
             model,
             messages,
             tools,
+            tool_choice,
             max_tokens,
+            max_completion_tokens,
             temperature,
             top_p,
             n,
@@ -327,24 +1169,207 @@ impl ChatCompletionRequestBuilder {
             stop,
             frequency_penalty,
             response_format,
+            seed,
+            logprobs,
+            top_logprobs,
+            web_search_options,
+            extra,
+            timeout,
+            headers,
         };
 
-        for l in serde_json::to_string_pretty(&r)?.lines() {
-            trace!("REQ: {}", l);
+        if !skip_validation {
+            r.validate()?;
+        }
+
+        if tracing::enabled!(Level::TRACE) {
+            let mut body = serde_json::to_value(&r)?;
+            crate::json::redact(&mut body);
+            for l in serde_json::to_string_pretty(&body)?.lines() {
+                trace!("REQ: {}", l);
+            }
         }
 
         Ok(r)
     }
 }
 
+/// soft guardrail for `max_tokens`, which is deprecated by openai in favor of `max_completion_tokens`
+fn warn_max_tokens(model: &str, max_tokens: Option<u64>, max_completion_tokens: Option<u64>) {
+    let Some(max_tokens) = max_tokens else {
+        return;
+    };
+
+    if let Some(max_completion_tokens) = max_completion_tokens {
+        warn!(
+            %max_tokens,
+            %max_completion_tokens,
+            "both max_tokens and max_completion_tokens are set, provider may pick either"
+        );
+        return;
+    }
+
+    let is_o_series = model
+        .strip_prefix('o')
+        .and_then(|m| m.chars().next())
+        .is_some_and(|c| c.is_ascii_digit());
+
+    if is_o_series || model.starts_with("gpt-4o") {
+        warn!(%model, %max_tokens, "max_tokens is deprecated for this model, use max_completion_tokens");
+    }
+}
+
 impl ChatCompletionRequest {
     pub fn builder() -> ChatCompletionRequestBuilder {
         ChatCompletionRequestBuilder::default()
     }
+
+    /// builder with model pre-filled from `Client::default_model`
+    pub fn builder_for(client: &Client) -> ChatCompletionRequestBuilder {
+        Self::builder().with_default_model_from(client)
+    }
+
+    /// hash of canonical json of this request, equal requests hash the same across processes,
+    /// all maps of request serialize in key order, e.g. tool properties and `extra`
+    pub fn stable_hash(&self) -> u64 {
+        let body = serde_json::to_vec(self).expect("chat completion request is serializable");
+        crate::cache::fnv1a(&body)
+    }
+
+    /// only deterministic non-streaming request could be cached
+    pub fn is_cacheable(&self) -> bool {
+        self.stream != Some(true) && (self.temperature == Some(0.0) || self.seed.is_some())
+    }
+
+    /// one-shot request with a single user prompt
+    pub fn simple(model: impl Into<String>, prompt: impl Into<Content>) -> Result<Self> {
+        Self::builder()
+            .with_model(model)
+            .add_message(
+                Message::builder()
+                    .with_role(Role::user)
+                    .with_content(prompt)
+                    .build(),
+            )
+            .build()
+    }
+
+    /// one-shot request with a system prompt and a user prompt
+    pub fn with_system(
+        model: impl Into<String>,
+        system: impl Into<Content>,
+        prompt: impl Into<Content>,
+    ) -> Result<Self> {
+        Self::builder()
+            .with_model(model)
+            .with_messages([
+                Message::builder()
+                    .with_role(Role::system)
+                    .with_content(system)
+                    .build(),
+                Message::builder()
+                    .with_role(Role::user)
+                    .with_content(prompt)
+                    .build(),
+            ])
+            .build()
+    }
+
+    /// typed fields, which are not allowed as `extra` keys
+    pub const FIELDS: &'static [&'static str] = &[
+        "model",
+        "messages",
+        "tools",
+        "tool_choice",
+        "max_tokens",
+        "max_completion_tokens",
+        "temperature",
+        "top_p",
+        "n",
+        "stream",
+        "stop",
+        "frequency_penalty",
+        "response_format",
+        "seed",
+        "logprobs",
+        "top_logprobs",
+        "web_search_options",
+    ];
+
+    /// max stop sequences accepted by openai
+    pub const MAX_STOPS: usize = 4;
+
+    /// structural checks, catch common `400` locally before sending
+    pub fn validate(&self) -> Result<()> {
+        for (index, message) in self.messages.iter().enumerate() {
+            let empty = message.content.as_ref().is_none_or(|c| match c {
+                Content::Text(t) => t.is_empty(),
+                Content::Containers(cs) => cs.is_empty(),
+            });
+            if empty && message.tool_calls.is_empty() {
+                error!(%index, "message without content or tool_calls");
+                return Err(Error::EmptyMessage(index));
+            }
+
+            if message.role == Some(Role::tool) && message.tool_call_id.is_none() {
+                error!(%index, "tool message without tool_call_id");
+                return Err(Error::MissingToolCallId(index));
+            }
+        }
+
+        let stops = match self.stop.as_ref() {
+            Some(Stop::Text(_)) => 1,
+            Some(Stop::Texts(ts)) => ts.len(),
+            None => 0,
+        };
+        if stops > Self::MAX_STOPS {
+            error!(%stops, "too many stop sequences");
+            return Err(Error::TooManyStops(stops, Self::MAX_STOPS));
+        }
+
+        if let Some(ToolChoice::Function(name)) = self.tool_choice.as_ref() {
+            if !self
+                .tools
+                .iter()
+                .any(|t| t.function.name.as_ref() == Some(name))
+            {
+                error!(%name, "tool_choice function is not declared");
+                return Err(Error::UnknownToolChoice(name.clone()));
+            }
+        }
+
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                error!(%temperature, "temperature out of range");
+                return Err(Error::InvalidTemperature(temperature));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// rough prompt size, sum of text chars of all messages, not a real tokenizer
+    pub fn estimated_prompt_chars(&self) -> usize {
+        self.messages
+            .iter()
+            .filter_map(|m| m.content.as_ref())
+            .map(|c| c.to_text().chars().count())
+            .sum()
+    }
+
+    /// cheap preflight to catch obviously oversized prompt before sending
+    pub fn check_within(&self, max_chars: usize) -> Result<()> {
+        let chars = self.estimated_prompt_chars();
+        if chars > max_chars {
+            error!(%chars, %max_chars, "prompt too large");
+            return Err(Error::PromptTooLarge(chars, max_chars));
+        }
+        Ok(())
+    }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct ChatCompletionResponse {
     pub id: String,
     #[default("chat.completion".to_string())]
@@ -354,9 +1379,104 @@ pub struct ChatCompletionResponse {
     #[serde(default)]
     pub choices: Vec<Choice>,
     pub usage: Option<ChatComplitionUsage>,
+    /// azure filter results of prompt, one per prompt index
+    pub prompt_filter_results: Option<Vec<PromptFilterResult>>,
+    /// provider specific fields, e.g. kimi `search_results`, kept through serialize round trip
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// set by `merge_delta` once a chunk disagreed with id, created or model, warned only once
+    #[serde(skip)]
+    pub identity_mismatch: bool,
 }
 
 impl ChatCompletionResponse {
+    /// any choice filtered or any prompt tripped a filter category, see `Choice::is_content_filtered`
+    pub fn is_content_filtered(&self) -> bool {
+        self.choices.iter().any(Choice::is_content_filtered)
+            || self
+                .prompt_filter_results
+                .iter()
+                .flatten()
+                .any(|p| p.content_filter_results.values().any(|c| c.filtered))
+    }
+
+    /// text content of first choice
+    pub fn text(&self) -> Option<String> {
+        self.choices
+            .first()
+            .and_then(|c| c.message.content.as_ref())
+            .map(|c| c.to_text())
+    }
+
+    /// deserialize text content of first choice as `T`, see `with_structured_output`,
+    /// refusal of model is `Error::Refusal`
+    pub fn parse_structured<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        let message = &self.choices.first().ok_or(Error::NoContent)?.message;
+
+        if let Some(refusal) = message.refusal.as_ref() {
+            warn!(%refusal, "model refused structured output");
+            return Err(Error::Refusal(refusal.clone()));
+        }
+
+        let text = message
+            .content
+            .as_ref()
+            .map(|c| c.to_text())
+            .ok_or(Error::NoContent)?;
+
+        Ok(crate::json::from_str(&text)?)
+    }
+
+    /// text content of every choice, in order of choice index
+    pub fn texts(&self) -> Vec<Option<String>> {
+        self.choices
+            .iter()
+            .map(|c| c.message.content.as_ref().map(|c| c.to_text()))
+            .collect()
+    }
+
+    /// choice with highest `score`, the first one wins a tie
+    pub fn best_by<S, F>(&self, mut score: F) -> Option<&Choice>
+    where
+        S: PartialOrd,
+        F: FnMut(&Choice) -> S,
+    {
+        let mut best: Option<(&Choice, S)> = None;
+        for choice in self.choices.iter() {
+            let s = score(choice);
+            if best.as_ref().is_none_or(|(_, b)| s > *b) {
+                best = Some((choice, s));
+            }
+        }
+        best.map(|(c, _)| c)
+    }
+
+    /// message of every choice as assistant message, e.g. to re-inject into a conversation
+    pub fn into_messages(self) -> Vec<Message> {
+        self.choices
+            .into_iter()
+            .map(|c| {
+                let mut message = c.message;
+                if message.role.is_none() {
+                    message.role = Some(Role::assistant);
+                }
+                message
+            })
+            .collect()
+    }
+
+    /// tool calls of first choice, empty if none
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        self.choices
+            .first()
+            .map(|c| c.message.tool_calls.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn has_tool_calls(&self) -> bool {
+        !self.tool_calls().is_empty()
+    }
+
     pub fn merge_delta(&mut self, delta: ChatCompletionStreamData) {
         let ChatCompletionStreamData {
             id,
@@ -365,28 +1485,56 @@ impl ChatCompletionResponse {
             model,
             choices,
             usage,
+            prompt_filter_results,
         } = delta;
 
         if let Some(usage) = usage {
             self.usage = Some(usage);
         }
 
-        if let Some(id) = id {
-            self.id = id;
+        if let Some(prompt_filter_results) = prompt_filter_results {
+            self.prompt_filter_results
+                .get_or_insert_with(Vec::new)
+                .extend(prompt_filter_results);
+        }
+
+        // identity of response is taken from the first chunk carrying it,
+        // azure sends prompt filter results first with empty identity
+        let mut mismatch = vec![];
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            if self.id.is_empty() {
+                self.id = id;
+            } else if self.id != id {
+                mismatch.push(format!("id `{}` vs `{}`", self.id, id));
+            }
         }
 
-        if let Some(object) = object {
+        if let Some(object) = object.filter(|object| !object.is_empty()) {
             if self.object.is_empty() {
                 self.object = object;
             }
         }
 
-        if let Some(created) = created {
-            self.created = created;
+        if let Some(created) = created.filter(|created| *created != 0) {
+            if self.created == 0 {
+                self.created = created;
+            } else if self.created != created {
+                mismatch.push(format!("created `{}` vs `{}`", self.created, created));
+            }
+        }
+
+        if let Some(model) = model.filter(|model| !model.is_empty()) {
+            if self.model.is_empty() {
+                self.model = model;
+            } else if self.model != model {
+                mismatch.push(format!("model `{}` vs `{}`", self.model, model));
+            }
         }
 
-        if let Some(model) = model {
-            self.model = model;
+        // a disagreeing provider usually does so on every chunk, warn once per response
+        if !mismatch.is_empty() && !self.identity_mismatch {
+            self.identity_mismatch = true;
+            warn!(?mismatch, "stream chunk with different identity, ignored");
         }
 
         'outer: for delta in choices {
@@ -395,6 +1543,9 @@ impl ChatCompletionResponse {
                 delta,
                 finish_reason,
                 usage,
+                logprobs,
+                content_filter_results,
+                full_message,
             } = delta;
 
             if let Some(usage) = usage {
@@ -406,10 +1557,17 @@ impl ChatCompletionResponse {
                 content,
                 tool_calls,
                 tool_call_id,
+                annotations,
+                refusal,
+                extra,
             } = delta;
 
             for choice in &mut self.choices {
                 if choice.index == index {
+                    if full_message {
+                        choice.message.forget_merged(content.as_ref(), &tool_calls);
+                    }
+
                     if let Some(role) = role {
                         choice.message.role = Some(role);
                     }
@@ -428,65 +1586,302 @@ impl ChatCompletionResponse {
                         choice.message.tool_call_id = Some(tool_call_id);
                     }
 
-                    if choice.message.tool_calls.is_empty() {
-                        choice.message.tool_calls = tool_calls;
-                    } else {
-                        choice
-                            .message
-                            .tool_calls
-                            .iter_mut()
-                            .zip(tool_calls)
-                            .for_each(|(lhs, rhs)| {
-                                if let Some(name) = rhs.function.name.as_ref() {
-                                    if !name.is_empty() {
-                                        lhs.function.name = Some(name.clone());
-                                    }
-                                }
-
-                                match (&mut lhs.function.arguments, &rhs.function.arguments) {
-                                    (Some(lhs), Some(rhs)) => {
-                                        *lhs = format!("{}{}", lhs, rhs);
-                                    }
-                                    (None, Some(rhs)) => {
-                                        lhs.function.arguments = Some(rhs.clone());
-                                    }
-                                    _ => {}
-                                }
-                            });
+                    merge_tool_calls(&mut choice.message.tool_calls, tool_calls);
+
+                    if let Some(refusal) = refusal {
+                        match choice.message.refusal.as_mut() {
+                            Some(r) => r.push_str(&refusal),
+                            None => choice.message.refusal = Some(refusal),
+                        }
+                    }
+
+                    // some providers repeat annotations already sent in later chunks
+                    for annotation in annotations {
+                        if !choice.message.annotations.contains(&annotation) {
+                            choice.message.annotations.push(annotation);
+                        }
                     }
 
                     if let Some(finish_reason) = finish_reason {
                         choice.finish_reason = Some(finish_reason);
                     }
 
+                    merge_logprobs(&mut choice.logprobs, logprobs);
+
+                    merge_content_filter_results(
+                        &mut choice.content_filter_results,
+                        content_filter_results,
+                    );
+
+                    // string fields are streamed in pieces, e.g. `reasoning_content`, others replaced
+                    for (key, value) in extra {
+                        match (choice.message.extra.get_mut(&key), value) {
+                            (
+                                Some(serde_json::Value::String(lhs)),
+                                serde_json::Value::String(rhs),
+                            ) if full_message && rhs.starts_with(lhs.as_str()) => *lhs = rhs,
+                            (
+                                Some(serde_json::Value::String(lhs)),
+                                serde_json::Value::String(rhs),
+                            ) => lhs.push_str(&rhs),
+                            (_, value) => {
+                                choice.message.extra.insert(key, value);
+                            }
+                        }
+                    }
+
                     continue 'outer;
                 }
             }
 
-            self.choices.push(Choice {
-                index,
-                message: Message {
-                    role,
-                    content,
-                    tool_call_id,
-                    tool_calls,
+            // chunks of different choices may interleave, keep choices ordered by index
+            let pos = self.choices.partition_point(|c| c.index < index);
+            self.choices.insert(
+                pos,
+                Choice {
+                    index,
+                    message: Message {
+                        role,
+                        content,
+                        tool_call_id,
+                        tool_calls,
+                        annotations,
+                        refusal,
+                        extra,
+                    },
+                    finish_reason,
+                    logprobs,
+                    content_filter_results,
+                    extra: Default::default(),
                 },
-                finish_reason,
-            });
+            );
+        }
+
+        // some providers omit role in every delta, default finished choice to assistant
+        for choice in &mut self.choices {
+            if choice.finish_reason.is_some() && choice.message.role.is_none() {
+                warn!(
+                    index = choice.index,
+                    "choice finished without role, default to assistant"
+                );
+                choice.message.role = Some(Role::assistant);
+            }
         }
     }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Choice {
     pub index: usize,
     pub message: Message,
     pub finish_reason: Option<String>,
+    /// set if requested with `logprobs`
+    pub logprobs: Option<ChoiceLogprobs>,
+    /// azure filter results of completion
+    pub content_filter_results: Option<ContentFilterResults>,
+    /// provider specific fields, e.g. vllm `stop_reason`
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Choice {
+    /// known finish reason, `None` if not finished or provider specific
+    pub fn parsed_finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason.as_deref()?.parse().ok()
+    }
+
+    /// finished by `content_filter` or any filter category tripped
+    pub fn is_content_filtered(&self) -> bool {
+        self.parsed_finish_reason() == Some(FinishReason::content_filter)
+            || self
+                .content_filter_results
+                .as_ref()
+                .is_some_and(|r| r.values().any(|c| c.filtered))
+    }
+}
+
+/// log probabilities of output tokens
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChoiceLogprobs {
+    pub content: Option<Vec<TokenLogprob>>,
+    pub refusal: Option<Vec<TokenLogprob>>,
+}
+
+/// `logprob` is `f64`, so this and every type holding logprobs, e.g. `Choice`,
+/// `ChatCompletionResponse`, `StreamChoice` and `ChatCompletionStreamData`, is no longer `Eq`
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// utf-8 bytes of token, a character may span several tokens
+    pub bytes: Option<Vec<u8>>,
+    /// most likely tokens at this position, empty unless `top_logprobs` requested
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// each stream chunk carries logprobs of its own tokens, append them in order
+fn merge_logprobs(lhs: &mut Option<ChoiceLogprobs>, rhs: Option<ChoiceLogprobs>) {
+    let Some(rhs) = rhs else {
+        return;
+    };
+    let lhs = lhs.get_or_insert_with(Default::default);
+    for (lhs, rhs) in [
+        (&mut lhs.content, rhs.content),
+        (&mut lhs.refusal, rhs.refusal),
+    ] {
+        if let Some(rhs) = rhs {
+            lhs.get_or_insert_with(Vec::new).extend(rhs);
+        }
+    }
+}
+
+/// azure content filter results by category, e.g. `hate`, `violence`, `jailbreak`,
+/// categories unknown to this crate are kept as well
+pub type ContentFilterResults = BTreeMap<String, ContentFilterResult>;
+
+/// azure content filter result of one category
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "ContentFilterResultWire", into = "ContentFilterResultWire")]
+pub struct ContentFilterResult {
+    pub filtered: bool,
+    /// `safe`, `low`, `medium` or `high`
+    pub severity: Option<String>,
+    /// set by detection categories, e.g. `jailbreak`, `protected_material_text`
+    pub detected: Option<bool>,
+    /// other fields, e.g. `citation` or `code` and `message` of a failed filter
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// category which is not an object, kept as is, e.g. `custom_blocklists` sent as array
+    /// by older api versions, `filtered` is then set if any entry is, other fields are unset
+    pub other: Option<serde_json::Value>,
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ContentFilterResultObject {
+    #[serde(default)]
+    filtered: bool,
+    severity: Option<String>,
+    detected: Option<bool>,
+    #[serde(flatten, default)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum ContentFilterResultWire {
+    Object(ContentFilterResultObject),
+    Other(serde_json::Value),
+}
+
+impl From<ContentFilterResultWire> for ContentFilterResult {
+    fn from(wire: ContentFilterResultWire) -> Self {
+        match wire {
+            ContentFilterResultWire::Object(r) => Self {
+                filtered: r.filtered,
+                severity: r.severity,
+                detected: r.detected,
+                extra: r.extra,
+                other: None,
+            },
+            ContentFilterResultWire::Other(other) => Self {
+                filtered: other
+                    .as_array()
+                    .is_some_and(|a| a.iter().any(|e| e["filtered"] == true)),
+                other: Some(other),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<ContentFilterResult> for ContentFilterResultWire {
+    fn from(r: ContentFilterResult) -> Self {
+        match r.other {
+            Some(other) => Self::Other(other),
+            None => Self::Object(ContentFilterResultObject {
+                filtered: r.filtered,
+                severity: r.severity,
+                detected: r.detected,
+                extra: r.extra,
+            }),
+        }
+    }
+}
+
+/// azure filter results of one prompt
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PromptFilterResult {
+    pub prompt_index: usize,
+    #[serde(default)]
+    pub content_filter_results: ContentFilterResults,
+}
+
+/// merge filter results of a stream chunk, a tripped category wins over an untripped one
+fn merge_content_filter_results(
+    lhs: &mut Option<ContentFilterResults>,
+    rhs: Option<ContentFilterResults>,
+) {
+    let Some(rhs) = rhs else {
+        return;
+    };
+    let lhs = lhs.get_or_insert_with(Default::default);
+    for (category, result) in rhs {
+        match lhs.get_mut(&category) {
+            Some(current) if current.filtered && !result.filtered => {}
+            Some(current) => *current = result,
+            None => {
+                lhs.insert(category, result);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum FinishReason {
+    stop,
+    length,
+    tool_calls,
+    content_filter,
+    function_call,
+}
+
+impl_wire_str!(
+    FinishReason,
+    stop => "stop",
+    length => "length",
+    tool_calls => "tool_calls",
+    content_filter => "content_filter",
+    function_call => "function_call",
+);
+
+impl FinishReason {
+    /// conversation turn is over, `false` if model waits for tool results
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, Self::tool_calls | Self::function_call)
+    }
+
+    /// reply was cut off by `max_tokens` or context length
+    pub fn was_truncated(&self) -> bool {
+        matches!(self, Self::length)
+    }
+}
+
+/// `"stop": "\n"` or `"stop": ["a", "b"]` on the wire
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
 pub enum Stop {
     Text(String),
     Texts(Vec<String>),
@@ -513,7 +1908,7 @@ impl Stop {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct ChatComplitionUsage {
     pub cached_tokens: Option<u64>,
     pub completion_tokens: u64,
@@ -521,35 +1916,201 @@ pub struct ChatComplitionUsage {
     pub total_tokens: u64,
 }
 
-#[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+impl std::ops::Add for ChatComplitionUsage {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self += rhs;
+        self
+    }
+}
+
+impl std::ops::AddAssign for ChatComplitionUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.cached_tokens = match (self.cached_tokens, rhs.cached_tokens) {
+            (None, None) => None,
+            (lhs, rhs) => Some(lhs.unwrap_or_default() + rhs.unwrap_or_default()),
+        };
+        self.completion_tokens += rhs.completion_tokens;
+        self.prompt_tokens += rhs.prompt_tokens;
+        self.total_tokens += rhs.total_tokens;
+    }
+}
+
+impl ChatComplitionUsage {
+    /// usage with `total_tokens` computed from prompt and completion
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            cached_tokens: None,
+            completion_tokens,
+            prompt_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// provider specific message fields only valid in a response
+pub const RESPONSE_ONLY_FIELDS: &[&str] = &["reasoning_content"];
+
+/// serialized by `MessageWire`, unset fields are omitted
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, SmartDefault)]
 pub struct Message {
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub role: Option<Role>,
+    /// assistant message with `tool_calls` sends `"content": null` when unset, as openai requires
+    #[serde(default)]
     pub content: Option<Content>,
+    #[serde(default)]
     pub tool_call_id: Option<String>,
+    /// omitted when empty, stricter gateways reject `"tool_calls": []`
     #[serde(default)]
     pub tool_calls: Vec<ToolCall>,
+    /// citations of assistant message, e.g. from web search
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    /// reason of model declining a structured output request
+    #[serde(default)]
+    pub refusal: Option<String>,
+    /// provider specific fields, e.g. `reasoning_content`, kept through serialize round trip
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-fn empty_string_as_none<'de, D>(de: D) -> std::result::Result<Option<Role>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let opt = Option::<String>::deserialize(de)?;
-    match opt.as_deref() {
-        None | Some("") => Ok(None),
-        Some(s) => Role::deserialize(s.into_deserializer()).map(Some),
-    }
+#[derive(serde::Serialize)]
+struct MessageWire<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'a Role>,
+    /// `Some(None)` is written as `null`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<Option<&'a Content>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tool_calls: &'a [ToolCall],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    annotations: &'a [Annotation],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refusal: Option<&'a String>,
+    #[serde(flatten)]
+    extra: &'a serde_json::Map<String, serde_json::Value>,
 }
 
-impl Message {
-    pub fn builder() -> MessageBuilder {
-        MessageBuilder::default()
+impl serde::Serialize for Message {
+    fn serialize<S>(&self, ser: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let null_content = self.role == Some(Role::assistant) && !self.tool_calls.is_empty();
+        let content = match self.content.as_ref() {
+            Some(content) => Some(Some(content)),
+            None if null_content => Some(None),
+            None => None,
+        };
+
+        MessageWire {
+            role: self.role.as_ref(),
+            content,
+            tool_call_id: self.tool_call_id.as_ref(),
+            tool_calls: &self.tool_calls,
+            annotations: &self.annotations,
+            refusal: self.refusal.as_ref(),
+            extra: &self.extra,
+        }
+        .serialize(ser)
     }
 }
 
-#[derive(SmartDefault)]
+/// annotation on assistant message content, tagged by `type`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Annotation {
+    UrlCitation {
+        url_citation: UrlCitation,
+    },
+    /// annotation type unknown to this crate, kept as is, so it round trips unchanged
+    #[serde(untagged)]
+    Other(serde_json::Value),
+}
+
+/// cited web page, `start_index..end_index` is the citing span of message content in chars
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct UrlCitation {
+    pub start_index: usize,
+    pub end_index: usize,
+    pub url: String,
+    pub title: Option<String>,
+}
+
+impl Annotation {
+    pub fn url_citation(&self) -> Option<&UrlCitation> {
+        match self {
+            Annotation::UrlCitation { url_citation } => Some(url_citation),
+            Annotation::Other(_) => None,
+        }
+    }
+}
+
+fn empty_string_as_none<'de, D>(de: D) -> std::result::Result<Option<Role>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let opt = Option::<String>::deserialize(de)?;
+    match opt.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => Role::deserialize(s.into_deserializer()).map(Some),
+    }
+}
+
+impl Message {
+    pub fn builder() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+
+    /// drop `RESPONSE_ONLY_FIELDS` from `extra`, see `with_keep_response_fields`
+    pub fn remove_response_fields(&mut self) {
+        for key in RESPONSE_ONLY_FIELDS {
+            self.extra.remove(*key);
+        }
+    }
+
+    /// rewrite content into the form accepted by provider, see `ContentStyle`
+    pub fn normalize_for(&mut self, style: ContentStyle) {
+        if style == ContentStyle::PreferText {
+            if let Some(content) = self.content.take() {
+                self.content = Some(content.flatten());
+            }
+        }
+    }
+
+    /// drop merged parts which a whole message chunk repeats, so merging it stays idempotent,
+    /// text is dropped if `content` extends it, tool calls if `tool_calls` repeat all their ids
+    fn forget_merged(&mut self, content: Option<&Content>, tool_calls: &[ToolCall]) {
+        if let (Some(merged), Some(content)) = (self.content.as_ref(), content) {
+            if content.to_text().starts_with(&merged.to_text()) {
+                self.content = None;
+            }
+        }
+
+        let repeated = |t: &ToolCall| t.id.is_some() && tool_calls.iter().any(|rhs| rhs.id == t.id);
+        if !tool_calls.is_empty() && self.tool_calls.iter().all(repeated) {
+            self.tool_calls.clear();
+        }
+    }
+}
+
+/// how message content is serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ContentStyle {
+    /// keep content as is
+    #[default]
+    Preserve,
+    /// flatten text-only content arrays into plain string, for backends reject array form,
+    /// content with image or file parts is untouched
+    PreferText,
+}
+
+#[derive(SmartDefault)]
 pub struct MessageBuilder {
     role: Option<Role>,
     content: Option<Content>,
@@ -568,15 +2129,41 @@ impl MessageBuilder {
         self
     }
 
-    // pub fn add_content(mut self, content: impl Into<Content>) -> Self {
-    //     let mut lhs = None;
-    //     std::mem::swap(&mut self.content, &mut lhs);
-    //     self.content = match lhs {
-    //         None => Some(content.into()),
-    //         Some(lhs) => Some(lhs.merge(content)),
-    //     };
-    //     self
-    // }
+    /// merge `content` after current content, see `Content::merge`
+    pub fn add_content(mut self, content: impl Into<Content>) -> Self {
+        match self.content.as_mut() {
+            Some(lhs) => lhs.merge(content.into()),
+            None => self.content = Some(content.into()),
+        }
+        self
+    }
+
+    /// text part after current content, plain string if it is the only part
+    pub fn add_text(mut self, text: impl Into<String>) -> Self {
+        match self.content.as_mut() {
+            Some(lhs) => lhs.append(text.into()),
+            None => self.content = Some(Content::Text(text.into())),
+        }
+        self
+    }
+
+    /// image part of `url` after current content
+    pub fn add_image_url(self, url: impl Into<String>) -> Self {
+        self.add_part(ImageUrl::from_url(url))
+    }
+
+    /// image part of local file encoded as data url, see `ImageUrl::from_local_file`
+    pub async fn add_image_file(self, path: impl Into<std::path::PathBuf>) -> Result<Self> {
+        Ok(self.add_part(ImageUrl::from_local_file(path).await?))
+    }
+
+    fn add_part(mut self, part: impl Into<ContentContainer>) -> Self {
+        match self.content.as_mut() {
+            Some(lhs) => lhs.append(part),
+            None => self.content = Some(Content::Containers(vec![part.into()])),
+        }
+        self
+    }
 
     pub fn with_tool_call_id(mut self, tool_call_id: impl Into<String>) -> Self {
         self.tool_call_id = Some(tool_call_id.into());
@@ -606,12 +2193,15 @@ impl MessageBuilder {
             content,
             tool_call_id,
             tool_calls,
+            annotations: vec![],
+            refusal: None,
+            extra: Default::default(),
         }
     }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum Role {
     system,
@@ -620,8 +2210,16 @@ pub enum Role {
     tool,
 }
 
+impl_wire_str!(
+    Role,
+    system => "system",
+    user => "user",
+    assistant => "assistant",
+    tool => "tool",
+);
+
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(untagged)]
 pub enum Content {
     Text(String),
@@ -661,6 +2259,112 @@ impl Content {
         Content::Text(text.into())
     }
 
+    /// serialize structured data into compact json text, e.g. tool call result
+    pub fn from_json(value: &impl serde::Serialize) -> Result<Self> {
+        Ok(Content::Text(serde_json::to_string(value)?))
+    }
+
+    /// sample every `every_nth_frame` frame of a video, at most `max_frames` frames,
+    /// downscale to fit `max_dim` and encode each one as jpeg `image_url` part
+    #[cfg(feature = "opencv")]
+    pub async fn from_video_frames(
+        path: impl Into<std::path::PathBuf>,
+        every_nth_frame: usize,
+        max_frames: usize,
+        max_dim: i32,
+    ) -> Result<Self> {
+        use opencv::prelude::*;
+        use opencv::videoio::{VideoCapture, CAP_ANY};
+
+        let path = path.into();
+        let every_nth_frame = every_nth_frame.max(1);
+
+        let containers = tokio::task::spawn_blocking(move || -> Result<Vec<ContentContainer>> {
+            let file_name = path.to_str().ok_or(Error::NoFileName)?;
+            let mut capture = VideoCapture::from_file(file_name, CAP_ANY)?;
+            if !capture.is_opened()? {
+                error!(?path, "failed to open video");
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("failed to open video {}", path.display()),
+                )));
+            }
+
+            let mut containers = vec![];
+            let mut frame = opencv::core::Mat::default();
+            let mut index = 0usize;
+
+            while containers.len() < max_frames && capture.read(&mut frame)? {
+                if index % every_nth_frame == 0 {
+                    let mat = downscale_mat(std::mem::take(&mut frame), max_dim)?;
+                    containers.push(ImageUrl::from_mat(&mat, "jpg")?.into());
+                }
+                index += 1;
+            }
+
+            trace!(?path, frames = %index, sampled = %containers.len(), "sample video frames");
+
+            Ok(containers)
+        })
+        .await??;
+
+        Ok(Content::Containers(containers))
+    }
+
+    /// reference a file uploaded with `FileUploadRequest` by its id
+    pub fn from_file_id(id: impl Into<String>) -> Self {
+        Content::Containers(vec![ContentContainer::from_file_id(id)])
+    }
+
+    /// convert text-only containers into `Content::Text`, others returned as is
+    pub fn flatten(self) -> Self {
+        match self {
+            Content::Containers(cs)
+                if cs
+                    .iter()
+                    .all(|c| matches!(c, ContentContainer::Text { .. })) =>
+            {
+                Content::Text(Content::Containers(cs).to_text())
+            }
+            content => content,
+        }
+    }
+
+    /// trim leading and trailing whitespace of every text part, other parts are untouched
+    pub fn trim(&mut self) {
+        let trim = |s: &mut String| {
+            if s.trim().len() != s.len() {
+                *s = s.trim().to_string();
+            }
+        };
+        match self {
+            Content::Text(s) => trim(s),
+            Content::Containers(cs) => {
+                for c in cs.iter_mut() {
+                    if let ContentContainer::Text { text, .. } = c {
+                        trim(text);
+                    }
+                }
+            }
+        }
+    }
+
+    /// concat all text parts, non-text parts are skipped
+    pub fn to_text(&self) -> String {
+        match self {
+            Content::Text(s) => s.clone(),
+            Content::Containers(cs) => cs
+                .iter()
+                .filter_map(|c| match c {
+                    ContentContainer::Text { text, .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// append `rhs` after self, text into text is concatenated,
+    /// otherwise parts are kept in order, text turned into a `text` part
     pub fn merge(&mut self, rhs: Self) {
         *self = match self {
             Content::Text(s0) => match rhs {
@@ -669,7 +2373,13 @@ impl Content {
                     return;
                 }
                 Content::Containers(cs) => {
-                    let mut cs_ = vec![s0.clone().into()];
+                    let mut cs_ = vec![];
+                    if !s0.is_empty() {
+                        cs_.push(ContentContainer::Text {
+                            typ: "text".into(),
+                            text: std::mem::take(s0),
+                        });
+                    }
                     cs_.extend(cs);
                     Content::Containers(cs_)
                 }
@@ -707,7 +2417,7 @@ impl Content {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(untagged)]
 pub enum ContentContainer {
     Text {
@@ -720,10 +2430,31 @@ pub enum ContentContainer {
         typ: String,
         image_url: ImageUrl,
     },
+    File {
+        #[serde(rename = "type")]
+        typ: String,
+        file: FileRef,
+    },
+}
+
+impl ContentContainer {
+    pub fn from_file_id(id: impl Into<String>) -> Self {
+        ContentContainer::File {
+            typ: "file".into(),
+            file: FileRef { file_id: id.into() },
+        }
+    }
+}
+
+/// reference to a previously uploaded file
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FileRef {
+    pub file_id: String,
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct ImageUrl {
     pub url: String,
 }
@@ -746,35 +2477,196 @@ impl From<String> for ContentContainer {
     }
 }
 
+/// default max size of image binary encoded into data url, 5MB
+pub const IMAGE_MAX_BYTES: usize = 5 * 1024 * 1024;
+
+/// mime of image by magic bytes, png, jpeg, gif, webp and bmp are recognized
+pub fn sniff_image_mime(image: &[u8]) -> Option<&'static str> {
+    match image {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+/// mime of image by file extension, `jpg` is `image/jpeg`
+pub(crate) fn image_mime_of_suffix(suffix: &str) -> Option<&'static str> {
+    match suffix.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "bmp" => Some("image/bmp"),
+        _ => None,
+    }
+}
+
 impl ImageUrl {
+    /// read local image into data url, reject image larger than `IMAGE_MAX_BYTES`,
+    /// mime is sniffed from content, extension is only used if sniffing fails
     pub async fn from_local_file(path: impl Into<std::path::PathBuf>) -> Result<Self> {
+        Self::from_local_file_with_limit(path, Some(IMAGE_MAX_BYTES)).await
+    }
+
+    /// read local image into data url, reject image larger than `max_bytes` if set
+    pub async fn from_local_file_with_limit(
+        path: impl Into<std::path::PathBuf>,
+        max_bytes: Option<usize>,
+    ) -> Result<Self> {
         let path = path.into();
-        let suffix = path
-            .extension()
-            .ok_or(Error::NoFileExtension)?
-            .to_str()
-            .ok_or(Error::NoFileExtension)?;
+        if let Some(max_bytes) = max_bytes {
+            let size = tokio::fs::metadata(&path).await?.len() as usize;
+            if size > max_bytes {
+                return Err(Error::ImageTooLarge(size, max_bytes));
+            }
+        }
         let binary = tokio::fs::read(&path).await?;
-        Ok(ImageUrl::from_image_binary(binary, suffix))
+
+        let suffix_mime = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .and_then(image_mime_of_suffix);
+
+        let mime = match (sniff_image_mime(&binary), suffix_mime) {
+            (Some(mime), Some(suffix_mime)) if mime != suffix_mime => {
+                warn!(?path, %mime, "image extension mismatch content, use sniffed mime");
+                mime
+            }
+            (Some(mime), _) | (None, Some(mime)) => mime,
+            (None, None) => {
+                error!(?path, "unsupported image format");
+                return Err(Error::UnsupportedImageFormat(path.display().to_string()));
+            }
+        };
+
+        Ok(Self::from_mime(binary, mime))
     }
 
     pub fn from_url(url: impl Into<String>) -> Self {
         ImageUrl { url: url.into() }
     }
 
+    /// encode image into data url, `suffix` is extension of image format, e.g. `png`, `jpg`
     pub fn from_image_binary(image: impl AsRef<[u8]>, suffix: impl AsRef<str>) -> Self {
+        let suffix = suffix.as_ref();
+        match image_mime_of_suffix(suffix) {
+            Some(mime) => Self::from_mime(image, mime),
+            None => Self::from_mime(image, &format!("image/{}", suffix)),
+        }
+    }
+
+    fn from_mime(image: impl AsRef<[u8]>, mime: &str) -> Self {
         ImageUrl {
             url: format!(
-                "data:image/{};base64,{}",
-                suffix.as_ref(),
+                "data:{};base64,{}",
+                mime,
                 base64::prelude::BASE64_STANDARD.encode(image)
             ),
         }
     }
+
+    /// same as `from_image_binary`, but reject image larger than `max_bytes` before encoding
+    pub fn from_image_binary_with_limit(
+        image: impl AsRef<[u8]>,
+        suffix: impl AsRef<str>,
+        max_bytes: Option<usize>,
+    ) -> Result<Self> {
+        let size = image.as_ref().len();
+        match max_bytes {
+            Some(max_bytes) if size > max_bytes => {
+                error!(%size, %max_bytes, "image too large to encode");
+                Err(Error::ImageTooLarge(size, max_bytes))
+            }
+            _ => Ok(Self::from_image_binary(image, suffix)),
+        }
+    }
+
+    /// encode opencv `Mat` into data url, `suffix` decide image format, e.g. `jpg`, `png`
+    #[cfg(feature = "opencv")]
+    pub fn from_mat(mat: &opencv::core::Mat, suffix: impl AsRef<str>) -> Result<Self> {
+        use opencv::core::Vector;
+
+        let suffix = suffix.as_ref();
+        let mut buf = Vector::<u8>::new();
+        if !opencv::imgcodecs::imencode(&format!(".{}", suffix), mat, &mut buf, &Vector::new())? {
+            error!(%suffix, "failed to encode mat");
+            return Err(Error::ImageCodec(format!(
+                "failed to encode mat as {}",
+                suffix
+            )));
+        }
+        Ok(Self::from_image_binary(buf.as_slice(), suffix))
+    }
+
+    /// read local image, downscale it to fit `max_dim` x `max_dim` keeping aspect ratio, then encode into data url
+    #[cfg(feature = "opencv")]
+    pub async fn from_local_file_resized(
+        path: impl Into<std::path::PathBuf>,
+        max_dim: i32,
+    ) -> Result<Self> {
+        use opencv::prelude::*;
+
+        let path = path.into();
+        let suffix = path
+            .extension()
+            .ok_or(Error::NoFileExtension)?
+            .to_str()
+            .ok_or(Error::NoFileExtension)?
+            .to_string();
+
+        let url = tokio::task::spawn_blocking(move || -> Result<Self> {
+            let file_name = path.to_str().ok_or(Error::NoFileName)?;
+            let mat = opencv::imgcodecs::imread(file_name, opencv::imgcodecs::IMREAD_COLOR)?;
+            if mat.empty() {
+                error!(?path, "failed to decode image");
+                return Err(Error::ImageCodec(format!(
+                    "failed to decode {}",
+                    path.display()
+                )));
+            }
+
+            Self::from_mat(&downscale_mat(mat, max_dim)?, &suffix)
+        })
+        .await??;
+
+        Ok(url)
+    }
+}
+
+/// downscale `mat` to fit `max_dim` x `max_dim` keeping aspect ratio, smaller one is returned as is
+#[cfg(feature = "opencv")]
+fn downscale_mat(mat: opencv::core::Mat, max_dim: i32) -> Result<opencv::core::Mat> {
+    use opencv::prelude::*;
+
+    let (width, height) = (mat.cols(), mat.rows());
+    let scale = max_dim as f64 / width.max(height) as f64;
+    if scale >= 1.0 {
+        return Ok(mat);
+    }
+
+    let size = opencv::core::Size::new(
+        ((width as f64 * scale).round() as i32).max(1),
+        ((height as f64 * scale).round() as i32).max(1),
+    );
+    trace!(%width, %height, ?size, "downscale image");
+
+    let mut resized = Mat::default();
+    opencv::imgproc::resize(
+        &mat,
+        &mut resized,
+        size,
+        0.0,
+        0.0,
+        opencv::imgproc::INTER_AREA,
+    )?;
+    Ok(resized)
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ChatCompletionStreamData {
     pub id: Option<String>,
     pub object: Option<String>,
@@ -782,15 +2674,62 @@ pub struct ChatCompletionStreamData {
     pub model: Option<String>,
     pub choices: Vec<StreamChoice>,
     pub usage: Option<ChatComplitionUsage>,
+    /// azure sends it in the first chunk, usually with no choices
+    pub prompt_filter_results: Option<Vec<PromptFilterResult>>,
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "StreamChoiceWire")]
 pub struct StreamChoice {
     pub index: usize,
     pub delta: Message,
     pub finish_reason: Option<String>,
     pub usage: Option<ChatComplitionUsage>,
+    /// logprobs of tokens in this chunk
+    pub logprobs: Option<ChoiceLogprobs>,
+    /// azure filter results of this chunk
+    pub content_filter_results: Option<ContentFilterResults>,
+    /// chunk may repeat the whole message, e.g. the final `message` chunk of some ollama proxies,
+    /// `merge_delta` replaces merged text the message extends instead of appending,
+    /// only set for a `message` chunk with `role` and `finish_reason`, older fastchat streams increments
+    /// under `message` too, where a repeated token must still be appended
+    #[serde(skip)]
+    pub full_message: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct StreamChoiceWire {
+    index: usize,
+    delta: Option<Message>,
+    message: Option<Message>,
+    finish_reason: Option<String>,
+    usage: Option<ChatComplitionUsage>,
+    logprobs: Option<ChoiceLogprobs>,
+    content_filter_results: Option<ContentFilterResults>,
+}
+
+impl From<StreamChoiceWire> for StreamChoice {
+    fn from(wire: StreamChoiceWire) -> Self {
+        let (delta, full_message) = match (wire.delta, wire.message) {
+            (Some(delta), _) => (delta, false),
+            // a final increment carries no role, it must not be taken for the whole message
+            (None, Some(message)) => {
+                let full = wire.finish_reason.is_some() && message.role.is_some();
+                (message, full)
+            }
+            (None, None) => (Message::default(), false),
+        };
+        Self {
+            index: wire.index,
+            delta,
+            finish_reason: wire.finish_reason,
+            usage: wire.usage,
+            logprobs: wire.logprobs,
+            content_filter_results: wire.content_filter_results,
+            full_message,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -881,8 +2820,8 @@ async fn example_code() -> Result<()> {
 async fn test_chat_simple_ok() -> Result<()> {
     let client = Client::from_env_file(".env.stepfun")?;
 
-    let model_name = std::env::var("OPENAI_API_MODEL_NAME")?;
-    let use_stream = std::env::var("USE_STREAM").is_ok();
+    let _model_name = std::env::var("OPENAI_API_MODEL_NAME")?;
+    let _use_stream = std::env::var("USE_STREAM").is_ok();
 
     let _ = tracing_subscriber::fmt::try_init();
 
@@ -952,3 +2891,2258 @@ async fn test_chat_simple_ok() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[test]
+fn test_tool_call_message_null_content_ok() -> Result<()> {
+    let rep: ChatCompletionResponse = serde_json::from_value(serde_json::json!({
+        "id": "1", "object": "chat.completion", "created": 1, "model": "m",
+        "choices": [{"index": 0, "finish_reason": "tool_calls", "message": {
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "now", "arguments": "{}"}}]
+        }}]
+    }))?;
+
+    let mut req = ChatCompletionRequest::simple("m", "what time is it?")?;
+    req.messages.push(rep.choices[0].message.clone());
+    req.messages.push(
+        Message::builder()
+            .with_role(Role::tool)
+            .with_tool_call_id("call_1")
+            .with_content("12:00")
+            .build(),
+    );
+
+    let value = serde_json::to_value(&req)?;
+    let assistant = value["messages"][1].as_object().expect("assistant message");
+    assert_eq!(assistant.get("content"), Some(&serde_json::Value::Null));
+    assert_eq!(assistant["tool_calls"][0]["id"], "call_1");
+
+    let user = value["messages"][0].as_object().expect("user message");
+    assert!(user.get("tool_calls").is_none());
+
+    // absent content is still omitted without tool_calls
+    let msg = Message::builder().with_role(Role::assistant).build();
+    assert_eq!(
+        serde_json::to_value(&msg)?,
+        serde_json::json!({"role": "assistant"})
+    );
+
+    let back: ChatCompletionRequest = serde_json::from_value(value)?;
+    assert_eq!(back.messages, req.messages);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_message_builder_mixed_content_ok() -> Result<()> {
+    let png = std::env::temp_dir().join(format!("openai-ng-mixed-{}.png", std::process::id()));
+    std::fs::write(&png, [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a])?;
+
+    let msg = Message::builder()
+        .with_role(Role::user)
+        .add_text("compare these:")
+        .add_image_url("https://a.com/1.png")
+        .add_image_url("https://a.com/2.png")
+        .add_image_file(&png)
+        .await?
+        .add_text("which is brighter?")
+        .build();
+    std::fs::remove_file(&png)?;
+
+    let value = serde_json::to_value(&msg)?;
+    let parts = value["content"].as_array().expect("content parts");
+    let types = parts.iter().map(|p| p["type"].as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [
+            Some("text"),
+            Some("image_url"),
+            Some("image_url"),
+            Some("image_url"),
+            Some("text")
+        ]
+    );
+    assert_eq!(parts[0]["text"], "compare these:");
+    assert_eq!(parts[2]["image_url"]["url"], "https://a.com/2.png");
+    assert!(parts[3]["image_url"]["url"]
+        .as_str()
+        .is_some_and(|u| u.starts_with("data:image/png;base64,")));
+    assert_eq!(parts[4]["text"], "which is brighter?");
+
+    // single text stays a plain string
+    let msg = Message::builder().add_text("hi").build();
+    assert_eq!(msg.content, Some(Content::Text("hi".into())));
+
+    // text merged with parts becomes a leading `text` part
+    let msg = Message::builder()
+        .add_content("look: ")
+        .add_content("here")
+        .add_content(Content::from_image_url("https://a.com/1.png"))
+        .build();
+    assert_eq!(
+        serde_json::to_value(&msg.content)?,
+        serde_json::json!([
+            {"type": "text", "text": "look: here"},
+            {"type": "image_url", "image_url": {"url": "https://a.com/1.png"}}
+        ])
+    );
+
+    let msg = Message::builder()
+        .add_image_url("https://a.com/1.png")
+        .add_content("caption")
+        .build();
+    assert_eq!(
+        serde_json::to_value(&msg.content)?,
+        serde_json::json!([
+            {"type": "image_url", "image_url": {"url": "https://a.com/1.png"}},
+            {"type": "text", "text": "caption"}
+        ])
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_request_stable_hash_ok() -> Result<()> {
+    use crate::proto::tool::*;
+
+    let tool = |names: [&str; 3]| -> Result<ToolCall> {
+        ToolCallBuilder::function("search", "search the web", |p| {
+            names.into_iter().fold(p, |p, name| {
+                p.add_param(name, ParameterType::string, name, false)
+            })
+        })
+    };
+    let request = |names: [&str; 3], extra: [(&str, i64); 2], temperature: f64| {
+        let builder = ChatCompletionRequest::builder()
+            .with_model("m")
+            .with_temperature(temperature)
+            .add_message(
+                Message::builder()
+                    .with_role(Role::user)
+                    .with_content("hi")
+                    .build(),
+            )
+            .with_tools([tool(names)?]);
+        extra
+            .into_iter()
+            .fold(builder, |b, (k, v)| b.with_extra(k, v))
+            .build()
+    };
+
+    let lhs = request(["query", "site", "lang"], [("a", 1), ("b", 2)], 0.5)?;
+    let rhs = request(["lang", "query", "site"], [("b", 2), ("a", 1)], 0.5)?;
+    assert_eq!(lhs.stable_hash(), rhs.stable_hash());
+    assert_eq!(serde_json::to_vec(&lhs)?, serde_json::to_vec(&rhs)?);
+
+    let hotter = request(["query", "site", "lang"], [("a", 1), ("b", 2)], 0.9)?;
+    assert_ne!(lhs.stable_hash(), hotter.stable_hash());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_response_extra_fields_ok() -> Result<()> {
+    let raw = serde_json::json!({
+        "id": "1", "object": "chat.completion", "created": 1, "model": "moonshot-v1-8k",
+        "search_results": [{"title": "rust", "url": "https://rust-lang.org"}],
+        "choices": [{
+            "index": 0,
+            "finish_reason": "stop",
+            "stop_reason": null,
+            "message": {"role": "assistant", "content": "42", "reasoning_content": "think"}
+        }],
+        "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+    });
+
+    let rep: ChatCompletionResponse = serde_json::from_value(raw.clone())?;
+    assert_eq!(rep.extra["search_results"][0]["title"], "rust");
+    assert!(rep.choices[0].extra.contains_key("stop_reason"));
+    assert_eq!(rep.choices[0].message.extra["reasoning_content"], "think");
+    assert_eq!(serde_json::to_value(&rep)?, raw);
+
+    let mut rep = ChatCompletionResponse::default();
+    for chunk in [
+        r#"{"choices":[{"index":0,"delta":{"role":"assistant","reasoning_content":"let me "}}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"reasoning_content":"think"}}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"content":"42"},"finish_reason":"stop"}]}"#,
+    ] {
+        rep.merge_delta(serde_json::from_str(chunk)?);
+    }
+    assert_eq!(
+        rep.choices[0].message.extra["reasoning_content"],
+        "let me think"
+    );
+
+    let rep: ChatCompletionResponse =
+        serde_json::from_str(crate::tests::VLLM_CHAT_STOP_REASON_JSON)?;
+    assert_eq!(rep.choices[0].extra["stop_reason"], "###");
+    assert_eq!(rep.choices[0].logprobs, None);
+    assert!(rep.extra.contains_key("prompt_logprobs"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_message_skip_empty_tool_calls_ok() -> Result<()> {
+    let msg = Message::builder()
+        .with_role(Role::user)
+        .with_content("hi")
+        .build();
+    assert_eq!(
+        serde_json::to_value(&msg)?,
+        serde_json::json!({"role": "user", "content": "hi"})
+    );
+
+    let msg: Message = serde_json::from_value(serde_json::json!({
+        "role": "assistant",
+        "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "f", "arguments": "{}"}}]
+    }))?;
+    assert_eq!(serde_json::to_value(&msg)?["tool_calls"][0]["id"], "call_1");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_content_file_id_ok() -> Result<()> {
+    let msg = Message::builder()
+        .with_role(Role::user)
+        .with_content(Content::from_file_id("file-abc123"))
+        .build();
+
+    let value = serde_json::to_value(&msg)?;
+
+    assert_eq!(
+        value,
+        serde_json::json!({
+            "role": "user",
+            "content": [{"type": "file", "file": {"file_id": "file-abc123"}}]
+        })
+    );
+
+    let msg: Message = serde_json::from_value(value)?;
+
+    match msg.content {
+        Some(Content::Containers(cs)) => match &cs[0] {
+            ContentContainer::File { typ, file } => {
+                assert_eq!(typ, "file");
+                assert_eq!(file.file_id, "file-abc123");
+            }
+            _ => panic!("expect file content"),
+        },
+        _ => panic!("expect content containers"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_merge_delta_missing_role_ok() -> Result<()> {
+    let chunks = [
+        r#"{"id":"1","created":1,"model":"m","choices":[{"index":0,"delta":{"content":"hello"}}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"role":"","content":" world"},"finish_reason":"stop"}]}"#,
+    ];
+
+    let mut rep = ChatCompletionResponse::default();
+
+    for chunk in chunks {
+        rep.merge_delta(serde_json::from_str(chunk)?);
+    }
+
+    let message = rep.choices[0].message.clone();
+    assert!(matches!(message.role, Some(Role::assistant)));
+    assert!(matches!(message.content, Some(Content::Text(ref t)) if t == "hello world"));
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(message)
+        .build();
+    assert!(req.is_ok());
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(Message::builder().with_content("no role").build())
+        .build();
+    assert!(matches!(req, Err(Error::MessageBuild)));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_retry_before_first_frame_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = [
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#,
+        r#"data: {"choices":[{"index":0,"delta":{"content":"!"},"finish_reason":"stop"}]}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![
+        (503, "application/json", "{}".to_string()),
+        (200, "text/event-stream", sse),
+    ])
+    .await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_retry(2, Duration::from_millis(10))?
+        .build()?;
+
+    let mut rx = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_stream(true)
+        .build()?
+        .call_stream(&client, None)
+        .await?;
+
+    let mut rep = ChatCompletionResponse::default();
+    while let Some(data) = rx.recv().await {
+        rep.merge_delta(data?);
+    }
+
+    assert_eq!(server.hits(), 2);
+    assert!(matches!(rep.choices[0].message.content, Some(Content::Text(ref t)) if t == "hi!"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_image_url_too_large_ok() -> Result<()> {
+    let image = vec![0u8; IMAGE_MAX_BYTES + 1];
+
+    let res = ImageUrl::from_image_binary_with_limit(&image, "png", Some(IMAGE_MAX_BYTES));
+    assert!(
+        matches!(res, Err(Error::ImageTooLarge(size, limit)) if size == IMAGE_MAX_BYTES + 1 && limit == IMAGE_MAX_BYTES)
+    );
+
+    let url = ImageUrl::from_image_binary_with_limit(&image[..16], "png", Some(IMAGE_MAX_BYTES))?;
+    assert!(url.url.starts_with("data:image/png;base64,"));
+
+    assert!(ImageUrl::from_image_binary_with_limit(&image, "png", None).is_ok());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_image_url_sniff_mime_ok() -> Result<()> {
+    let png = b"\x89PNG\r\n\x1a\n fake png";
+    let jpeg = b"\xff\xd8\xff\xe0 fake jpeg";
+    let webp = b"RIFF\x00\x00\x00\x00WEBPVP8 fake webp";
+
+    let dir = std::env::temp_dir().join(format!("openai-ng-sniff-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let cases: [(&str, &[u8], &str); 5] = [
+        ("no-extension", png, "data:image/png;base64,"),
+        ("photo.jpg", jpeg, "data:image/jpeg;base64,"),
+        ("wrong.png", jpeg, "data:image/jpeg;base64,"),
+        ("sticker", webp, "data:image/webp;base64,"),
+        ("unknown.jpg", b"not an image", "data:image/jpeg;base64,"),
+    ];
+    for (name, binary, prefix) in cases {
+        let path = dir.join(name);
+        tokio::fs::write(&path, binary).await?;
+        let url = ImageUrl::from_local_file(&path).await?;
+        assert!(url.url.starts_with(prefix), "{}: {}", name, url.url);
+    }
+
+    let path = dir.join("unknown");
+    tokio::fs::write(&path, b"not an image").await?;
+    let res = ImageUrl::from_local_file(&path).await;
+    assert!(matches!(res, Err(Error::UnsupportedImageFormat(_))));
+
+    tokio::fs::remove_dir_all(&dir).await?;
+
+    assert!(ImageUrl::from_image_binary(jpeg, "jpg")
+        .url
+        .starts_with("data:image/jpeg;base64,"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_chat_cache_hit_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let answer = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#;
+    // server answers twice, once for plain request, once for request with extra header
+    let server = MockServer::start(vec![
+        (200, "application/json", answer.to_string()),
+        (200, "application/json", answer.to_string()),
+    ])
+    .await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_cache(8)?
+        .build()?;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("answer")
+                .build(),
+        )
+        .with_temperature(0.0)
+        .build()?;
+
+    let rep0 = req.call_once(&client, None).await?;
+    let rep1 = req.call_once(&client, None).await?;
+
+    assert_eq!(server.hits(), 1);
+    assert_eq!(rep0.text(), rep1.text());
+
+    // a different header is not served the cached response
+    let mut beta = req.clone();
+    beta.headers.push((
+        HeaderName::from_static("openai-beta"),
+        HeaderValue::from_static("assistants=v2"),
+    ));
+    beta.call_once(&client, None).await?;
+    beta.call_once(&client, None).await?;
+    assert_eq!(server.hits(), 2);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_serde_round_trip_ok() -> Result<()> {
+    use crate::tests::*;
+
+    fn round_trip<T>(json: &str) -> Result<()>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let lhs: T = serde_json::from_str(json)?;
+        let rhs: T = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+        assert_eq!(lhs, rhs);
+        Ok(())
+    }
+
+    round_trip::<ChatCompletionRequest>(STEPFUN_CHAT_COMPLETION_REQUEST_JSON)?;
+    round_trip::<ChatCompletionResponse>(STEPFUN_CHAT_TOOLS_RESPONSE_JSON)?;
+    round_trip::<ChatCompletionStreamData>(STEPFUN_CHAT_STREAM_RESPONSE_JSON)?;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_temperature(0.5)
+        .with_stop(Stop::Text("\n".into()))
+        .build()?;
+    round_trip::<ChatCompletionRequest>(&serde_json::to_string(&req)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_idle_timeout_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = format!(
+        "{}\n\n",
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#
+    );
+
+    let server = MockServer::start_stalled("text/event-stream", sse, Duration::from_secs(5)).await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_stream_idle_timeout(Duration::from_millis(100))?
+        .build()?;
+
+    let mut rx = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_stream(true)
+        .build()?
+        .call_stream(&client, None)
+        .await?;
+
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    assert!(matches!(
+        rx.recv().await,
+        Some(Err(Error::StreamIdleTimeout(idle))) if idle == Duration::from_millis(100)
+    ));
+    assert!(rx.recv().await.is_none());
+
+    // headers then a partial frame, stalled before the first frame completes
+    let server = MockServer::start_stalled(
+        "text/event-stream",
+        "data: {\"id\"".to_string(),
+        Duration::from_secs(5),
+    )
+    .await;
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_stream_idle_timeout(Duration::from_millis(100))?
+        .build()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "hi")?;
+    req.stream = Some(true);
+    let started = Instant::now();
+    let res = req.call_stream(&client, None).await;
+    assert!(matches!(
+        res,
+        Err(Error::StreamIdleTimeout(idle)) if idle == Duration::from_millis(100)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "opencv"))]
+#[tokio::test]
+async fn test_image_url_from_mat_ok() -> Result<()> {
+    use opencv::core::{Mat, Scalar, Vector, CV_8UC3};
+    use opencv::prelude::*;
+
+    let mat = Mat::new_rows_cols_with_default(200, 400, CV_8UC3, Scalar::all(128.0))?;
+
+    let url = ImageUrl::from_mat(&mat, "png")?;
+    assert!(url.url.starts_with("data:image/png;base64,"));
+
+    let path = std::env::temp_dir().join("openai-ng-test-resize.png");
+    opencv::imgcodecs::imwrite(path.to_str().expect("path"), &mat, &Vector::new())?;
+
+    let url = ImageUrl::from_local_file_resized(&path, 100).await?;
+    let (_, data) = url.url.split_once("base64,").expect("data url");
+    let binary = base64::prelude::BASE64_STANDARD
+        .decode(data)
+        .expect("base64");
+    let decoded = opencv::imgcodecs::imdecode(
+        &Vector::<u8>::from_slice(&binary),
+        opencv::imgcodecs::IMREAD_COLOR,
+    )?;
+    assert_eq!((decoded.cols(), decoded.rows()), (100, 50));
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_content_from_json_ok() -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct Weather {
+        city: &'static str,
+        celsius: f64,
+    }
+
+    let msg = Message::builder()
+        .with_role(Role::tool)
+        .with_tool_call_id("call_0")
+        .with_content(Content::from_json(&Weather {
+            city: "Beijing",
+            celsius: 21.5,
+        })?)
+        .build();
+
+    let value = serde_json::to_value(&msg)?;
+
+    assert_eq!(value["role"], "tool");
+    assert_eq!(value["tool_call_id"], "call_0");
+    assert_eq!(value["content"], r#"{"city":"Beijing","celsius":21.5}"#);
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "opencv"))]
+#[tokio::test]
+async fn test_content_from_video_frames_ok() -> Result<()> {
+    use opencv::core::{Mat, Scalar, Size, CV_8UC3};
+    use opencv::prelude::*;
+    use opencv::videoio::VideoWriter;
+
+    let path = std::env::temp_dir().join("openai-ng-test-video.avi");
+    let mut writer = VideoWriter::new(
+        path.to_str().expect("path"),
+        VideoWriter::fourcc('M', 'J', 'P', 'G')?,
+        10.0,
+        Size::new(320, 240),
+        true,
+    )?;
+    for i in 0..20 {
+        let frame =
+            Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(i as f64 * 10.0))?;
+        writer.write(&frame)?;
+    }
+    writer.release()?;
+
+    match Content::from_video_frames(&path, 5, 3, 64).await? {
+        Content::Containers(cs) => {
+            assert_eq!(cs.len(), 3);
+            assert!(cs.iter().all(|c| matches!(
+                c,
+                ContentContainer::Image { image_url, .. } if image_url.url.starts_with("data:image/jpeg;base64,")
+            )));
+        }
+        _ => panic!("expect content containers"),
+    }
+
+    let res = Content::from_video_frames("not-exist.mp4", 1, 1, 64).await;
+    assert!(matches!(res, Err(Error::Io(_)) | Err(Error::Opencv(_))));
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+#[tracing_test::traced_test]
+async fn test_usage_logged_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"step-1-8k","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}],"usage":{"prompt_tokens":11,"completion_tokens":2,"total_tokens":13}}"#;
+    let sse = [
+        r#"data: {"id":"2","model":"step-1-flash","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#,
+        r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}],"usage":{"prompt_tokens":7,"completion_tokens":5,"total_tokens":12}}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![
+        (200, "application/json", chat.to_string()),
+        (200, "text/event-stream", sse),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = ChatCompletionRequest::simple("step-1", "answer")?;
+    req.call_once(&client, None).await?;
+    assert!(logs_contain(
+        "usage prompt_tokens=11 completion_tokens=2 total_tokens=13 model=\"step-1-8k\""
+    ));
+
+    let mut req = req.clone();
+    req.stream = Some(true);
+    let mut rx = req.call_stream(&client, None).await?;
+    while let Some(data) = rx.recv().await {
+        data?;
+    }
+    assert!(logs_contain(
+        "usage prompt_tokens=7 completion_tokens=5 total_tokens=12 model=\"step-1-flash\""
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[tracing_test::traced_test]
+fn test_max_tokens_warning_ok() -> Result<()> {
+    let message = Message::builder()
+        .with_role(Role::user)
+        .with_content("hi")
+        .build();
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("step-1-8k")
+        .add_message(message.clone())
+        .with_max_tokens(16)
+        .with_max_completion_tokens(32)
+        .build()?;
+    assert_eq!(req.max_completion_tokens, Some(32));
+    assert!(logs_contain(
+        "both max_tokens and max_completion_tokens are set"
+    ));
+
+    ChatCompletionRequest::builder()
+        .with_model("o3-mini")
+        .add_message(message.clone())
+        .with_max_tokens(16)
+        .build()?;
+    assert!(logs_contain("max_tokens is deprecated for this model"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_capture_raw_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = [
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#,
+        r#"data: {"choices":[{"index":0,"delta":"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![(200, "text/event-stream", sse)]).await;
+
+    let frames = Arc::new(Mutex::new(vec![]));
+    let frames_ = frames.clone();
+
+    let mut rx = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_stream(true)
+        .build()?
+        .call_stream_with(
+            &server.client()?,
+            None,
+            StreamConfig::default()
+                .capture_raw(true)
+                .on_raw(move |raw| frames_.lock().expect("lock").push(raw.to_string())),
+        )
+        .await?;
+
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    assert!(matches!(
+        rx.recv().await,
+        Some(Err(Error::StreamParse { ref raw, .. })) if raw == r#"{"choices":[{"index":0,"delta":"#
+    ));
+    assert!(rx.recv().await.is_none());
+
+    assert_eq!(frames.lock().expect("lock").len(), 3);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_config_read_idle_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = format!(
+        "{}\n\n",
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#
+    );
+
+    let server = MockServer::start_stalled("text/event-stream", sse, Duration::from_secs(5)).await;
+
+    let idle = Duration::from_millis(100);
+
+    let mut rx = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_stream(true)
+        .build()?
+        .call_stream_with(
+            &server.client()?,
+            None,
+            StreamConfig::default().with_timeouts(Timeouts::default().with_read_idle(idle)),
+        )
+        .await?;
+
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    assert!(matches!(rx.recv().await, Some(Err(Error::StreamIdleTimeout(d))) if d == idle));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_on_complete_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_stream(true)
+        .build()?;
+
+    let sse = [
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#,
+        r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+        r#"data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":1,"total_tokens":4}}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![(200, "text/event-stream", sse)]).await;
+
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    let mut rx = req
+        .call_stream_with(
+            &server.client()?,
+            None,
+            StreamConfig::default().on_complete(move |s| {
+                done_tx.send(s).expect("send summary");
+            }),
+        )
+        .await?;
+
+    while let Some(data) = rx.recv().await {
+        data?;
+    }
+
+    let summary = done_rx.await.expect("summary");
+    assert_eq!(summary.chunks, 3);
+    assert_eq!(summary.usage, Some(ChatComplitionUsage::new(3, 1)));
+    assert_eq!(
+        summary.finish_reasons.get(&0).map(|r| r.as_str()),
+        Some("stop")
+    );
+
+    // consumer drops receiver while the server stalls
+    let sse = format!(
+        "{}\n\n",
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#
+    );
+    let server = MockServer::start_stalled("text/event-stream", sse, Duration::from_secs(5)).await;
+
+    let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let calls_ = calls.clone();
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+
+    let mut rx = req
+        .call_stream_with(
+            &server.client()?,
+            None,
+            StreamConfig::default().on_complete(move |s| {
+                calls_.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                done_tx.send(s).expect("send summary");
+            }),
+        )
+        .await?;
+
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    drop(rx);
+
+    let summary = tokio::time::timeout(Duration::from_secs(1), done_rx)
+        .await
+        .expect("on_complete after receiver dropped")
+        .expect("summary");
+    assert_eq!(summary.chunks, 1);
+    assert!(summary.usage.is_none());
+    assert!(summary.finish_reasons.is_empty());
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_request_builder_timeout_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let builder = || {
+        ChatCompletionRequest::builder()
+            .with_model("m")
+            .add_message(
+                Message::builder()
+                    .with_role(Role::user)
+                    .with_content("hi")
+                    .build(),
+            )
+    };
+
+    let server =
+        MockServer::start_stalled("application/json", "{".into(), Duration::from_secs(5)).await;
+    let req = builder().with_timeout(Duration::from_millis(100)).build()?;
+    assert!(!serde_json::to_string(&req)?.contains("timeout"));
+
+    let started = std::time::Instant::now();
+    assert!(req.call_once(&server.client()?, None).await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // explicit call site timeout wins
+    let server =
+        MockServer::start_stalled("application/json", "{".into(), Duration::from_secs(5)).await;
+    let req = builder().with_timeout(Duration::from_secs(10)).build()?;
+
+    let started = std::time::Instant::now();
+    assert!(req
+        .call_once(&server.client()?, Some(Duration::from_millis(100)))
+        .await
+        .is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_stop_string_or_array_ok() -> Result<()> {
+    let req = |stop: &str| {
+        serde_json::from_str::<ChatCompletionRequest>(&format!(
+            r#"{{"model":"m","messages":[{{"role":"user","content":"hi"}}],"stop":{}}}"#,
+            stop
+        ))
+    };
+
+    assert_eq!(req(r#""\n""#)?.stop, Some(Stop::Text("\n".into())));
+    assert_eq!(
+        req(r#"["a","b"]"#)?.stop,
+        Some(Stop::Texts(vec!["a".into(), "b".into()]))
+    );
+    assert_eq!(req("null")?.stop, None);
+    assert!(req("1").is_err());
+
+    assert_eq!(serde_json::to_string(&Stop::Text("\n".into()))?, r#""\n""#);
+    assert_eq!(
+        serde_json::to_string(&Stop::Texts(vec!["a".into(), "b".into()]))?,
+        r#"["a","b"]"#
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_response_tool_calls_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let rep: ChatCompletionResponse = serde_json::from_str(STEPFUN_CHAT_TOOLS_RESPONSE_JSON)?;
+    assert!(rep.has_tool_calls());
+    assert_eq!(
+        rep.tool_calls().len(),
+        rep.choices[0].message.tool_calls.len()
+    );
+
+    let rep: ChatCompletionResponse = serde_json::from_str(
+        r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#,
+    )?;
+    assert!(!rep.has_tool_calls());
+    assert!(rep.tool_calls().is_empty());
+
+    assert!(ChatCompletionResponse::default().tool_calls().is_empty());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_add_assistant_response_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let rep: ChatCompletionResponse = serde_json::from_str(STEPFUN_CHAT_TOOLS_RESPONSE_JSON)?;
+    let call = rep.tool_calls()[0].clone();
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("1 + 2 = ?")
+                .build(),
+        )
+        .add_assistant_response(&rep)
+        .add_message(
+            Message::builder()
+                .with_role(Role::tool)
+                .with_tool_call_id(call.id.clone().unwrap_or_default())
+                .with_content("3")
+                .build(),
+        )
+        .add_assistant_response(&ChatCompletionResponse::default())
+        .build()?;
+
+    assert_eq!(req.messages.len(), 3);
+    assert_eq!(req.messages[1].role, Some(Role::assistant));
+    assert_eq!(
+        req.messages[1].tool_calls,
+        rep.choices[0].message.tool_calls
+    );
+    assert_eq!(req.messages[1].tool_calls[0], call);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_response_multi_choice_ok() -> Result<()> {
+    let chunks = [
+        r#"{"id":"1","choices":[{"index":1,"delta":{"role":"assistant","content":"a longer"}}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":"short"}}]}"#,
+        r#"{"choices":[{"index":2,"delta":{"content":"mid one"},"finish_reason":"stop"}]}"#,
+        r#"{"choices":[{"index":1,"delta":{"content":" answer"},"finish_reason":"stop"}]}"#,
+        r#"{"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+    ];
+
+    let mut rep = ChatCompletionResponse::default();
+    for chunk in chunks {
+        rep.merge_delta(serde_json::from_str(chunk)?);
+    }
+
+    assert_eq!(
+        rep.choices.iter().map(|c| c.index).collect::<Vec<_>>(),
+        [0, 1, 2]
+    );
+    assert_eq!(
+        rep.texts(),
+        [
+            Some("short".to_string()),
+            Some("a longer answer".to_string()),
+            Some("mid one".to_string())
+        ]
+    );
+
+    let longest = rep.best_by(|c| c.message.content.as_ref().map(|c| c.to_text().len()));
+    assert_eq!(longest.map(|c| c.index), Some(1));
+
+    let shortest =
+        rep.best_by(|c| std::cmp::Reverse(c.message.content.as_ref().map(|c| c.to_text().len())));
+    assert_eq!(shortest.map(|c| c.index), Some(0));
+
+    assert!(ChatCompletionResponse::default().best_by(|_| 0).is_none());
+
+    let messages = rep.into_messages();
+    assert_eq!(messages.len(), 3);
+    assert!(messages.iter().all(|m| m.role == Some(Role::assistant)));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_auxiliary_events_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = [
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"Rust "}}]}"#,
+        r#"event: search_progress
+data: {"stage":"searching","query":"rust"}"#,
+        r#"data: {"choices":[{"index":0,"delta":{"content":"is fast"}}]}"#,
+        "event: citation\ndata: https://www.rust-lang.org",
+        r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let error = [
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"Rust "}}]}"#,
+        r#"event: error
+data: {"message":"upstream overloaded"}"#,
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![
+        (200, "text/event-stream", sse.clone()),
+        (200, "text/event-stream", sse),
+        (200, "text/event-stream", error),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "is rust fast?")?;
+    req.stream = Some(true);
+
+    let mut rx = req
+        .call_stream_events(&client, None, StreamConfig::default())
+        .await?;
+    let mut rep = ChatCompletionResponse::default();
+    let mut others = vec![];
+    while let Some(event) = rx.recv().await {
+        match event? {
+            StreamEvent::Delta(data) => rep.merge_delta(data),
+            StreamEvent::Other { event, data } => others.push((event, data)),
+        }
+    }
+    assert_eq!(rep.text().as_deref(), Some("Rust is fast"));
+    assert_eq!(
+        others,
+        [
+            (
+                "search_progress".to_string(),
+                serde_json::json!({"stage": "searching", "query": "rust"})
+            ),
+            (
+                "citation".to_string(),
+                serde_json::json!("https://www.rust-lang.org")
+            ),
+        ]
+    );
+
+    let mut rx = req.call_stream(&client, None).await?;
+    let mut rep = ChatCompletionResponse::default();
+    while let Some(data) = rx.recv().await {
+        rep.merge_delta(data?);
+    }
+    assert_eq!(rep.text().as_deref(), Some("Rust is fast"));
+
+    // named error event is not dropped by `call_stream`
+    let mut rx = req.call_stream(&client, None).await?;
+    assert!(rx.recv().await.is_some_and(|r| r.is_ok()));
+    assert!(matches!(
+        rx.recv().await,
+        Some(Err(Error::StreamErrorEvent(data))) if data == r#"{"message":"upstream overloaded"}"#
+    ));
+    assert!(rx.recv().await.is_none());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_web_search_annotations_ok() -> Result<()> {
+    let req = ChatCompletionRequest::builder()
+        .with_model("gpt-4o-search-preview")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("news?")
+                .build(),
+        )
+        .with_web_search(
+            WebSearchOptions::default()
+                .with_search_context_size(SearchContextSize::low)
+                .with_user_location(ApproximateLocation {
+                    country: Some("GB".into()),
+                    city: Some("London".into()),
+                    ..Default::default()
+                }),
+        )
+        .build()?;
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(
+        value["web_search_options"],
+        serde_json::json!({
+            "search_context_size": "low",
+            "user_location": {"type": "approximate", "approximate": {"country": "GB", "city": "London"}}
+        })
+    );
+    assert!(value["messages"][0].get("annotations").is_none());
+
+    let citation = |start: usize, url: &str| {
+        serde_json::json!({
+            "type": "url_citation",
+            "url_citation": {"start_index": start, "end_index": start + 4, "url": url, "title": "t"}
+        })
+    };
+
+    let rep: ChatCompletionResponse = serde_json::from_value(serde_json::json!({
+        "id": "1", "object": "chat.completion", "created": 1, "model": "m",
+        "choices": [{"index": 0, "finish_reason": "stop", "message": {
+            "role": "assistant", "content": "news",
+            "annotations": [citation(0, "https://a.com"), {"type": "file_citation", "file_id": "f"}]
+        }}]
+    }))?;
+    let annotations = &rep.choices[0].message.annotations;
+    let file_citation = serde_json::json!({"type": "file_citation", "file_id": "f"});
+    assert_eq!(annotations[1], Annotation::Other(file_citation.clone()));
+    assert_eq!(serde_json::to_value(&annotations[1])?, file_citation);
+    assert_eq!(
+        annotations[0].url_citation().map(|c| c.url.as_str()),
+        Some("https://a.com")
+    );
+
+    let mut rep = ChatCompletionResponse::default();
+    for delta in [
+        serde_json::json!({"choices": [{"index": 0, "delta": {"role": "assistant", "content": "news "}}]}),
+        serde_json::json!({"choices": [{"index": 0, "delta": {"content": "more", "annotations": [citation(0, "https://a.com")]}}]}),
+        serde_json::json!({"choices": [{"index": 0, "delta": {"annotations": [citation(0, "https://a.com"), citation(5, "https://b.com")]}, "finish_reason": "stop"}]}),
+    ] {
+        rep.merge_delta(serde_json::from_value(delta)?);
+    }
+    let urls = rep.choices[0]
+        .message
+        .annotations
+        .iter()
+        .filter_map(|a| a.url_citation())
+        .map(|c| (c.start_index, c.url.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(urls, [(0, "https://a.com"), (5, "https://b.com")]);
+    assert_eq!(rep.text().as_deref(), Some("news more"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_message_hash_dedup_ok() -> Result<()> {
+    use std::collections::HashSet;
+
+    let message = || {
+        Message::builder()
+            .with_role(Role::user)
+            .with_content(Content::Containers(vec![
+                "describe this".to_string().into(),
+                ImageUrl::from_url("https://example.com/cat.png").into(),
+            ]))
+            .build()
+    };
+
+    let mut set = HashSet::new();
+    set.insert(message());
+    set.insert(message());
+    assert_eq!(set.len(), 1);
+
+    set.insert(
+        Message::builder()
+            .with_role(Role::assistant)
+            .with_content("a cat")
+            .build(),
+    );
+    assert_eq!(set.len(), 2);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_role_finish_reason_str_ok() -> Result<()> {
+    for role in [Role::system, Role::user, Role::assistant, Role::tool] {
+        assert_eq!(serde_json::to_value(&role)?, role.as_str());
+        assert_eq!(role.to_string().parse::<Role>()?, role);
+    }
+
+    use FinishReason::*;
+    for reason in [stop, length, tool_calls, content_filter, function_call] {
+        assert_eq!(serde_json::to_value(reason)?, reason.as_str());
+        assert_eq!(reason.to_string().parse::<FinishReason>()?, reason);
+    }
+
+    assert!(matches!(
+        "developer".parse::<Role>(),
+        Err(Error::UnknownVariant("Role", ref s)) if s == "developer"
+    ));
+
+    let rep: ChatCompletionResponse = serde_json::from_str(
+        r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#,
+    )?;
+    assert_eq!(rep.choices[0].parsed_finish_reason(), Some(stop));
+
+    for (reason, terminal, truncated) in [
+        (stop, true, false),
+        (length, true, true),
+        (content_filter, true, false),
+        (tool_calls, false, false),
+        (function_call, false, false),
+    ] {
+        let choice: Choice = serde_json::from_value(serde_json::json!({
+            "index": 0,
+            "message": {"role": "assistant"},
+            "finish_reason": reason.as_str()
+        }))?;
+        let parsed = choice.parsed_finish_reason().expect("known reason");
+        assert_eq!(parsed, reason);
+        assert_eq!(parsed.is_terminal(), terminal, "{}", reason);
+        assert_eq!(parsed.was_truncated(), truncated, "{}", reason);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_estimated_prompt_chars_ok() -> Result<()> {
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .with_messages([
+            Message::builder()
+                .with_role(Role::system)
+                .with_content("be brief")
+                .build(),
+            Message::builder()
+                .with_role(Role::user)
+                .with_content(Content::Containers(vec![
+                    "你好".to_string().into(),
+                    ImageUrl::from_url("https://example.com/cat.png").into(),
+                    " cat".to_string().into(),
+                ]))
+                .build(),
+        ])
+        .build()?;
+
+    assert_eq!(req.estimated_prompt_chars(), 8 + 2 + 4);
+    assert!(req.check_within(14).is_ok());
+    assert!(matches!(
+        req.check_within(13),
+        Err(Error::PromptTooLarge(14, 13))
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_request_validate_ok() -> Result<()> {
+    let user = Message::builder()
+        .with_role(Role::user)
+        .with_content("hi")
+        .build();
+    let builder = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(user.clone());
+
+    assert!(builder.clone().build().is_ok());
+
+    let res = builder
+        .clone()
+        .add_message(Message::builder().with_role(Role::assistant).build())
+        .build();
+    assert!(matches!(res, Err(Error::EmptyMessage(1))));
+
+    let res = builder
+        .clone()
+        .add_message(
+            Message::builder()
+                .with_role(Role::tool)
+                .with_content("42")
+                .build(),
+        )
+        .build();
+    assert!(matches!(res, Err(Error::MissingToolCallId(1))));
+
+    let res = builder
+        .clone()
+        .with_stop(Stop::Texts(
+            ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        ))
+        .build();
+    assert!(matches!(res, Err(Error::TooManyStops(5, 4))));
+
+    let res = builder.clone().with_temperature(3.5).build();
+    assert!(matches!(res, Err(Error::InvalidTemperature(t)) if t == 3.5));
+
+    let req = builder
+        .with_temperature(3.5)
+        .with_skip_validation(true)
+        .build()?;
+    assert!(matches!(req.validate(), Err(Error::InvalidTemperature(_))));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_receiver_drop_releases_connection_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start_endless(
+        "text/event-stream",
+        r#"data: {"choices":[{"index":0,"delta":{"content":"la"}}]}
+
+"#
+        .to_string(),
+    )
+    .await;
+
+    let mut req = ChatCompletionRequest::simple("m", "sing forever")?;
+    req.stream = Some(true);
+
+    let mut rx = req.call_stream(&server.client()?, None).await?;
+    assert!(rx.recv().await.is_some());
+    assert_eq!(server.closed(), 0);
+    drop(rx);
+
+    let started = Instant::now();
+    while server.closed() == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "connection still open after receiver dropped"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_cancel_releases_connection_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start_endless(
+        "text/event-stream",
+        r#"data: {"choices":[{"index":0,"delta":{"content":"la"}}]}
+
+"#
+        .to_string(),
+    )
+    .await;
+
+    let mut req = ChatCompletionRequest::simple("m", "sing forever")?;
+    req.stream = Some(true);
+
+    let token = CancellationToken::new();
+    let mut rx = match req
+        .call_cancellable(&server.client()?, None, token.clone())
+        .await?
+    {
+        ChatCompletionResult::Delta(rx) => rx,
+        _ => panic!("expect stream"),
+    };
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    token.cancel();
+
+    // receiver is still alive, the stream task must quit on its own
+    let started = Instant::now();
+    let mut last = None;
+    while let Some(data) = rx.recv().await {
+        last = Some(data);
+    }
+    assert!(matches!(last, Some(Err(Error::Cancelled))));
+    while server.closed() == 0 {
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "connection still open after cancelled"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[tracing_test::traced_test]
+fn test_merge_delta_identity_ok() -> Result<()> {
+    let mut rep = ChatCompletionResponse::default();
+    for delta in [
+        serde_json::json!({"id": "chatcmpl-1", "created": 100, "model": "gpt-4o", "choices": [{"index": 0, "delta": {"role": "assistant", "content": "a"}}]}),
+        serde_json::json!({"id": "chatcmpl-1", "created": 100, "model": "gpt-4o", "choices": [{"index": 0, "delta": {"content": "b"}}]}),
+        serde_json::json!({"id": "chatcmpl-2", "created": 101, "model": "gpt-4o-mini", "choices": [{"index": 0, "delta": {"content": "c"}}]}),
+        serde_json::json!({"id": "chatcmpl-3", "created": 102, "model": "gpt-4o-mini", "choices": [{"index": 0, "delta": {"content": "d"}}]}),
+    ] {
+        rep.merge_delta(serde_json::from_value(delta)?);
+    }
+
+    assert_eq!(rep.id, "chatcmpl-1");
+    assert_eq!(rep.created, 100);
+    assert_eq!(rep.model, "gpt-4o");
+    assert_eq!(rep.text().as_deref(), Some("abcd"));
+    assert!(rep.identity_mismatch);
+    logs_assert(|lines: &[&str]| {
+        let warned = lines
+            .iter()
+            .filter(|l| l.contains("stream chunk with different identity, ignored"))
+            .collect::<Vec<_>>();
+        match warned.as_slice() {
+            [line] if line.contains("WARN") && line.contains("model `gpt-4o` vs `gpt-4o-mini`") => {
+                Ok(())
+            }
+            _ => Err(format!("expect a single warn, got {:?}", warned)),
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_merge_fragmented_tool_calls_ok() -> Result<()> {
+    let delta = |tool_call: serde_json::Value| -> Result<ChatCompletionStreamData> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "choices": [{"index": 0, "delta": {"tool_calls": [tool_call]}}]
+        }))?)
+    };
+
+    let mut rep = ChatCompletionResponse::default();
+    for tool_call in [
+        serde_json::json!({"index": 0, "id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": ""}}),
+        serde_json::json!({"index": 0, "function": {"arguments": "{\"city\":"}}),
+        serde_json::json!({"index": 1, "id": "call_2", "type": "function", "function": {"name": "get_time", "arguments": ""}}),
+        serde_json::json!({"index": 0, "function": {"arguments": "\"beijing\"}"}}),
+        serde_json::json!({"index": 1, "id": "", "function": {"arguments": "{}"}}),
+    ] {
+        rep.merge_delta(delta(tool_call)?);
+    }
+
+    let calls = rep.tool_calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].id.as_deref(), Some("call_1"));
+    assert_eq!(calls[0].typ.as_deref(), Some("function"));
+    assert_eq!(calls[0].function.name.as_deref(), Some("get_weather"));
+    assert_eq!(
+        calls[0].function.arguments.as_deref(),
+        Some(r#"{"city":"beijing"}"#)
+    );
+    assert_eq!(calls[1].id.as_deref(), Some("call_2"));
+    assert_eq!(calls[1].function.arguments.as_deref(), Some("{}"));
+
+    let value = serde_json::to_value(&rep.choices[0].message)?;
+    assert!(value["tool_calls"][0].get("index").is_none());
+
+    // fragments without index continue the last tool call
+    let mut rep = ChatCompletionResponse::default();
+    for tool_call in [
+        serde_json::json!({"id": "call_1", "type": "function", "function": {"name": "add", "arguments": "{\"a\""}}),
+        serde_json::json!({"function": {"arguments": ":1}"}}),
+    ] {
+        rep.merge_delta(delta(tool_call)?);
+    }
+    let calls = rep.tool_calls();
+    assert_eq!(calls[0].id.as_deref(), Some("call_1"));
+    assert_eq!(calls[0].function.arguments.as_deref(), Some(r#"{"a":1}"#));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_force_tool_ok() -> Result<()> {
+    let weather = Function::builder()
+        .with_name("get_weather")
+        .with_description("weather of city")
+        .build()?;
+    let builder = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("weather in beijing?")
+                .build(),
+        );
+
+    let req = builder
+        .clone()
+        .with_tool(weather.clone())
+        .force_tool(weather.clone())
+        .build()?;
+    assert_eq!(req.tools.len(), 1);
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(
+        value["tool_choice"],
+        serde_json::json!({"type": "function", "function": {"name": "get_weather"}})
+    );
+    let back: ChatCompletionRequest = serde_json::from_value(value)?;
+    assert_eq!(
+        back.tool_choice,
+        Some(ToolChoice::Function("get_weather".into()))
+    );
+
+    let req = builder.clone().force_tool(weather.clone()).build()?;
+    assert_eq!(req.tools.len(), 1);
+
+    let req = builder
+        .clone()
+        .with_tool(weather.clone())
+        .with_tool_choice(ToolChoice::Required)
+        .build()?;
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["tool_choice"], "required");
+    let back: ChatCompletionRequest = serde_json::from_value(value)?;
+    assert_eq!(back.tool_choice, Some(ToolChoice::Required));
+
+    let res = builder
+        .clone()
+        .with_tool(weather)
+        .with_tool_choice(ToolChoice::Function("get_time".into()))
+        .build();
+    assert!(matches!(res, Err(Error::UnknownToolChoice(ref n)) if n == "get_time"));
+
+    assert!(serde_json::from_str::<ToolChoice>(r#""sometimes""#).is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_chat_call_cancellable_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let req = ChatCompletionRequest::simple("m", "hi")?;
+
+    let server = MockServer::start_stalled(
+        "application/json",
+        r#"{"id":"1""#.to_string(),
+        Duration::from_secs(5),
+    )
+    .await;
+
+    let token = CancellationToken::new();
+    let token_ = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        token_.cancel();
+    });
+
+    let res = req.call_cancellable(&server.client()?, None, token).await;
+    assert!(matches!(res, Err(Error::Cancelled)));
+
+    let sse = format!(
+        "{}\n\n",
+        r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"}}]}"#
+    );
+    let server = MockServer::start_stalled("text/event-stream", sse, Duration::from_secs(5)).await;
+
+    let token = CancellationToken::new();
+
+    let mut req = req;
+    req.stream = Some(true);
+    let mut rx = match req
+        .call_cancellable(&server.client()?, None, token.clone())
+        .await?
+    {
+        ChatCompletionResult::Delta(rx) => rx,
+        _ => panic!("expect stream"),
+    };
+
+    assert!(matches!(rx.recv().await, Some(Ok(_))));
+    token.cancel();
+    assert!(matches!(rx.recv().await, Some(Err(Error::Cancelled))));
+    assert!(rx.recv().await.is_none());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_content_style_ok() -> Result<()> {
+    let messages = [
+        Message::builder()
+            .with_role(Role::assistant)
+            .with_content(Content::Containers(vec![
+                "hello".to_string().into(),
+                " world".to_string().into(),
+            ]))
+            .build(),
+        Message::builder()
+            .with_role(Role::user)
+            .with_content(Content::Containers(vec![
+                "what is it".to_string().into(),
+                ImageUrl::from_url("https://example.com/cat.png").into(),
+            ]))
+            .build(),
+    ];
+
+    let builder = ChatCompletionRequest::builder()
+        .with_model("m")
+        .with_messages(messages);
+
+    let value = serde_json::to_value(builder.clone().build()?)?;
+    assert_eq!(
+        value["messages"][0]["content"],
+        serde_json::json!([
+            {"type": "text", "text": "hello"},
+            {"type": "text", "text": " world"}
+        ])
+    );
+
+    let value = serde_json::to_value(
+        builder
+            .with_content_style(ContentStyle::PreferText)
+            .build()?,
+    )?;
+    assert_eq!(value["messages"][0]["content"], "hello world");
+    assert_eq!(
+        value["messages"][1]["content"],
+        serde_json::json!([
+            {"type": "text", "text": "what is it"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+        ])
+    );
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "schemars"))]
+#[tokio::test]
+async fn test_structured_output_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    enum Priority {
+        Low,
+        High,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Step {
+        title: String,
+        minutes: u32,
+        priority: Priority,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Plan {
+        goal: String,
+        steps: Vec<Step>,
+        note: Option<String>,
+    }
+
+    let reply = |message: serde_json::Value| {
+        serde_json::json!({
+            "id": "1", "object": "chat.completion", "created": 1, "model": "gpt-4o",
+            "choices": [{"index": 0, "finish_reason": "stop", "message": message}]
+        })
+        .to_string()
+    };
+    let plan =
+        r#"{"goal":"ship","steps":[{"title":"test","minutes":30,"priority":"High"}],"note":null}"#;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "application/json",
+            reply(serde_json::json!({"role": "assistant", "content": plan})),
+        ),
+        (
+            200,
+            "application/json",
+            reply(serde_json::json!({"role": "assistant", "content": null, "refusal": "no plans"})),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("gpt-4o")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("plan my day")
+                .build(),
+        )
+        .with_structured_output::<Plan>("plan")
+        .build()?;
+
+    let value = serde_json::to_value(&req)?;
+    let format = &value["response_format"];
+    assert_eq!(format["type"], "json_schema");
+    assert_eq!(format["json_schema"]["name"], "plan");
+    assert_eq!(format["json_schema"]["strict"], true);
+    let schema = &format["json_schema"]["schema"];
+    assert!(schema.get("$schema").is_none());
+    assert_eq!(schema["additionalProperties"], false);
+    assert_eq!(
+        schema["required"],
+        serde_json::json!(["goal", "note", "steps"])
+    );
+    assert_eq!(schema["$defs"]["Step"]["additionalProperties"], false);
+    assert!(schema["$defs"]["Step"]["properties"]["minutes"]
+        .get("format")
+        .is_none());
+
+    let rep = req.call_once(&client, None).await?;
+    assert_eq!(
+        rep.parse_structured::<Plan>()?,
+        Plan {
+            goal: "ship".into(),
+            steps: vec![Step {
+                title: "test".into(),
+                minutes: 30,
+                priority: Priority::High,
+            }],
+            note: None,
+        }
+    );
+
+    let rep = req.call_once(&client, None).await?;
+    let res = rep.parse_structured::<Plan>();
+    assert!(matches!(res, Err(Error::Refusal(ref r)) if r == "no plans"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_response_format_text_ok() -> Result<()> {
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_reponse_format(ResponseType::text)
+        .build()?;
+
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(
+        value["response_format"],
+        serde_json::json!({"type": "text"})
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_usage_new_ok() -> Result<()> {
+    let usage = ChatComplitionUsage::new(12, 30);
+    assert_eq!(usage.total_tokens, 42);
+    assert!(usage.cached_tokens.is_none());
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_openrouter_extra_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let builder = ChatCompletionRequest::builder()
+        .with_model("openai/gpt-4o")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        );
+
+    let req = builder
+        .clone()
+        .with_openrouter_provider(
+            serde_json::json!({"order": ["OpenAI"], "allow_fallbacks": false}),
+        )
+        .with_openrouter_transforms(["middle-out"])
+        .with_openrouter_route("fallback")
+        .build()?;
+
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["provider"]["order"][0], "OpenAI");
+    assert_eq!(value["transforms"], serde_json::json!(["middle-out"]));
+    assert_eq!(value["route"], "fallback");
+    assert!(value.get("extra").is_none());
+
+    let res = builder.with_extra("model", "other").build();
+    assert!(matches!(res, Err(Error::ChatCompletionRequestBuild)));
+
+    for (json, message) in [
+        (OPENROUTER_ERROR_STRING_JSON, "No auth credentials found"),
+        (OPENROUTER_ERROR_OBJECT_JSON, "Provider returned error"),
+    ] {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let e = ApiErrorResponse::parse(&value).expect("api error");
+        assert_eq!(e.error.message(), message);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+#[tracing_test::traced_test]
+fn test_request_log_redacted_ok() -> Result<()> {
+    let url = format!("data:image/png;base64,{}", "A".repeat(4096));
+    let message = Message::builder()
+        .with_role(Role::user)
+        .add_text("what is in the image")
+        .add_image_url(url.clone())
+        .build();
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("step-1v-8k")
+        .add_message(message)
+        .build()?;
+
+    assert!(logs_contain("what is in the image"));
+    assert!(logs_contain(
+        "data:image/png;base64,AAAAAAAAAA...(4118 bytes)"
+    ));
+    assert!(!logs_contain(&url));
+    assert!(crate::json::to_string(&req)?.contains(&url));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_request_extra_header_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#;
+    let server = MockServer::start(vec![(200, "application/json", chat.to_string())]).await;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("m")
+        .add_message(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content("hi")
+                .build(),
+        )
+        .with_header(
+            HeaderName::from_static("openai-beta"),
+            HeaderValue::from_static("assistants=v2"),
+        )
+        .build()?;
+    assert!(!crate::json::to_string(&req)?.contains("assistants=v2"));
+
+    req.call_once(&server.client()?, None).await?;
+    assert_eq!(
+        server.header(0, "OpenAI-Beta").as_deref(),
+        Some("assistants=v2")
+    );
+    assert_eq!(
+        server.header(0, "content-type").as_deref(),
+        Some("application/json")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_full_message_chunks_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "text/event-stream",
+            FASTCHAT_CHAT_STREAM_SSE.to_string(),
+        ),
+        (
+            200,
+            "text/event-stream",
+            OLLAMA_PROXY_CHAT_STREAM_SSE.to_string(),
+        ),
+        (
+            200,
+            "text/event-stream",
+            FASTCHAT_CHAT_STREAM_REPEAT_SSE.to_string(),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "hi")?;
+    req.stream = Some(true);
+
+    let mut merged = vec![];
+    for _ in 0..3 {
+        let mut rep = ChatCompletionResponse::default();
+        let mut rx = req.call_stream(&client, None).await?;
+        while let Some(data) = rx.recv().await {
+            rep.merge_delta(data?);
+        }
+        merged.push(rep);
+    }
+
+    // `message` used as delta name, every chunk is appended
+    assert_eq!(merged[0].text().as_deref(), Some("Hello world"));
+    assert_eq!(merged[0].choices[0].message.role, Some(Role::assistant));
+
+    // final chunk repeats the whole message, nothing is duplicated
+    let message = &merged[1].choices[0].message;
+    assert_eq!(merged[1].text().as_deref(), Some("Hello world"));
+    assert_eq!(message.tool_calls.len(), 1);
+    assert_eq!(
+        message.tool_calls[0].function.arguments.as_deref(),
+        Some(r#"{"city":"Paris"}"#)
+    );
+    assert_eq!(
+        merged[1].choices[0].finish_reason.as_deref(),
+        Some("tool_calls")
+    );
+
+    // increments under `message` which repeat merged text are appended
+    assert_eq!(merged[2].text().as_deref(), Some("haha  ha!"));
+    // so is a final increment which extends merged text
+    assert_eq!(
+        merged[2].choices[1]
+            .message
+            .content
+            .as_ref()
+            .map(|c| c.to_text())
+            .as_deref(),
+        Some("haha")
+    );
+
+    let chunk: ChatCompletionStreamData =
+        serde_json::from_str(r#"{"choices":[{"index":0,"message":{"content":"a"}}]}"#)?;
+    assert!(!chunk.choices[0].full_message);
+    assert!(serde_json::to_string(&chunk)?.contains(r#""delta":{"content":"a"}"#));
+
+    let chunk: ChatCompletionStreamData = serde_json::from_str(
+        r#"{"choices":[{"index":0,"message":{"content":"a"},"finish_reason":"stop"}]}"#,
+    )?;
+    assert!(!chunk.choices[0].full_message);
+
+    let chunk: ChatCompletionStreamData = serde_json::from_str(
+        r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"a"},"finish_reason":"stop"}]}"#,
+    )?;
+    assert!(chunk.choices[0].full_message);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_trim_messages_ok() -> Result<()> {
+    let messages = vec![
+        Message::builder()
+            .with_role(Role::system)
+            .with_content("  be brief\n")
+            .build(),
+        Message::builder()
+            .with_role(Role::user)
+            .add_text("\n what is in the image ")
+            .add_image_url("https://example.com/ a.png ")
+            .build(),
+    ];
+    let builder = || {
+        ChatCompletionRequest::builder()
+            .with_model("m")
+            .with_messages(messages.clone())
+    };
+
+    let req = builder().build()?;
+    assert_eq!(req.messages, messages);
+
+    let req = builder().with_trim_messages(true).build()?;
+    assert_eq!(
+        req.messages[0].content,
+        Some(Content::Text("be brief".to_string()))
+    );
+    let Some(Content::Containers(parts)) = req.messages[1].content.as_ref() else {
+        panic!("expect content parts");
+    };
+    assert!(
+        matches!(&parts[0], ContentContainer::Text { text, .. } if text == "what is in the image")
+    );
+    assert!(
+        matches!(&parts[1], ContentContainer::Image { image_url, .. } if image_url.url == "https://example.com/ a.png ")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_reinjected_reasoning_message_ok() -> Result<()> {
+    let rep: ChatCompletionResponse = serde_json::from_value(serde_json::json!({
+        "id": "1",
+        "object": "chat.completion",
+        "created": 1,
+        "model": "deepseek-reasoner",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": "42", "reasoning_content": "think", "prefix": true},
+            "finish_reason": "stop"
+        }]
+    }))?;
+    let builder = || {
+        ChatCompletionRequest::builder()
+            .with_model("deepseek-reasoner")
+            .with_messages(rep.clone().into_messages())
+            .add_message(
+                Message::builder()
+                    .with_role(Role::user)
+                    .with_content("why")
+                    .build(),
+            )
+    };
+
+    let body = serde_json::to_value(builder().build()?)?;
+    let message = &body["messages"][0];
+    assert_eq!(message["content"], "42");
+    assert!(message.get("reasoning_content").is_none());
+    assert_eq!(message["prefix"], true);
+
+    let body = serde_json::to_value(builder().with_keep_response_fields(true).build()?)?;
+    assert_eq!(body["messages"][0]["reasoning_content"], "think");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_request_accept_header_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#;
+    let sse = "data: [DONE]\n\n".to_string();
+    let server = MockServer::start(vec![
+        (200, "application/json", chat.to_string()),
+        (200, "text/event-stream", sse),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "hi")?;
+    req.call_once(&client, None).await?;
+    assert_eq!(
+        server.header(0, "accept").as_deref(),
+        Some("application/json")
+    );
+
+    req.stream = Some(true);
+    let mut rx = req.call_stream(&client, None).await?;
+    while rx.recv().await.is_some() {}
+    assert_eq!(
+        server.header(1, "accept").as_deref(),
+        Some("text/event-stream")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_azure_content_filter_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let rep: ChatCompletionResponse = serde_json::from_str(AZURE_CHAT_CONTENT_FILTER_JSON)?;
+    assert!(rep.is_content_filtered());
+
+    let choice = &rep.choices[0];
+    assert!(choice.is_content_filtered());
+    let results = choice.content_filter_results.as_ref().expect("results");
+    assert!(results["violence"].filtered);
+    assert_eq!(results["violence"].severity.as_deref(), Some("medium"));
+    assert_eq!(results["protected_material_code"].detected, Some(true));
+    assert!(results["protected_material_code"]
+        .extra
+        .contains_key("citation"));
+    assert!(results["custom_blocklists"].extra.contains_key("details"));
+    assert_eq!(results["error"].extra["code"], "content_filter_error");
+    assert_eq!(choice.logprobs, None);
+    assert!(choice.extra.is_empty());
+
+    let prompt = &rep.prompt_filter_results.as_ref().expect("prompt")[0];
+    assert_eq!(
+        prompt.content_filter_results["jailbreak"].detected,
+        Some(false)
+    );
+
+    let chunks = [
+        serde_json::json!({"id": "", "object": "", "created": 0, "model": "", "choices": [], "prompt_filter_results": [{"prompt_index": 0, "content_filter_results": {"hate": {"filtered": false, "severity": "safe"}}}]}),
+        serde_json::json!({"id": "chatcmpl-1", "created": 1, "model": "gpt-4o", "choices": [{"index": 0, "delta": {"role": "assistant", "content": "I"}, "content_filter_results": {"violence": {"filtered": true, "severity": "medium"}}}]}),
+        serde_json::json!({"id": "chatcmpl-1", "created": 1, "model": "gpt-4o", "choices": [{"index": 0, "delta": {"content": " can"}, "content_filter_results": {"violence": {"filtered": false, "severity": "safe"}, "hate": {"filtered": false, "severity": "safe"}}}]}),
+        serde_json::json!({"id": "chatcmpl-1", "created": 1, "model": "gpt-4o", "choices": [{"index": 0, "delta": {}, "finish_reason": "content_filter"}]}),
+    ];
+
+    let mut merged = ChatCompletionResponse::default();
+    for chunk in chunks {
+        merged.merge_delta(serde_json::from_value(chunk)?);
+    }
+    let results = merged.choices[0]
+        .content_filter_results
+        .as_ref()
+        .expect("results");
+    assert!(results["violence"].filtered);
+    assert_eq!(results["hate"].severity.as_deref(), Some("safe"));
+    assert_eq!(merged.prompt_filter_results.as_ref().map(Vec::len), Some(1));
+    assert!(merged.is_content_filtered());
+    assert_eq!(merged.text().as_deref(), Some("I can"));
+    assert_eq!(merged.id, "chatcmpl-1");
+    assert_eq!(merged.model, "gpt-4o");
+
+    // older api versions send `custom_blocklists` as array
+    let raw: serde_json::Value = serde_json::from_str(AZURE_CHAT_CONTENT_FILTER_LEGACY_JSON)?;
+    let rep: ChatCompletionResponse = serde_json::from_value(raw.clone())?;
+    let results = rep.choices[0]
+        .content_filter_results
+        .as_ref()
+        .expect("results");
+    assert!(results["custom_blocklists"]
+        .other
+        .as_ref()
+        .is_some_and(|v| v.is_array()));
+    assert!(!results["custom_blocklists"].filtered);
+    assert!(results["hate"].other.is_none());
+    assert!(!rep.is_content_filtered());
+    assert_eq!(serde_json::to_value(&rep)?, raw);
+
+    // a tripped legacy blocklist entry marks the category filtered
+    let raw: serde_json::Value = serde_json::from_str(AZURE_CHAT_CONTENT_FILTER_BLOCKLIST_JSON)?;
+    let rep: ChatCompletionResponse = serde_json::from_value(raw.clone())?;
+    let choice = &rep.choices[0];
+    assert!(choice.content_filter_results.as_ref().expect("results")["custom_blocklists"].filtered);
+    assert!(choice.is_content_filtered());
+    assert!(rep.is_content_filtered());
+    assert_eq!(serde_json::to_value(&rep)?, raw);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_stream_logprobs_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let server = MockServer::start(vec![(
+        200,
+        "text/event-stream",
+        OPENAI_CHAT_LOGPROBS_STREAM_SSE.to_string(),
+    )])
+    .await;
+    let client = server.client()?;
+
+    let req = ChatCompletionRequest::builder()
+        .with_model("gpt-4o-mini")
+        .with_messages([Message::builder()
+            .with_role(Role::user)
+            .with_content("hi")
+            .build()])
+        .with_stream(true)
+        .with_top_logprobs(2)
+        .build()?;
+    let body = serde_json::to_value(&req)?;
+    assert_eq!(body["logprobs"], true);
+    assert_eq!(body["top_logprobs"], 2);
+
+    let mut streamed = ChatCompletionResponse::default();
+    let mut rx = req.call_stream(&client, None).await?;
+    while let Some(data) = rx.recv().await {
+        streamed.merge_delta(data?);
+    }
+
+    let full: ChatCompletionResponse = serde_json::from_str(OPENAI_CHAT_LOGPROBS_JSON)?;
+    let tokens = |rep: &ChatCompletionResponse| {
+        rep.choices[0]
+            .logprobs
+            .as_ref()
+            .and_then(|l| l.content.clone())
+            .unwrap_or_default()
+    };
+
+    assert_eq!(streamed.text().as_deref(), Some("Hello world!"));
+    assert_eq!(tokens(&streamed).len(), 3);
+    assert_eq!(tokens(&streamed), tokens(&full));
+    assert_eq!(tokens(&streamed)[1].top_logprobs[1].token, " there");
+
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use serde_json::{Map, Value};
+use serde_with::skip_serializing_none;
+
+use crate::error::*;
+use crate::proto::chat::*;
+use crate::proto::tool::ToolCall;
+
+/// multi-turn conversation state, persist it with `to_json` and resume with `from_json`
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Conversation {
+    pub model: String,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub tools: Vec<ToolCall>,
+    /// fields unknown to this version, kept as is for forward compatibility
+    #[serde(flatten, default)]
+    pub extra: Map<String, Value>,
+}
+
+impl Conversation {
+    /// version of persisted envelope
+    pub const VERSION: u64 = 1;
+
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            messages: vec![],
+            tools: vec![],
+            extra: Map::new(),
+        }
+    }
+
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = impl Into<ToolCall>>) -> Self {
+        self.tools.extend(tools.into_iter().map(|t| t.into()));
+        self
+    }
+
+    pub fn push(&mut self, message: Message) -> &mut Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn push_system(&mut self, content: impl Into<Content>) -> &mut Self {
+        self.push(
+            Message::builder()
+                .with_role(Role::system)
+                .with_content(content)
+                .build(),
+        )
+    }
+
+    pub fn push_user(&mut self, content: impl Into<Content>) -> &mut Self {
+        self.push(
+            Message::builder()
+                .with_role(Role::user)
+                .with_content(content)
+                .build(),
+        )
+    }
+
+    /// tool calls of last assistant message which have no tool result yet
+    pub fn pending_tool_calls(&self) -> Vec<&ToolCall> {
+        let Some(pos) = self
+            .messages
+            .iter()
+            .rposition(|m| m.role == Some(Role::assistant))
+        else {
+            return vec![];
+        };
+
+        let answered = self.messages[pos + 1..]
+            .iter()
+            .filter(|m| m.role == Some(Role::tool))
+            .filter_map(|m| m.tool_call_id.as_deref())
+            .collect::<Vec<_>>();
+
+        self.messages[pos]
+            .tool_calls
+            .iter()
+            .filter(|t| !t.id.as_deref().is_some_and(|id| answered.contains(&id)))
+            .collect()
+    }
+
+    /// branch a new conversation sharing history so far
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    /// request for next turn
+    pub fn to_request(&self) -> Result<ChatCompletionRequest> {
+        ChatCompletionRequest::builder()
+            .with_model(&self.model)
+            .with_messages(self.messages.iter().cloned())
+            .with_tools(self.tools.iter().cloned())
+            .build()
+    }
+
+    /// serialize into versioned json envelope
+    pub fn to_json(&self) -> Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        if let Value::Object(map) = &mut value {
+            map.insert("version".into(), Self::VERSION.into());
+        }
+        Ok(crate::json::to_string(&value)?)
+    }
+
+    /// restore from json envelope, newer envelope version is rejected
+    pub fn from_json(json: impl AsRef<str>) -> Result<Self> {
+        let mut value: Value = crate::json::from_str(json.as_ref())?;
+        let version = value
+            .as_object_mut()
+            .and_then(|m| m.remove("version"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(Self::VERSION);
+        if version > Self::VERSION {
+            tracing::error!(%version, "unsupported conversation version");
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_conversation_resume_tool_call_ok() -> Result<()> {
+    use crate::proto::tool::*;
+
+    let function = Function::builder()
+        .with_name("add_number")
+        .with_description("add two numbers")
+        .with_parameters(
+            Parameters::builder()
+                .add_property(
+                    "a",
+                    ParameterProperty::builder()
+                        .with_description("number a")
+                        .with_type(ParameterType::number)
+                        .build()?,
+                    true,
+                )
+                .build()?,
+        )
+        .build()?;
+
+    let mut conversation = Conversation::new("m").with_tools([function]);
+    conversation
+        .push_system("you are a calculator")
+        .push_user("1 + 2 = ?")
+        .push(
+            Message::builder()
+                .with_role(Role::assistant)
+                .add_tool_call(ToolCall {
+                    index: None,
+                    id: Some("call_0".into()),
+                    typ: Some("function".into()),
+                    function: Function {
+                        name: Some("add_number".into()),
+                        description: None,
+                        parameters: None,
+                        arguments: Some(r#"{"a":1,"b":2}"#.into()),
+                    },
+                })
+                .build(),
+        );
+    conversation
+        .extra
+        .insert("labels".into(), serde_json::json!(["demo"]));
+
+    let json = conversation.to_json()?;
+    assert_eq!(serde_json::from_str::<Value>(&json)?["version"], 1);
+
+    let mut resumed = Conversation::from_json(&json)?;
+    assert_eq!(resumed, conversation);
+    assert_eq!(resumed.pending_tool_calls().len(), 1);
+
+    let mut branch = resumed.fork();
+
+    resumed.push(
+        Message::builder()
+            .with_role(Role::tool)
+            .with_tool_call_id("call_0")
+            .with_content("3")
+            .build(),
+    );
+    assert!(resumed.pending_tool_calls().is_empty());
+    assert_eq!(branch.pending_tool_calls().len(), 1);
+
+    let req = resumed.to_request()?;
+    req.validate()?;
+    assert_eq!(req.messages.len(), 4);
+    assert_eq!(req.tools.len(), 1);
+
+    branch.push(
+        Message::builder()
+            .with_role(Role::tool)
+            .with_tool_call_id("call_0")
+            .with_content("4")
+            .build(),
+    );
+    assert_ne!(branch.messages, resumed.messages);
+
+    let res = Conversation::from_json(r#"{"version":99,"model":"m"}"#);
+    assert!(matches!(res, Err(Error::UnsupportedVersion(99))));
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use http::Method;
+use serde_with::skip_serializing_none;
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::error::*;
+use crate::proto::chat::ChatComplitionUsage;
+
+/// embed each string of `input` into a vector
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+    /// shorten vectors, only supported by newer models
+    pub dimensions: Option<u32>,
+    pub user: Option<String>,
+}
+
+impl EmbeddingRequest {
+    pub fn new(
+        model: impl Into<String>,
+        input: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            input: input.into_iter().map(|i| i.into()).collect(),
+            dimensions: None,
+            user: None,
+        }
+    }
+
+    /// use `Client::default_model`, `Error::NoDefaultModel` if not configured
+    pub fn new_for(
+        client: &Client,
+        input: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self> {
+        let model = client.default_model().ok_or(Error::NoDefaultModel)?;
+        Ok(Self::new(model, input))
+    }
+
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub async fn call(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<EmbeddingResponse> {
+        let rep = client
+            .call_json::<_, EmbeddingResponse>(Method::POST, "embeddings", self, timeout)
+            .await;
+
+        // embeddings have no completion, prompt tokens are counted
+        let tracker = client.usage_tracker();
+        match &rep {
+            Ok(r) => tracker.record(
+                r.usage
+                    .map(|u| ChatComplitionUsage::new(u.prompt_tokens, 0))
+                    .as_ref(),
+            ),
+            Err(_) => tracker.record_error(),
+        }
+        rep
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EmbeddingResponse {
+    pub object: Option<String>,
+    pub model: Option<String>,
+    pub data: Vec<Embedding>,
+    pub usage: Option<EmbeddingUsage>,
+}
+
+impl EmbeddingResponse {
+    /// vectors in the order of `EmbeddingRequest::input`
+    pub fn into_vectors(mut self) -> Vec<Vec<f32>> {
+        self.data.sort_by_key(|e| e.index);
+        self.data.into_iter().map(|e| e.embedding).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Embedding {
+    /// index into `EmbeddingRequest::input`
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: u64,
+    pub total_tokens: u64,
+}
@@ -1,16 +1,18 @@
+use futures::{Stream, StreamExt};
 use http::Method;
-use reqwest::{
-    multipart::{Form, Part},
-    Body,
-};
-use serde_json::Value;
+use serde_with::skip_serializing_none;
 use smart_default::SmartDefault;
 use std::{path::PathBuf, time::Duration};
-use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use url::Url;
 
-use crate::{client::Client, error::*};
+use crate::{
+    client::{cancellable, read_error, read_json, Client, Payload},
+    error::*,
+    proto::{form::FormBuilder, sys::DeletionStatus},
+};
 
 pub struct FileContentRequest {
     pub id: String,
@@ -27,43 +29,51 @@ impl FileContentRequest {
         timeout: Option<Duration>,
     ) -> Result<FileContentResponse> {
         let rep = client
-            .call_impl(
+            .request(
                 Method::GET,
                 &format!("files/{}/content", self.id),
                 vec![],
-                None,
-                None,
+                Payload::None,
                 timeout,
             )
             .await?;
 
-        let status = rep.status();
-
-        let rep: Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
+        let rep: FileContentResponse = read_json(rep).await?;
 
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_success() {
-                trace!(%l, "REP");
-            } else {
-                error!(%l, "REP");
-            }
+        for l in rep.content.lines() {
+            trace!(%l, "REP");
         }
 
-        if !status.is_success() {
-            return Err(Error::ApiError(status.as_u16()));
-        }
+        Ok(rep)
+    }
+}
 
-        let rep: FileContentResponse = serde_json::from_value(rep)?;
+impl FileContentRequest {
+    /// raw bytes of file content as a stream, without buffering the whole body
+    pub async fn byte_stream(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let rep = client
+            .request(
+                Method::GET,
+                &format!("files/{}/content", self.id),
+                vec![],
+                Payload::None,
+                timeout,
+            )
+            .await?;
 
-        for l in rep.content.lines() {
-            trace!(%l, "REP");
+        if !rep.status().is_success() {
+            return Err(read_error(rep).await);
         }
 
-        Ok(rep)
+        Ok(rep.bytes_stream().map(|r| r.map_err(Error::from)))
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct FileContentResponse {
     pub file_type: String,
     pub filename: String,
@@ -82,35 +92,18 @@ impl FileDeleteRequest {
         Self { id: id.into() }
     }
 
-    pub async fn call(&self, client: &Client, timeout: Option<Duration>) -> Result<()> {
+    pub async fn call(&self, client: &Client, timeout: Option<Duration>) -> Result<DeletionStatus> {
         let rep = client
-            .call_impl(
+            .request(
                 Method::DELETE,
                 &format!("files/{}", self.id),
                 vec![],
-                None,
-                None,
+                Payload::None,
                 timeout,
             )
             .await?;
 
-        let status = rep.status();
-
-        let rep: Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
-
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_success() {
-                trace!(%l, "REP");
-            } else {
-                error!(%l, "REP");
-            }
-        }
-
-        if !status.is_success() {
-            return Err(Error::ApiError(status.as_u16()));
-        }
-
-        Ok(())
+        read_json(rep).await
     }
 }
 
@@ -129,66 +122,122 @@ impl FileGetRequest {
         timeout: Option<Duration>,
     ) -> Result<FileUploadResponse> {
         let rep = client
-            .call_impl(
+            .request(
                 Method::GET,
                 &format!("files/{}", self.id),
                 vec![],
-                None,
-                None,
+                Payload::None,
                 timeout,
             )
             .await?;
 
-        let status = rep.status();
+        read_json(rep).await
+    }
+}
 
-        let rep: Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
+#[derive(Debug, Clone, Default)]
+pub struct FileListRequest {
+    pub purpose: Option<FilePurpose>,
+    /// cursor, id of last file of previous page
+    pub after: Option<String>,
+    pub limit: Option<u32>,
+}
 
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_success() {
-                trace!(%l, "REP");
-            } else {
-                error!(%l, "REP");
-                return Err(Error::ApiError(status.as_u16()));
-            }
-        }
+impl FileListRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        Ok(serde_json::from_value(rep)?)
+    /// only list files of `purpose`
+    pub fn with_purpose(mut self, purpose: FilePurpose) -> Self {
+        self.purpose = Some(purpose);
+        self
     }
-}
 
-pub struct FileListRequest;
+    pub fn with_after(mut self, after: impl Into<String>) -> Self {
+        self.after = Some(after.into());
+        self
+    }
 
-impl FileListRequest {
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn uri(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(purpose) = self.purpose.as_ref() {
+            query.append_pair("purpose", purpose.as_str());
+        }
+        if let Some(after) = self.after.as_ref() {
+            query.append_pair("after", after);
+        }
+        if let Some(limit) = self.limit {
+            query.append_pair("limit", &limit.to_string());
+        }
+        match query.finish() {
+            query if query.is_empty() => "files".to_string(),
+            query => format!("files?{}", query),
+        }
+    }
+
+    /// fetch a single page
     pub async fn call(
         &self,
         client: &Client,
         timeout: Option<Duration>,
     ) -> Result<FileListResponse> {
         let rep = client
-            .call_impl(Method::GET, "files", vec![], None, None, timeout)
+            .request(Method::GET, self.uri(), vec![], Payload::None, timeout)
             .await?;
 
-        let status = rep.status();
-
-        let rep: Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
+        read_json(rep).await
+    }
 
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_success() {
-                trace!(%l, "REP");
-            } else {
-                error!(%l, "REP");
-                return Err(Error::ApiError(status.as_u16()));
+    /// follow `has_more` from `after` page by page, return every file, `timeout` applies per page
+    pub async fn all(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<FileUploadResponse>> {
+        let mut req = self.clone();
+        let mut files = vec![];
+
+        loop {
+            let page = req.call(client, timeout).await?;
+            let after = page.next_after().map(|id| id.to_string());
+            files.extend(page.data);
+
+            match after {
+                Some(after) => {
+                    trace!(%after, count = %files.len(), "fetch next page of files");
+                    req.after = Some(after);
+                }
+                None => break,
             }
         }
 
-        Ok(serde_json::from_value(rep)?)
+        Ok(files)
     }
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
 pub struct FileListResponse {
     pub object: String,
     pub data: Vec<FileUploadResponse>,
+    /// kimi doesn't paginate and never sends it
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+impl FileListResponse {
+    /// id of last file as `after` of next page, `None` if no more
+    pub fn next_after(&self) -> Option<&str> {
+        self.data
+            .last()
+            .filter(|_| self.has_more)
+            .map(|f| f.id.as_str())
+    }
 }
 
 impl From<PathBuf> for FileSource {
@@ -214,52 +263,126 @@ pub enum FileSource {
     },
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub enum FilePurpose {
     #[default]
     #[serde(rename = "file-extract")]
     Extract,
+    #[serde(rename = "batch")]
+    Batch,
+    #[serde(rename = "fine-tune")]
+    FineTune,
+    #[serde(rename = "assistants")]
+    Assistants,
+    #[serde(rename = "vision")]
+    Vision,
+    #[serde(rename = "user_data")]
+    UserData,
+    #[serde(rename = "evals")]
+    Evals,
+    /// output purposes, set by api on generated files
+    #[serde(rename = "assistants_output")]
+    AssistantsOutput,
+    #[serde(rename = "batch_output")]
+    BatchOutput,
+    #[serde(rename = "fine-tune-results")]
+    FineTuneResults,
+}
+
+impl FilePurpose {
+    /// purposes only accept jsonl files
+    pub fn requires_jsonl(&self) -> bool {
+        matches!(self, FilePurpose::Batch | FilePurpose::FineTune)
+    }
 }
 
 impl From<FilePurpose> for String {
     fn from(value: FilePurpose) -> Self {
-        match value {
-            FilePurpose::Extract => "file-extract".to_string(),
-        }
+        String::from(&value)
     }
 }
 
+impl_wire_str!(
+    FilePurpose,
+    Extract => "file-extract",
+    Batch => "batch",
+    FineTune => "fine-tune",
+    Assistants => "assistants",
+    Vision => "vision",
+    UserData => "user_data",
+    Evals => "evals",
+    AssistantsOutput => "assistants_output",
+    BatchOutput => "batch_output",
+    FineTuneResults => "fine-tune-results",
+);
+
 impl From<&FilePurpose> for String {
     fn from(value: &FilePurpose) -> Self {
-        match value {
-            FilePurpose::Extract => "file-extract".to_string(),
-        }
+        value.as_str().to_string()
     }
 }
 
 pub struct FileUploadRequest {
     pub source: FileSource,
     pub purpose: FilePurpose,
+    /// file is deleted automatically after seconds since created
+    pub expires_after_seconds: Option<u64>,
+    /// used when `call` is given no timeout
+    pub timeout: Option<Duration>,
 }
 
 impl FileUploadRequest {
+    /// text fields of multipart form, file part excluded
+    pub(crate) fn form_fields(&self) -> FormBuilder {
+        FormBuilder::new()
+            .text("purpose", &self.purpose)
+            .text_opt(
+                "expires_after[anchor]",
+                self.expires_after_seconds.map(|_| "created_at"),
+            )
+            .text_opt("expires_after[seconds]", self.expires_after_seconds)
+    }
+
+    /// check file extension against purpose before uploading, e.g. `batch` requires `.jsonl`
+    pub fn validate(&self) -> Result<()> {
+        if !self.purpose.requires_jsonl() {
+            return Ok(());
+        }
+
+        let path = match &self.source {
+            FileSource::Local(local_path) => local_path.clone(),
+            FileSource::Remote { url, .. } => PathBuf::from(url.path()),
+        };
+
+        let extension = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_lowercase())
+            .unwrap_or_default();
+
+        if extension != "jsonl" {
+            error!(purpose = %self.purpose, ?path, "file purpose requires jsonl");
+            return Err(Error::FilePurposeMismatch(
+                self.purpose.to_string(),
+                path.display().to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub async fn call(
         &self,
         client: &Client,
         timeout: Option<Duration>,
     ) -> Result<FileUploadResponse> {
-        let part = match &self.source {
-            FileSource::Local(local_path) => {
-                let file_name = local_path
-                    .file_name()
-                    .and_then(|s| s.to_str())
-                    .map(|s| s.to_string())
-                    .ok_or(Error::NoFileName)?;
-                let file = tokio::fs::File::open(local_path).await?;
-                let stream = FramedRead::new(file, BytesCodec::new());
-                let file_body = Body::wrap_stream(stream);
-                Part::stream(file_body).file_name(file_name)
-            }
+        self.validate()?;
+
+        let timeout = timeout.or(self.timeout);
+
+        // remote file is fetched once, the form is rebuilt from it on every attempt
+        let file = match &self.source {
+            FileSource::Local(local_path) => UploadFile::Local(local_path),
             FileSource::Remote {
                 url,
                 trust_all_certification,
@@ -279,41 +402,53 @@ impl FileUploadRequest {
                     .send()
                     .await?;
 
-                let bytes = rep.bytes().await?;
-                Part::stream(bytes).file_name(filename)
+                UploadFile::Remote(filename, rep.bytes().await?)
             }
         };
 
-        let purpose = String::from(&self.purpose);
+        let purpose = self.purpose.as_str();
 
         info!(?purpose);
 
-        let form = Form::new()
-            .text("purpose", String::from(&self.purpose))
-            .part("file", part);
-
         let rep = client
-            .call_impl(Method::POST, "files", vec![], None, Some(form), timeout)
+            .request_multipart("files", timeout, || async {
+                let form = match &file {
+                    UploadFile::Local(local_path) => {
+                        self.form_fields()
+                            .file_from_path("file", local_path)
+                            .await?
+                    }
+                    UploadFile::Remote(filename, bytes) => self.form_fields().file_from_bytes(
+                        "file",
+                        filename.clone(),
+                        bytes.clone(),
+                    )?,
+                };
+                Ok(form)
+            })
             .await?;
 
-        let status = rep.status();
-
-        let rep: Value = serde_json::from_slice(rep.bytes().await?.as_ref())?;
-
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_success() {
-                trace!(%l, "REP");
-            } else {
-                error!(%l, "REP");
-                return Err(Error::ApiError(status.as_u16()));
-            }
-        }
-
-        Ok(serde_json::from_value(rep)?)
+        read_json(rep).await
     }
 }
 
+/// file part of an upload, remote file is held in memory so retries can replay it
+enum UploadFile<'a> {
+    Local(&'a PathBuf),
+    Remote(String, Bytes),
+}
+
 impl FileUploadRequest {
+    /// same as `call`, but abort with `Error::Cancelled` once `token` fired
+    pub async fn call_cancellable(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+        token: CancellationToken,
+    ) -> Result<FileUploadResponse> {
+        cancellable(&token, self.call(client, timeout)).await
+    }
+
     pub fn builder() -> FileUploadRequestBuilder {
         FileUploadRequestBuilder::default()
     }
@@ -323,6 +458,8 @@ impl FileUploadRequest {
 pub struct FileUploadRequestBuilder {
     source: Option<FileSource>,
     purpose: FilePurpose,
+    expires_after_seconds: Option<u64>,
+    timeout: Option<Duration>,
 }
 
 impl FileUploadRequestBuilder {
@@ -336,24 +473,163 @@ impl FileUploadRequestBuilder {
         self
     }
 
+    pub fn with_expires_after_seconds(mut self, seconds: u64) -> Self {
+        self.expires_after_seconds = Some(seconds);
+        self
+    }
+
+    /// default timeout of `call`, explicit timeout of `call` still wins
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn build(self) -> Result<FileUploadRequest> {
-        Ok(FileUploadRequest {
+        let req = FileUploadRequest {
             source: self.source.ok_or(Error::FileRequestBuild)?,
             purpose: self.purpose,
-        })
+            expires_after_seconds: self.expires_after_seconds,
+            timeout: self.timeout,
+        };
+        req.validate()?;
+        Ok(req)
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct FileUploadResponse {
     pub id: String,
     pub object: String,
     pub bytes: usize,
     pub created_at: u64,
+    /// unix timestamp the file is deleted at, openai only
+    #[serde(default)]
+    pub expires_at: Option<u64>,
     pub filename: String,
     pub purpose: FilePurpose,
-    pub status: String,
-    pub status_details: String,
+    /// deprecated by openai, still sent by kimi
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub status_details: Option<String>,
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_list_providers_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let rep: FileListResponse = serde_json::from_str(OPENAI_FILES_JSON)?;
+    assert_eq!(rep.data.len(), 5);
+    assert_eq!(rep.data[0].expires_at, Some(1614282185));
+    assert_eq!(rep.data[0].purpose, FilePurpose::Assistants);
+    assert_eq!(rep.data[0].status, None);
+    assert_eq!(rep.data[1].status.as_deref(), Some("processed"));
+    assert_eq!(rep.data[1].status_details, None);
+    assert_eq!(rep.data[2].purpose, FilePurpose::BatchOutput);
+
+    let rep: FileListResponse = serde_json::from_str(KIMI_FILES_JSON)?;
+    assert_eq!(rep.data[0].purpose, FilePurpose::Extract);
+    assert_eq!(rep.data[0].status.as_deref(), Some("ok"));
+    assert_eq!(rep.data[0].status_details.as_deref(), Some(""));
+    assert_eq!(rep.data[0].expires_at, None);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_serde_round_trip_ok() -> Result<()> {
+    use crate::tests::*;
+
+    for json in [OPENAI_FILES_JSON, KIMI_FILES_JSON] {
+        let lhs: FileListResponse = serde_json::from_str(json)?;
+        let rhs: FileListResponse = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+        assert_eq!(lhs, rhs);
+
+        for file in lhs.data {
+            let rhs: FileUploadResponse = serde_json::from_str(&serde_json::to_string(&file)?)?;
+            assert_eq!(file, rhs);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_delete_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(
+        200,
+        "application/json",
+        r#"{"id":"file-1","object":"file","deleted":true}"#.to_string(),
+    )])
+    .await;
+
+    let rep = FileDeleteRequest::new("file-1")
+        .call(&server.client()?, None)
+        .await?;
+    assert!(rep.deleted);
+    assert_eq!(rep.id, "file-1");
+    assert_eq!(server.paths(), ["/v1/files/file-1"]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_list_all_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let file = |id: &str| {
+        format!(
+            r#"{{"id":"{}","object":"file","bytes":1,"created_at":1,"filename":"{}.jsonl","purpose":"batch"}}"#,
+            id, id
+        )
+    };
+    let page = |ids: &[&str], has_more: bool| {
+        let data = ids.iter().map(|id| file(id)).collect::<Vec<_>>();
+        format!(
+            r#"{{"object":"list","data":[{}],"has_more":{}}}"#,
+            data.join(","),
+            has_more
+        )
+    };
+
+    let server = MockServer::start(vec![
+        (200, "application/json", page(&["file-1", "file-2"], true)),
+        (200, "application/json", page(&["file-3"], false)),
+    ])
+    .await;
+
+    let files = FileListRequest::new()
+        .with_purpose(FilePurpose::Batch)
+        .with_limit(2)
+        .all(&server.client()?, None)
+        .await?;
+    assert_eq!(
+        files.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(),
+        ["file-1", "file-2", "file-3"]
+    );
+    assert_eq!(
+        server.paths(),
+        [
+            "/v1/files?purpose=batch&limit=2",
+            "/v1/files?purpose=batch&after=file-2&limit=2"
+        ]
+    );
+
+    let list: FileListResponse = serde_json::from_str(&page(&["file-1", "file-2"], true))?;
+    assert_eq!(list.next_after(), Some("file-2"));
+    assert_eq!(
+        FileListRequest::new().with_after("file 2&x=1").uri(),
+        "files?after=file+2%26x%3D1"
+    );
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -378,14 +654,14 @@ async fn test_file_upload_ok() -> anyhow::Result<()> {
         .with_version(version)?
         .build()?;
 
-    let rep = FileListRequest.call(&client, None).await?;
+    let rep = FileListRequest::new().call(&client, None).await?;
 
     for item in &rep.data {
         if item.filename != "161528_24 司马光 优质教案.pdf" {
             continue;
         }
-        let rep = FileGetRequest::new(&item.id).call(&client, None).await?;
-        let rep = FileContentRequest::new(&item.id)
+        let _rep = FileGetRequest::new(&item.id).call(&client, None).await?;
+        let _rep = FileContentRequest::new(&item.id)
             .call(&client, None)
             .await?;
     }
@@ -404,3 +680,203 @@ async fn test_file_upload_ok() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_purpose_mismatch_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    // server never answers, validation must fail before any request
+    let server = MockServer::start(vec![]).await;
+    let client = server.client()?;
+
+    let res = FileUploadRequest::builder()
+        .with_source(PathBuf::from("batch.txt"))
+        .with_purpose(FilePurpose::Batch)
+        .build();
+    assert!(
+        matches!(res, Err(Error::FilePurposeMismatch(ref purpose, ref path)) if purpose == "batch" && path == "batch.txt")
+    );
+
+    let req = FileUploadRequest {
+        source: PathBuf::from("train.txt").into(),
+        purpose: FilePurpose::FineTune,
+        expires_after_seconds: None,
+        timeout: None,
+    };
+    let res = req.call(&client, None).await;
+    assert!(matches!(res, Err(Error::FilePurposeMismatch(..))));
+    assert_eq!(server.hits(), 0);
+
+    assert!(FileUploadRequest::builder()
+        .with_source(PathBuf::from("batch.JSONL"))
+        .with_purpose(FilePurpose::Batch)
+        .build()
+        .is_ok());
+
+    assert!(FileUploadRequest::builder()
+        .with_source(PathBuf::from("doc.pdf"))
+        .with_purpose(FilePurpose::Extract)
+        .build()
+        .is_ok());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_purpose_str_ok() -> Result<()> {
+    for purpose in [
+        FilePurpose::Extract,
+        FilePurpose::Batch,
+        FilePurpose::FineTune,
+        FilePurpose::Assistants,
+        FilePurpose::Vision,
+        FilePurpose::UserData,
+        FilePurpose::Evals,
+        FilePurpose::AssistantsOutput,
+        FilePurpose::BatchOutput,
+        FilePurpose::FineTuneResults,
+    ] {
+        let wire = serde_json::to_value(&purpose)?;
+        assert_eq!(wire, purpose.as_str());
+        assert_eq!(purpose.to_string().parse::<FilePurpose>()?, purpose);
+    }
+
+    assert!(matches!(
+        "answers".parse::<FilePurpose>(),
+        Err(Error::UnknownVariant("FilePurpose", ref s)) if s == "answers"
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_upload_cancellable_ok() -> Result<()> {
+    let path = std::env::temp_dir().join("openai-ng-test-cancel.jsonl");
+    tokio::fs::write(&path, b"{}\n").await?;
+
+    let server = crate::tests::MockServer::start_stalled(
+        "application/json",
+        r#"{"id":"#.to_string(),
+        Duration::from_secs(5),
+    )
+    .await;
+
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let res = FileUploadRequest::builder()
+        .with_source(path.clone())
+        .with_purpose(FilePurpose::Batch)
+        .build()?
+        .call_cancellable(&server.client()?, None, token)
+        .await;
+    assert!(matches!(res, Err(Error::Cancelled)));
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_upload_expires_after_ok() -> Result<()> {
+    let builder = || {
+        FileUploadRequest::builder()
+            .with_source(PathBuf::from("doc.pdf"))
+            .with_purpose(FilePurpose::Extract)
+    };
+
+    let fields = builder().build()?.form_fields().text_fields();
+    assert_eq!(fields, [("purpose", "file-extract".to_string())]);
+
+    let fields = builder()
+        .with_expires_after_seconds(3600)
+        .build()?
+        .form_fields()
+        .text_fields();
+    assert_eq!(
+        fields,
+        [
+            ("purpose", "file-extract".to_string()),
+            ("expires_after[anchor]", "created_at".to_string()),
+            ("expires_after[seconds]", "3600".to_string()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_content_byte_stream_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let chunks = vec![
+        "line 1\n".to_string(),
+        "line 2\n".to_string(),
+        "line 3".to_string(),
+    ];
+    let server = MockServer::start_chunked("application/octet-stream", chunks.clone()).await;
+
+    let stream = FileContentRequest::new("file-1")
+        .byte_stream(&server.client()?, None)
+        .await?;
+
+    let parts = stream.collect::<Vec<_>>().await;
+    assert!(!parts.is_empty());
+
+    let mut content = vec![];
+    for part in parts {
+        content.extend_from_slice(part?.as_ref());
+    }
+    assert_eq!(content, chunks.concat().as_bytes());
+    assert_eq!(server.paths(), ["/v1/files/file-1/content"]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_file_upload_retry_rebuilds_form_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let path = std::env::temp_dir().join("openai-ng-test-retry.jsonl");
+    tokio::fs::write(&path, b"{\"custom_id\":\"1\"}\n").await?;
+
+    let uploaded = r#"{"id":"file-1","object":"file","bytes":18,"created_at":1,"filename":"openai-ng-test-retry.jsonl","purpose":"batch"}"#;
+    let server = MockServer::start(vec![
+        (500, "application/json", "{}".to_string()),
+        (200, "application/json", uploaded.to_string()),
+    ])
+    .await;
+
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_retry(1, Duration::from_millis(10))?
+        .build()?;
+
+    let rep = FileUploadRequest::builder()
+        .with_source(path.clone())
+        .with_purpose(FilePurpose::Batch)
+        .build()?
+        .call(&client, None)
+        .await?;
+    assert_eq!(rep.id, "file-1");
+    assert_eq!(server.hits(), 2);
+
+    // both attempts carry the whole file
+    for index in 0..2 {
+        let body = server.body(index).expect("body");
+        assert!(body.contains("filename=\"openai-ng-test-retry.jsonl\""));
+        assert!(body.contains("{\"custom_id\":\"1\"}\n"));
+    }
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    Ok(())
+}
@@ -0,0 +1,465 @@
+use http::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tracing::*;
+
+use crate::{
+    client::{read_error, read_json, Client, Payload},
+    error::*,
+    proto::chat::SseDecoder,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum FineTuningJobStatus {
+    validating_files,
+    queued,
+    running,
+    succeeded,
+    failed,
+    cancelled,
+    /// status unknown to this crate, e.g. newly added by openai
+    #[serde(other)]
+    unknown,
+}
+
+impl_wire_str!(
+    FineTuningJobStatus,
+    validating_files => "validating_files",
+    queued => "queued",
+    running => "running",
+    succeeded => "succeeded",
+    failed => "failed",
+    cancelled => "cancelled",
+    unknown => "unknown",
+);
+
+impl FineTuningJobStatus {
+    /// job will not change any more
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::succeeded | Self::failed | Self::cancelled)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FineTuningJob {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub fine_tuned_model: Option<String>,
+    pub status: FineTuningJobStatus,
+    pub training_file: String,
+    pub validation_file: Option<String>,
+    pub trained_tokens: Option<u64>,
+    pub error: Option<Value>,
+}
+
+impl FineTuningJob {
+    /// poll job every `poll_interval` until status is terminal, return the final job
+    pub async fn wait_until_done(
+        &self,
+        client: &Client,
+        poll_interval: Duration,
+    ) -> Result<FineTuningJob> {
+        let mut job = self.clone();
+        while !job.status.is_terminal() {
+            trace!(id = %job.id, status = %job.status, "wait fine tuning job");
+            tokio::time::sleep(poll_interval).await;
+            job = FineTuningJobGetRequest::new(&job.id)
+                .call(client, None)
+                .await?;
+        }
+        debug!(id = %job.id, status = %job.status, "fine tuning job done");
+        Ok(job)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FineTuningEvent {
+    pub id: String,
+    pub object: String,
+    pub created_at: u64,
+    pub level: String,
+    pub message: String,
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FineTuningCheckpoint {
+    pub id: String,
+    pub object: String,
+    pub created_at: u64,
+    pub fine_tuned_model_checkpoint: String,
+    pub fine_tuning_job_id: String,
+    pub step_number: u64,
+    pub metrics: Option<Value>,
+}
+
+/// one page of listing, use last id as `after` to fetch next page
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FineTuningList<T> {
+    pub object: String,
+    pub data: Vec<T>,
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+impl<T> FineTuningList<T> {
+    fn next_after_by(&self, id: fn(&T) -> &str) -> Option<&str> {
+        self.data.last().filter(|_| self.has_more).map(id)
+    }
+}
+
+impl FineTuningList<FineTuningEvent> {
+    /// id of last event as `after` of next page, `None` if no more
+    pub fn next_after(&self) -> Option<&str> {
+        self.next_after_by(|event| &event.id)
+    }
+}
+
+impl FineTuningList<FineTuningCheckpoint> {
+    /// id of last checkpoint as `after` of next page, `None` if no more
+    pub fn next_after(&self) -> Option<&str> {
+        self.next_after_by(|checkpoint| &checkpoint.id)
+    }
+}
+
+pub struct FineTuningJobGetRequest {
+    pub id: String,
+}
+
+impl FineTuningJobGetRequest {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into() }
+    }
+
+    pub async fn call(&self, client: &Client, timeout: Option<Duration>) -> Result<FineTuningJob> {
+        get_json(client, format!("fine_tuning/jobs/{}", self.id), timeout).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FineTuningEventsRequest {
+    pub job_id: String,
+    pub after: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl FineTuningEventsRequest {
+    pub fn new(job_id: impl Into<String>) -> Self {
+        Self {
+            job_id: job_id.into(),
+            after: None,
+            limit: None,
+        }
+    }
+
+    pub fn with_after(mut self, after: impl Into<String>) -> Self {
+        self.after = Some(after.into());
+        self
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub async fn call(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<FineTuningList<FineTuningEvent>> {
+        let uri = list_uri(
+            format!("fine_tuning/jobs/{}/events", self.job_id),
+            &self.after,
+            self.limit,
+            false,
+        );
+        get_json(client, uri, timeout).await
+    }
+
+    /// follow events with sse while job is running, stream ends on `done` event
+    pub async fn stream_events(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<Receiver<Result<FineTuningEvent>>> {
+        let uri = list_uri(
+            format!("fine_tuning/jobs/{}/events", self.job_id),
+            &self.after,
+            self.limit,
+            true,
+        );
+
+        let mut rep = client
+            .request(Method::GET, uri, vec![], Payload::None, timeout)
+            .await?;
+
+        if !rep.status().is_success() {
+            return Err(read_error(rep).await);
+        }
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+
+        tokio::spawn(async move {
+            let mut decoder = SseDecoder::default();
+
+            'outer: loop {
+                let next = tokio::select! {
+                    biased;
+                    _ = tx.closed() => {
+                        debug!("event receiver dropped, stop stream");
+                        break;
+                    }
+                    chunk = rep.chunk() => chunk,
+                };
+                let chunk = match next {
+                    Ok(Some(r)) => r,
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("event stream return with error: {:?}", e);
+                        tx.send(Err(e.into()))
+                            .await
+                            .map_err(|_| Error::SendMessage)?;
+                        break;
+                    }
+                };
+
+                for frame in decoder.feed_events(chunk.as_ref()) {
+                    let event = match frame.event.as_deref() {
+                        Some("done") => break 'outer,
+                        Some("error") => {
+                            error!(data = %frame.data, "event stream error");
+                            Err(Error::StreamErrorEvent(frame.data))
+                        }
+                        _ if frame.data.trim() == "[DONE]" => break 'outer,
+                        _ => crate::json::from_str::<FineTuningEvent>(&frame.data)
+                            .map_err(Error::from),
+                    };
+                    tx.send(event).await.map_err(|_| {
+                        error!("failed to send message to event receiver");
+                        Error::SendMessage
+                    })?;
+                }
+            }
+
+            trace!("event stream quit");
+            Result::Ok(())
+        });
+
+        Ok(rx)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FineTuningCheckpointsRequest {
+    pub job_id: String,
+    pub after: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl FineTuningCheckpointsRequest {
+    pub fn new(job_id: impl Into<String>) -> Self {
+        Self {
+            job_id: job_id.into(),
+            after: None,
+            limit: None,
+        }
+    }
+
+    pub fn with_after(mut self, after: impl Into<String>) -> Self {
+        self.after = Some(after.into());
+        self
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub async fn call(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<FineTuningList<FineTuningCheckpoint>> {
+        let uri = list_uri(
+            format!("fine_tuning/jobs/{}/checkpoints", self.job_id),
+            &self.after,
+            self.limit,
+            false,
+        );
+        get_json(client, uri, timeout).await
+    }
+}
+
+fn list_uri(path: String, after: &Option<String>, limit: Option<u32>, stream: bool) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(after) = after {
+        query.append_pair("after", after);
+    }
+    if let Some(limit) = limit {
+        query.append_pair("limit", &limit.to_string());
+    }
+    if stream {
+        query.append_pair("stream", "true");
+    }
+    match query.finish() {
+        query if query.is_empty() => path,
+        query => format!("{}?{}", path, query),
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(
+    client: &Client,
+    uri: String,
+    timeout: Option<Duration>,
+) -> Result<T> {
+    let rep = client
+        .request(Method::GET, uri, vec![], Payload::None, timeout)
+        .await?;
+
+    read_json(rep).await
+}
+
+#[cfg(test)]
+fn job_json(status: &str) -> String {
+    format!(
+        r#"{{"id":"ftjob-1","object":"fine_tuning.job","model":"gpt-4o-mini","created_at":1,"finished_at":null,"fine_tuned_model":null,"status":"{}","training_file":"file-1","validation_file":null,"trained_tokens":null,"error":null}}"#,
+        status
+    )
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_fine_tuning_wait_until_done_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![
+        (200, "application/json", job_json("running")),
+        (200, "application/json", job_json("succeeded")),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let job: FineTuningJob = serde_json::from_str(&job_json("queued"))?;
+    let job = job
+        .wait_until_done(&client, Duration::from_millis(10))
+        .await?;
+
+    assert_eq!(job.status, FineTuningJobStatus::succeeded);
+    assert_eq!(server.hits(), 2);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_fine_tuning_events_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let event = |id: &str, message: &str| {
+        format!(
+            r#"{{"id":"{}","object":"fine_tuning.job.event","created_at":1,"level":"info","message":"{}","type":"message"}}"#,
+            id, message
+        )
+    };
+
+    let page = format!(
+        r#"{{"object":"list","data":[{},{}],"has_more":true}}"#,
+        event("ev-1", "created"),
+        event("ev-2", "started")
+    );
+
+    let sse = [
+        ": keep-alive".to_string(),
+        format!(
+            "event: fine_tuning.job.event\ndata: {}",
+            event("ev-3", "step 1")
+        ),
+        format!(
+            "event: fine_tuning.job.event\ndata: {}",
+            event("ev-4", "step 2")
+        ),
+        "event: done\ndata: [DONE]".to_string(),
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let checkpoints = r#"{"object":"list","data":[{"id":"ftckpt-1","object":"fine_tuning.job.checkpoint","created_at":1,"fine_tuned_model_checkpoint":"ft:gpt-4o-mini:ckpt-step-10","fine_tuning_job_id":"ftjob-1","step_number":10,"metrics":{"step":10,"train_loss":0.5}}],"has_more":false}"#;
+
+    let server = MockServer::start(vec![
+        (200, "application/json", page),
+        (200, "text/event-stream", sse),
+        (200, "application/json", checkpoints.to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = FineTuningEventsRequest::new("ftjob-1").with_limit(2);
+    let rep = req.call(&client, None).await?;
+    assert_eq!(rep.data.len(), 2);
+    assert_eq!(rep.next_after(), Some("ev-2"));
+
+    let mut rx = req.with_after("ev-2").stream_events(&client, None).await?;
+    let mut messages = vec![];
+    while let Some(event) = rx.recv().await {
+        messages.push(event?.message);
+    }
+    assert_eq!(messages, ["step 1", "step 2"]);
+
+    let rep = FineTuningCheckpointsRequest::new("ftjob-1")
+        .call(&client, None)
+        .await?;
+    assert_eq!(rep.data[0].step_number, 10);
+    assert!(rep.next_after().is_none());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_fine_tuning_events_error_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let sse = [
+        r#"data: {"id":"ev-1","object":"fine_tuning.job.event","created_at":1,"level":"info","message":"got [DONE] marker in data"}"#,
+        r#"event: error
+data: {"message":"training file invalid"}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![(200, "text/event-stream", sse)]).await;
+    let client = server.client()?;
+
+    let mut rx = FineTuningEventsRequest::new("ftjob-1")
+        .with_after("ev 0&x=1")
+        .stream_events(&client, None)
+        .await?;
+
+    let event = rx.recv().await.expect("event")?;
+    assert_eq!(event.message, "got [DONE] marker in data");
+    let res = rx.recv().await.expect("error");
+    assert!(
+        matches!(res, Err(Error::StreamErrorEvent(ref data)) if data == r#"{"message":"training file invalid"}"#)
+    );
+    assert!(rx.recv().await.is_none());
+
+    assert_eq!(
+        server.paths(),
+        ["/v1/fine_tuning/jobs/ftjob-1/events?after=ev+0%26x%3D1&stream=true"]
+    );
+
+    let job: FineTuningJob = serde_json::from_str(&job_json("paused"))?;
+    assert_eq!(job.status, FineTuningJobStatus::unknown);
+    assert!(!job.status.is_terminal());
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use reqwest::multipart::{Form, Part};
+use reqwest::Body;
+use std::path::Path;
+use tokio_util::bytes::Bytes;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use tracing::*;
+
+use crate::error::*;
+use crate::proto::chat::image_mime_of_suffix;
+
+/// field added to `FormBuilder`, kept for tracing and tests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FormField {
+    Text {
+        name: &'static str,
+        value: String,
+    },
+    File {
+        name: &'static str,
+        file_name: String,
+        mime: &'static str,
+    },
+}
+
+/// multipart form shared by upload endpoints, optional fields are skipped,
+/// arrays are sent as repeated fields and file parts carry filename and mime
+#[derive(Debug, Default)]
+pub(crate) struct FormBuilder {
+    form: Form,
+    fields: Vec<FormField>,
+}
+
+impl FormBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, name: &'static str, value: impl ToString) -> Self {
+        let value = value.to_string();
+        self.form = self.form.text(name, value.clone());
+        self.fields.push(FormField::Text { name, value });
+        self
+    }
+
+    /// skip field if `value` is `None`
+    pub fn text_opt(self, name: &'static str, value: Option<impl ToString>) -> Self {
+        match value {
+            Some(value) => self.text(name, value),
+            None => self,
+        }
+    }
+
+    /// one field per value, `name` should end with `[]` for array fields
+    pub fn texts(
+        self,
+        name: &'static str,
+        values: impl IntoIterator<Item = impl ToString>,
+    ) -> Self {
+        values
+            .into_iter()
+            .fold(self, |form, value| form.text(name, value))
+    }
+
+    /// stream local file, filename and mime are taken from `path`
+    pub async fn file_from_path(self, name: &'static str, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string())
+            .ok_or(Error::NoFileName)?;
+        let file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+        self.file_part(name, file_name, Part::stream_with_length(body, length))
+    }
+
+    /// mime is guessed from extension of `file_name`
+    pub fn file_from_bytes(
+        self,
+        name: &'static str,
+        file_name: impl Into<String>,
+        bytes: impl Into<Bytes>,
+    ) -> Result<Self> {
+        let bytes = bytes.into();
+        let length = bytes.len() as u64;
+        self.file_part(
+            name,
+            file_name.into(),
+            Part::stream_with_length(bytes, length),
+        )
+    }
+
+    fn file_part(mut self, name: &'static str, file_name: String, part: Part) -> Result<Self> {
+        let mime = mime_of_path(Path::new(&file_name));
+        let part = part.file_name(file_name.clone()).mime_str(mime)?;
+        self.form = self.form.part(name, part);
+        self.fields.push(FormField::File {
+            name,
+            file_name,
+            mime,
+        });
+        Ok(self)
+    }
+
+    #[cfg(test)]
+    pub fn fields(&self) -> &[FormField] {
+        &self.fields
+    }
+
+    /// name and value of text fields, in order
+    #[cfg(test)]
+    pub fn text_fields(&self) -> Vec<(&'static str, String)> {
+        self.fields
+            .iter()
+            .filter_map(|f| match f {
+                FormField::Text { name, value } => Some((*name, value.clone())),
+                FormField::File { .. } => None,
+            })
+            .collect()
+    }
+
+    /// text fields and file names, one per line, hashed as request body by recorder,
+    /// file content is not read, so uploads of same file name share a key
+    pub fn record_key(&self) -> String {
+        self.fields
+            .iter()
+            .map(|f| match f {
+                FormField::Text { name, value } => format!("{}={}", name, value),
+                FormField::File {
+                    name,
+                    file_name,
+                    mime,
+                } => format!("{}=@{};{}", name, file_name, mime),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn build(self) -> Form {
+        trace!(fields = ?self.fields, "build multipart form");
+        self.form
+    }
+}
+
+/// mime of file by extension, `application/octet-stream` if unknown
+pub(crate) fn mime_of_path(path: &Path) -> &'static str {
+    let suffix = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_ascii_lowercase())
+        .unwrap_or_default();
+    match suffix.as_str() {
+        "json" => "application/json",
+        "jsonl" => "application/jsonl",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain",
+        "csv" => "text/csv",
+        "mp3" | "mpga" | "mpeg" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        other => image_mime_of_suffix(other).unwrap_or("application/octet-stream"),
+    }
+}
+
+/// render form as the request body reqwest would send
+#[cfg(test)]
+async fn render(form: Form) -> Result<String> {
+    use http_body_util::BodyExt;
+
+    let mut req = reqwest::Client::new()
+        .post("http://localhost/")
+        .multipart(form)
+        .build()?;
+    let body = req.body_mut().take().expect("body");
+    let bytes = body.collect().await?.to_bytes();
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_form_builder_part_headers_ok() -> Result<()> {
+    let form = FormBuilder::new()
+        .text("model", "whisper-1")
+        .text_opt("language", None::<String>)
+        .text_opt("temperature", Some(0.5))
+        .texts("timestamp_granularities[]", ["word", "segment"])
+        .file_from_bytes("file", "hello.mp3", b"ID3".to_vec())?;
+
+    assert_eq!(
+        form.text_fields(),
+        [
+            ("model", "whisper-1".to_string()),
+            ("temperature", "0.5".to_string()),
+            ("timestamp_granularities[]", "word".to_string()),
+            ("timestamp_granularities[]", "segment".to_string()),
+        ]
+    );
+    assert_eq!(
+        form.fields().last(),
+        Some(&FormField::File {
+            name: "file",
+            file_name: "hello.mp3".to_string(),
+            mime: "audio/mpeg",
+        })
+    );
+
+    let body = render(form.build()).await?;
+    assert!(body.contains("Content-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n"));
+    assert!(!body.contains("name=\"language\""));
+    assert_eq!(
+        body.matches("name=\"timestamp_granularities[]\"").count(),
+        2
+    );
+    assert!(body.contains(
+        "Content-Disposition: form-data; name=\"file\"; filename=\"hello.mp3\"\r\nContent-Type: audio/mpeg\r\n\r\nID3\r\n"
+    ));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_form_builder_file_from_path_ok() -> Result<()> {
+    let path = std::env::temp_dir().join("openai-ng-form-test.jsonl");
+    tokio::fs::write(&path, "{\"a\":1}\n").await?;
+
+    let form = FormBuilder::new()
+        .text("purpose", "batch")
+        .file_from_path("file", &path)
+        .await?;
+    let body = render(form.build()).await?;
+    tokio::fs::remove_file(&path).await?;
+
+    assert!(body.contains(
+        "Content-Disposition: form-data; name=\"file\"; filename=\"openai-ng-form-test.jsonl\"\r\nContent-Type: application/jsonl\r\n\r\n{\"a\":1}\n\r\n"
+    ));
+
+    assert_eq!(mime_of_path(Path::new("a.PNG")), "image/png");
+    assert_eq!(mime_of_path(Path::new("a")), "application/octet-stream");
+
+    Ok(())
+}
@@ -1,68 +1,125 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::client::{cancellable, read_json, Payload};
 use crate::error::*;
+use crate::proto::chat::{sniff_image_mime, ImageUrl};
+use base64::Engine;
+use futures::StreamExt;
 use http::{
-    header::{self, HeaderValue},
+    header::{self, HeaderName, HeaderValue},
     Method,
 };
 use reqwest::Body;
+use serde_with::skip_serializing_none;
 use smart_default::SmartDefault;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct GenerationRequest {
     pub model: String,
     pub prompt: String,
     pub size: Option<String>,
     pub n: Option<i32>,
     pub response_format: Option<GenerationFormat>,
-    pub seed: Option<i32>,
+    pub seed: Option<i64>,
+    /// one seed per image when `n > 1`, stepfun only
+    pub seeds: Option<Vec<i64>>,
     pub steps: Option<i32>,
     pub cfg_scale: Option<f32>,
+    /// source image for img2img, url or base64 data url
+    pub image: Option<String>,
+    /// how much the source image is changed, 0.0 ~ 1.0
+    pub strength: Option<f32>,
+    pub negative_prompt: Option<String>,
+    /// vendor specific parameters, e.g. `sampler`, `guidance_scale`, flattened into request body
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// used when `call` is given no timeout, never sent
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+    /// extra http headers of every call, never in body
+    #[serde(skip)]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl GenerationRequest {
+    /// typed fields, which are not allowed as `extra` keys
+    pub const FIELDS: &'static [&'static str] = &[
+        "model",
+        "prompt",
+        "size",
+        "n",
+        "response_format",
+        "seed",
+        "seeds",
+        "steps",
+        "cfg_scale",
+        "image",
+        "strength",
+        "negative_prompt",
+    ];
+
     pub fn builder() -> GenerationRequestBuilder {
         GenerationRequestBuilder::default()
     }
 
+    /// builder with model pre-filled from `Client::default_model`
+    pub fn builder_for(client: &crate::client::Client) -> GenerationRequestBuilder {
+        match client.default_model() {
+            Some(model) => Self::builder().with_model(model),
+            None => Self::builder(),
+        }
+    }
+
     pub async fn call(
         &self,
         client: &crate::client::Client,
         timeout: Option<Duration>,
+    ) -> Result<GenerationResponse> {
+        let rep = self.send(client, timeout).await;
+        client.usage_tracker().track(&rep, |_| None);
+        rep
+    }
+
+    /// same as `call`, but abort with `Error::Cancelled` once `token` fired
+    pub async fn call_cancellable(
+        &self,
+        client: &crate::client::Client,
+        timeout: Option<Duration>,
+        token: CancellationToken,
+    ) -> Result<GenerationResponse> {
+        cancellable(&token, self.call(client, timeout)).await
+    }
+
+    async fn send(
+        &self,
+        client: &crate::client::Client,
+        timeout: Option<Duration>,
     ) -> Result<GenerationResponse> {
         let uri = "images/generations";
+        let timeout = timeout.or(self.timeout);
 
         let rep = client
-            .call_impl(
+            .request(
                 Method::POST,
                 uri,
-                vec![(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_str("application/json")?,
-                )],
-                Some(Body::from(serde_json::to_string(&self)?)),
-                None,
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    ),
+                    (header::ACCEPT, HeaderValue::from_static("application/json")),
+                ]
+                .into_iter()
+                .chain(self.headers.iter().cloned()),
+                Payload::Json(Body::from(crate::json::to_string(&self)?)),
                 timeout,
             )
             .await?;
 
-        let status = rep.status();
-
-        let rep = serde_json::from_slice::<serde_json::Value>(rep.bytes().await?.as_ref())?;
-
-        for l in serde_json::to_string_pretty(&rep)?.lines() {
-            if status.is_client_error() || status.is_server_error() {
-                tracing::error!("REP: {}", l);
-            } else {
-                tracing::trace!("REP: {}", l);
-            }
-        }
-
-        if !status.is_success() {
-            return Err(Error::ApiError(status.as_u16()));
-        }
-
-        Ok(serde_json::from_value(rep)?)
+        read_json(rep).await
     }
 }
 
@@ -73,9 +130,16 @@ pub struct GenerationRequestBuilder {
     size: Option<String>,
     n: Option<i32>,
     response_format: Option<GenerationFormat>,
-    seed: Option<i32>,
+    seed: Option<i64>,
+    seeds: Option<Vec<i64>>,
     steps: Option<i32>,
     cfg_scale: Option<f32>,
+    image: Option<String>,
+    strength: Option<f32>,
+    negative_prompt: Option<String>,
+    extra: serde_json::Map<String, serde_json::Value>,
+    timeout: Option<Duration>,
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl GenerationRequestBuilder {
@@ -94,6 +158,11 @@ impl GenerationRequestBuilder {
         self
     }
 
+    pub fn with_size_preset(mut self, size: ImageSize) -> Self {
+        self.size = Some(size.into());
+        self
+    }
+
     pub fn with_n(mut self, n: i32) -> Self {
         self.n = Some(n);
         self
@@ -104,11 +173,17 @@ impl GenerationRequestBuilder {
         self
     }
 
-    pub fn with_seed(mut self, seed: i32) -> Self {
+    pub fn with_seed(mut self, seed: i64) -> Self {
         self.seed = Some(seed);
         self
     }
 
+    /// seed of each image, length must equal `n` if `n` is set
+    pub fn with_seeds(mut self, seeds: impl IntoIterator<Item = i64>) -> Self {
+        self.seeds = Some(seeds.into_iter().collect());
+        self
+    }
+
     pub fn with_steps(mut self, steps: i32) -> Self {
         self.steps = Some(steps);
         self
@@ -119,6 +194,50 @@ impl GenerationRequestBuilder {
         self
     }
 
+    /// source image for img2img, use `ImageUrl::from_local_file` to embed local image
+    pub fn with_source_image(mut self, image: ImageUrl) -> Self {
+        self.image = Some(image.url);
+        self
+    }
+
+    /// source image for img2img as plain base64, without data url prefix, e.g. for sd webui
+    pub fn with_init_image_binary(mut self, image: impl AsRef<[u8]>) -> Self {
+        self.image = Some(base64::engine::general_purpose::STANDARD.encode(image));
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = Some(strength);
+        self
+    }
+
+    pub fn with_negative_prompt(mut self, negative_prompt: impl Into<String>) -> Self {
+        self.negative_prompt = Some(negative_prompt.into());
+        self
+    }
+
+    /// default timeout of `call`, explicit timeout of `call` still wins
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// add http header to every call of the request
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// add vendor specific parameter, key must not collide with typed fields
+    pub fn with_extra(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+
     pub fn build(self) -> Result<GenerationRequest> {
         let Self {
             model,
@@ -127,10 +246,32 @@ impl GenerationRequestBuilder {
             n,
             response_format,
             seed,
+            seeds,
             steps,
             cfg_scale,
+            image,
+            strength,
+            negative_prompt,
+            extra,
+            timeout,
+            headers,
         } = self;
 
+        if let Some(key) = extra
+            .keys()
+            .find(|k| GenerationRequest::FIELDS.contains(&k.as_str()))
+        {
+            tracing::error!("extra key `{}` collides with typed field", key);
+            return Err(Error::GenerationRequestBuild);
+        }
+
+        if let (Some(seeds), Some(n)) = (seeds.as_ref(), n) {
+            if seeds.len() != n as usize {
+                tracing::error!(seeds = seeds.len(), %n, "number of seeds mismatch n");
+                return Err(Error::GenerationRequestBuild);
+            }
+        }
+
         Ok(GenerationRequest {
             model: model.ok_or(Error::GenerationRequestBuild)?,
             prompt: prompt.ok_or(Error::GenerationRequestBuild)?,
@@ -138,27 +279,139 @@ impl GenerationRequestBuilder {
             n,
             response_format,
             seed,
+            seeds,
             steps,
             cfg_scale,
+            image,
+            strength,
+            negative_prompt,
+            extra,
+            timeout,
+            headers,
         })
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// common image size presets, use `Custom` for providers accept arbitrary size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    S256x256,
+    S512x512,
+    S1024x1024,
+    S1792x1024,
+    S1024x1792,
+    Custom(i32, i32),
+}
+
+impl From<ImageSize> for String {
+    fn from(value: ImageSize) -> Self {
+        match value {
+            ImageSize::S256x256 => "256x256".to_string(),
+            ImageSize::S512x512 => "512x512".to_string(),
+            ImageSize::S1024x1024 => "1024x1024".to_string(),
+            ImageSize::S1792x1024 => "1792x1024".to_string(),
+            ImageSize::S1024x1792 => "1024x1792".to_string(),
+            ImageSize::Custom(width, height) => format!("{}x{}", width, height),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct GenerationResponse {
     pub created: u64,
     pub data: Vec<GenerationData>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl GenerationResponse {
+    /// max concurrent downloads in `download_all`
+    pub const DOWNLOAD_CONCURRENCY: usize = 4;
+
+    /// fetch all images right away, since `url` results expire soon (about an hour for dall-e),
+    /// result is in the same order as `data`, each entry fails independently
+    pub async fn download_all(&self, client: &crate::client::Client) -> Vec<Result<Vec<u8>>> {
+        futures::stream::iter(self.data.iter().map(|data| data.download(client)))
+            .buffered(Self::DOWNLOAD_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// save all images into `dir` as `{prefix}_{index}.{ext}`, `ext` follows the sniffed format,
+    /// `png` if unknown; nothing is left behind if any image fails
+    pub async fn save_all(
+        &self,
+        client: &crate::client::Client,
+        dir: impl AsRef<Path>,
+        prefix: &str,
+    ) -> Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let images = self
+            .download_all(client)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        tokio::fs::create_dir_all(dir).await?;
+
+        let mut paths = vec![];
+        for (index, image) in images.into_iter().enumerate() {
+            let extension = match sniff_image_mime(&image) {
+                Some("image/jpeg") => "jpg",
+                Some("image/gif") => "gif",
+                Some("image/webp") => "webp",
+                Some("image/bmp") => "bmp",
+                _ => "png",
+            };
+            let path = dir.join(format!("{}_{}.{}", prefix, index, extension));
+            if let Err(e) = tokio::fs::write(&path, &image).await {
+                tracing::error!(?path, "failed to save image: {}", e);
+                for path in paths.iter().chain([&path]) {
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+                return Err(e.into());
+            }
+            tracing::debug!(?path, bytes = %image.len(), "image saved");
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct GenerationData {
-    pub seed: i32,
-    pub finish_reason: String,
+    /// omitted by openai
+    pub seed: Option<i64>,
+    /// omitted by openai
+    pub finish_reason: Option<String>,
+    /// base64 encoded image, openai name it `b64_json`
+    #[serde(alias = "b64_json")]
     pub image: Option<String>,
     pub url: Option<String>,
+    /// prompt actually used by dall-e-3
+    pub revised_prompt: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl GenerationData {
+    /// decode base64 image or download it from url
+    pub async fn download(&self, client: &crate::client::Client) -> Result<Vec<u8>> {
+        if let Some(image) = self.image.as_ref() {
+            return Ok(base64::engine::general_purpose::STANDARD.decode(image)?);
+        }
+
+        let url = self.url.as_ref().ok_or(Error::NoContent)?;
+
+        let rep = client.http().get(url).send().await?;
+
+        let status = rep.status();
+
+        if !status.is_success() {
+            tracing::error!(%url, %status, "failed to download image");
+            return Err(Error::ApiError(status.as_u16()));
+        }
+
+        Ok(rep.bytes().await?.to_vec())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum GenerationFormat {
     b64_json,
@@ -188,3 +441,307 @@ async fn test_genai_ok() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[test]
+fn test_image_size_preset_ok() -> Result<()> {
+    let presets = [
+        (ImageSize::S256x256, "256x256"),
+        (ImageSize::S512x512, "512x512"),
+        (ImageSize::S1024x1024, "1024x1024"),
+        (ImageSize::S1792x1024, "1792x1024"),
+        (ImageSize::S1024x1792, "1024x1792"),
+        (ImageSize::Custom(768, 512), "768x512"),
+    ];
+
+    for (preset, expected) in presets {
+        let req = GenerationRequest::builder()
+            .with_model("dall-e-3")
+            .with_prompt("a cat")
+            .with_size_preset(preset)
+            .build()?;
+        let value = serde_json::to_value(&req)?;
+        assert_eq!(value["size"], expected);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_generation_source_image_ok() -> Result<()> {
+    let path = std::env::temp_dir().join("openai-ng-test-source-image.png");
+    tokio::fs::write(&path, b"fake png").await?;
+
+    let req = GenerationRequest::builder()
+        .with_model("step-1x-medium")
+        .with_prompt("make it winter")
+        .with_source_image(ImageUrl::from_local_file(&path).await?)
+        .with_strength(0.6)
+        .build()?;
+
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["image"], "data:image/png;base64,ZmFrZSBwbmc=");
+    assert_eq!(value["strength"], 0.6f32 as f64);
+    assert!(value.get("seed").is_none());
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_init_image_binary_ok() -> Result<()> {
+    let builder = GenerationRequest::builder()
+        .with_model("sd-xl")
+        .with_prompt("make it winter");
+
+    let value = serde_json::to_value(builder.clone().build()?)?;
+    assert!(value.get("image").is_none());
+    assert!(value.get("strength").is_none());
+
+    let req = builder
+        .with_init_image_binary(b"fake png")
+        .with_strength(0.5)
+        .build()?;
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["image"], "ZmFrZSBwbmc=");
+    assert_eq!(value["strength"], 0.5);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_extra_ok() -> Result<()> {
+    let req = GenerationRequest::builder()
+        .with_model("sd-xl")
+        .with_prompt("a cat")
+        .with_negative_prompt("blurry")
+        .with_extra("sampler", "euler_a")
+        .with_extra("guidance_scale", 7.5)
+        .build()?;
+
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["negative_prompt"], "blurry");
+    assert_eq!(value["sampler"], "euler_a");
+    assert_eq!(value["guidance_scale"], 7.5);
+    assert!(value.get("extra").is_none());
+
+    let rhs: GenerationRequest = serde_json::from_value(value)?;
+    assert_eq!(req, rhs);
+
+    let res = GenerationRequest::builder()
+        .with_model("sd-xl")
+        .with_prompt("a cat")
+        .with_extra("seed", 42)
+        .build();
+    assert!(matches!(res, Err(Error::GenerationRequestBuild)));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_serde_round_trip_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let lhs = GenerationRequest::builder()
+        .with_model("step-1x-medium")
+        .with_prompt("a cat")
+        .with_size(512, 512)
+        .with_n(1)
+        .with_response_format(GenerationFormat::b64_json)
+        .with_seed(42)
+        .with_steps(30)
+        .with_cfg_scale(7.5)
+        .with_init_image_binary(b"fake png")
+        .with_strength(0.6)
+        .with_negative_prompt("blurry")
+        .with_extra("sampler", "euler_a")
+        .build()?;
+    let rhs: GenerationRequest = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+    assert_eq!(lhs, rhs);
+
+    for json in [OPENAI_IMAGE_GENERATION_JSON, STEPFUN_IMAGE_GENERATION_JSON] {
+        let lhs: GenerationResponse = serde_json::from_str(json)?;
+        let rhs: GenerationResponse = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+        assert_eq!(lhs, rhs);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_generation_download_all_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(200, "image/png", "png bytes".to_string())]).await;
+    let client = server.client()?;
+
+    let rep: GenerationResponse = serde_json::from_value(serde_json::json!({
+        "created": 1,
+        "data": [
+            {"seed": 1, "finish_reason": "success", "url": format!("{}/image.png", server.base_url)},
+            {"seed": 2, "finish_reason": "success", "b64_json": "ZmFrZSBwbmc="},
+            {"seed": 3, "finish_reason": "content_filtered"},
+        ]
+    }))?;
+
+    let images = rep.download_all(&client).await;
+
+    assert_eq!(images.len(), 3);
+    assert!(matches!(&images[0], Ok(b) if b == b"png bytes"));
+    assert!(matches!(&images[1], Ok(b) if b == b"fake png"));
+    assert!(matches!(&images[2], Err(Error::NoContent)));
+    assert_eq!(server.hits(), 1);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_negative_prompt_ok() -> Result<()> {
+    let builder = GenerationRequest::builder()
+        .with_model("dall-e-3")
+        .with_prompt("a cat");
+
+    let value = serde_json::to_value(builder.clone().build()?)?;
+    assert!(value.get("negative_prompt").is_none());
+
+    let value = serde_json::to_value(builder.with_negative_prompt("dog").build()?)?;
+    assert_eq!(value["negative_prompt"], "dog");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_response_providers_ok() -> Result<()> {
+    use crate::tests::*;
+
+    let rep: GenerationResponse = serde_json::from_str(OPENAI_IMAGE_GENERATION_JSON)?;
+    let data = &rep.data[0];
+    assert_eq!(data.image.as_deref(), Some("ZmFrZSBwbmc="));
+    assert!(data
+        .revised_prompt
+        .as_deref()
+        .is_some_and(|p| p.contains("mandarin fish")));
+    assert_eq!(data.seed, None);
+    assert_eq!(data.finish_reason, None);
+
+    let rep: GenerationResponse = serde_json::from_str(STEPFUN_IMAGE_GENERATION_JSON)?;
+    let data = &rep.data[0];
+    assert_eq!(data.image.as_deref(), Some("ZmFrZSBwbmc="));
+    assert_eq!(data.seed, Some(1848172362));
+    assert_eq!(data.finish_reason.as_deref(), Some("success"));
+    assert_eq!(data.revised_prompt, None);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_generation_save_all_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![(200, "image/png", "png bytes".to_string())]).await;
+    let client = server.client()?;
+
+    let rep: GenerationResponse = serde_json::from_value(serde_json::json!({
+        "created": 1,
+        "data": [
+            {"b64_json": "/9j/4A=="},
+            {"url": format!("{}/image.png", server.base_url)},
+        ]
+    }))?;
+
+    let dir = std::env::temp_dir().join("openai-ng-save-all-test");
+    let paths = rep.save_all(&client, &dir, "fish").await?;
+
+    assert_eq!(paths, [dir.join("fish_0.jpg"), dir.join("fish_1.png")]);
+    assert_eq!(tokio::fs::read(&paths[0]).await?, [0xff, 0xd8, 0xff, 0xe0]);
+    assert_eq!(tokio::fs::read(&paths[1]).await?, b"png bytes");
+
+    tokio::fs::remove_dir_all(&dir).await?;
+
+    // a failed image leaves nothing behind
+    let rep: GenerationResponse = serde_json::from_value(serde_json::json!({
+        "created": 1,
+        "data": [{"b64_json": "/9j/4A=="}, {"seed": 1}]
+    }))?;
+    assert!(matches!(
+        rep.save_all(&client, &dir, "fish").await,
+        Err(Error::NoContent)
+    ));
+    assert!(!dir.join("fish_0.jpg").exists());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_generation_call_cancellable_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start_stalled(
+        "application/json",
+        r#"{"created":"#.to_string(),
+        Duration::from_secs(5),
+    )
+    .await;
+
+    let token = CancellationToken::new();
+    let token_ = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        token_.cancel();
+    });
+
+    let started = std::time::Instant::now();
+    let res = GenerationRequest::builder()
+        .with_model("step-1x-medium")
+        .with_prompt("fish")
+        .build()?
+        .call_cancellable(&server.client()?, None, token)
+        .await;
+    assert!(matches!(res, Err(Error::Cancelled)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_seeds_ok() -> Result<()> {
+    let builder = || {
+        GenerationRequest::builder()
+            .with_model("step-1x-medium")
+            .with_prompt("fish")
+            .with_n(2)
+    };
+
+    let req = builder().with_seeds([1, 3_000_000_000]).build()?;
+    let value = serde_json::to_value(&req)?;
+    assert_eq!(value["seeds"], serde_json::json!([1, 3_000_000_000i64]));
+    assert!(value.get("seed").is_none());
+
+    assert!(builder().with_seeds([1]).build().is_err());
+    assert_eq!(builder().with_seed(7).build()?.seed, Some(7));
+
+    let rep: GenerationResponse = serde_json::from_value(serde_json::json!({
+        "created": 1,
+        "data": [
+            {"seed": 1, "finish_reason": "success", "image": "ZmFrZSBwbmc="},
+            {"seed": 3_000_000_000i64, "finish_reason": "success", "image": "ZmFrZSBwbmc="},
+        ]
+    }))?;
+    assert_eq!(
+        rep.data.iter().map(|d| d.seed).collect::<Vec<_>>(),
+        [Some(1), Some(3_000_000_000)]
+    );
+
+    Ok(())
+}
@@ -1,6 +1,44 @@
+/// implement `as_str`, `Display` and `FromStr` of an enum with its wire strings
+macro_rules! impl_wire_str {
+    ($typ: ident, $($variant: ident => $s: literal),+ $(,)?) => {
+        impl $typ {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $s,)+
+                }
+            }
+        }
+
+        impl std::fmt::Display for $typ {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for $typ {
+            type Err = crate::error::Error;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                match s {
+                    $($s => Ok(Self::$variant),)+
+                    _ => Err(crate::error::Error::UnknownVariant(stringify!($typ), s.to_string())),
+                }
+            }
+        }
+    };
+}
+
+pub mod audio;
 pub mod chat;
+pub mod conversation;
 pub mod embedding;
 pub mod file;
+pub mod fine_tuning;
+pub(crate) mod form;
 pub mod image;
+pub mod moderation;
+pub mod rerank;
 pub mod sys;
+pub mod template;
+pub mod text_stream;
 pub mod tool;
@@ -0,0 +1,221 @@
+use http::{
+    header::{self, HeaderValue},
+    Method,
+};
+use reqwest::Body;
+use serde_with::skip_serializing_none;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::*;
+
+use crate::client::{read_json, Client, Payload};
+use crate::error::*;
+use crate::proto::chat::{ChatCompletionRequest, ChatCompletionResult, Role};
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationRequest {
+    pub model: Option<String>,
+    pub input: Vec<String>,
+}
+
+impl ModerationRequest {
+    pub fn new(input: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            model: None,
+            input: input.into_iter().map(|i| i.into()).collect(),
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub async fn call(
+        &self,
+        client: &Client,
+        timeout: Option<Duration>,
+    ) -> Result<ModerationResponse> {
+        let rep = client
+            .request(
+                Method::POST,
+                "moderations",
+                vec![
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    ),
+                    (header::ACCEPT, HeaderValue::from_static("application/json")),
+                ],
+                Payload::Json(Body::from(crate::json::to_vec(&self)?)),
+                timeout,
+            )
+            .await?;
+
+        read_json(rep).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationResponse {
+    pub id: String,
+    pub model: String,
+    pub results: Vec<ModerationResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: HashMap<String, bool>,
+    pub category_scores: HashMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// reject request with `Error::ContentFlagged`
+    Block,
+    /// only emit a `tracing::warn!`
+    Warn,
+}
+
+/// decide which moderation categories block a request and which only warn
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationPolicy {
+    /// block any category flagged by api, unless a rule matches the category
+    pub block_flagged: bool,
+    /// category -> (score threshold, action)
+    pub rules: HashMap<String, (f64, ModerationAction)>,
+}
+
+impl Default for ModerationPolicy {
+    fn default() -> Self {
+        Self {
+            block_flagged: true,
+            rules: HashMap::new(),
+        }
+    }
+}
+
+impl ModerationPolicy {
+    /// policy only applying explicit rules, flags of api are ignored
+    pub fn rules_only() -> Self {
+        Self {
+            block_flagged: false,
+            rules: HashMap::new(),
+        }
+    }
+
+    pub fn with_block(mut self, category: impl Into<String>, threshold: f64) -> Self {
+        self.rules
+            .insert(category.into(), (threshold, ModerationAction::Block));
+        self
+    }
+
+    pub fn with_warn(mut self, category: impl Into<String>, threshold: f64) -> Self {
+        self.rules
+            .insert(category.into(), (threshold, ModerationAction::Warn));
+        self
+    }
+
+    /// `Err(Error::ContentFlagged)` if any blocking category is hit
+    pub fn check(&self, result: &ModerationResult) -> Result<()> {
+        let mut blocked = false;
+
+        for (category, flagged) in result.categories.iter() {
+            let score = result
+                .category_scores
+                .get(category)
+                .copied()
+                .unwrap_or_default();
+
+            let action = match self.rules.get(category) {
+                Some((threshold, action)) if score >= *threshold => Some(*action),
+                Some(_) => None,
+                None if *flagged && self.block_flagged => Some(ModerationAction::Block),
+                None => None,
+            };
+
+            match action {
+                Some(ModerationAction::Block) => {
+                    error!(%category, %score, "content flagged, blocked");
+                    blocked = true;
+                }
+                Some(ModerationAction::Warn) => {
+                    warn!(%category, %score, "content flagged");
+                }
+                None => {}
+            }
+        }
+
+        match blocked {
+            true => Err(Error::ContentFlagged(Box::new(result.clone()))),
+            false => Ok(()),
+        }
+    }
+}
+
+impl ChatCompletionRequest {
+    /// moderate concatenated user content with `policy` first, then do the chat call
+    pub async fn call_moderated(
+        &self,
+        client: &Client,
+        policy: &ModerationPolicy,
+        timeout: Option<Duration>,
+    ) -> Result<ChatCompletionResult> {
+        let input = self
+            .messages
+            .iter()
+            .filter(|m| m.role == Some(Role::user))
+            .filter_map(|m| m.content.as_ref())
+            .map(|c| c.to_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let rep = ModerationRequest::new([input])
+            .call(client, timeout)
+            .await?;
+
+        for result in rep.results.iter() {
+            policy.check(result)?;
+        }
+
+        self.call(client, timeout).await
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_call_moderated_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let moderation = r#"{"id":"modr-1","model":"omni-moderation-latest","results":[{"flagged":true,"categories":{"violence":true,"harassment":false},"category_scores":{"violence":0.91,"harassment":0.2}}]}"#;
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#;
+
+    let server = MockServer::start(vec![
+        (200, "application/json", moderation.to_string()),
+        (200, "application/json", moderation.to_string()),
+        (200, "application/json", chat.to_string()),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = ChatCompletionRequest::simple("m", "some text")?;
+
+    let res = req
+        .call_moderated(&client, &ModerationPolicy::default(), None)
+        .await;
+    assert!(matches!(res, Err(Error::ContentFlagged(ref r)) if r.flagged));
+    assert_eq!(server.hits(), 1);
+
+    let policy = ModerationPolicy::rules_only()
+        .with_warn("violence", 0.5)
+        .with_block("harassment", 0.5);
+    let res = req.call_moderated(&client, &policy, None).await?;
+    assert!(
+        matches!(res, ChatCompletionResult::Response(ref r) if r.text().as_deref() == Some("ok"))
+    );
+    assert_eq!(server.hits(), 3);
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use http::Method;
+use serde::Deserialize;
+use serde_with::skip_serializing_none;
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::error::*;
+
+/// rank `documents` by relevance to `query`, cohere style `rerank` endpoint
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<String>,
+    /// keep only the best `top_n` results
+    pub top_n: Option<u32>,
+    /// echo document text in results
+    pub return_documents: Option<bool>,
+}
+
+impl RerankRequest {
+    pub fn new(
+        model: impl Into<String>,
+        query: impl Into<String>,
+        documents: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            model: model.into(),
+            query: query.into(),
+            documents: documents.into_iter().map(|d| d.into()).collect(),
+            top_n: None,
+            return_documents: None,
+        }
+    }
+
+    pub fn with_top_n(mut self, top_n: u32) -> Self {
+        self.top_n = Some(top_n);
+        self
+    }
+
+    pub fn with_return_documents(mut self, return_documents: bool) -> Self {
+        self.return_documents = Some(return_documents);
+        self
+    }
+
+    pub async fn call(&self, client: &Client, timeout: Option<Duration>) -> Result<RerankResponse> {
+        client
+            .call_json(Method::POST, "rerank", self, timeout)
+            .await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RerankResponse {
+    /// ordered by `relevance_score`, highest first
+    pub results: Vec<RerankResult>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RerankResult {
+    /// index into `RerankRequest::documents`
+    pub index: usize,
+    pub relevance_score: f64,
+    /// cohere sends `{"text": "..."}`, others plain string
+    #[serde(default, deserialize_with = "text_or_object")]
+    pub document: Option<String>,
+}
+
+fn text_or_object<'de, D>(de: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum TextOrObject {
+        Text(String),
+        Object { text: String },
+    }
+
+    Ok(Option::<TextOrObject>::deserialize(de)?.map(|d| match d {
+        TextOrObject::Text(text) => text,
+        TextOrObject::Object { text } => text,
+    }))
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_rerank_ok() -> Result<()> {
+    use crate::tests::MockServer;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "application/json",
+            r#"{"id":"r","results":[{"index":2,"relevance_score":0.93,"document":{"text":"rust is fast"}},{"index":0,"relevance_score":0.12,"document":"python"}]}"#
+                .to_string(),
+        ),
+        (
+            200,
+            "application/json",
+            r#"{"results":[{"index":2,"relevance_score":0.93}]}"#.to_string(),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let req = RerankRequest::new(
+        "rerank-v3",
+        "is rust fast?",
+        ["python", "go", "rust is fast"],
+    )
+    .with_top_n(2)
+    .with_return_documents(true);
+    assert_eq!(
+        serde_json::to_value(&req)?,
+        serde_json::json!({
+            "model": "rerank-v3",
+            "query": "is rust fast?",
+            "documents": ["python", "go", "rust is fast"],
+            "top_n": 2,
+            "return_documents": true
+        })
+    );
+
+    let rep = req.call(&client, None).await?;
+    assert_eq!(rep.results.len(), 2);
+    assert_eq!(rep.results[0].index, 2);
+    assert_eq!(rep.results[0].document.as_deref(), Some("rust is fast"));
+    assert_eq!(rep.results[1].document.as_deref(), Some("python"));
+
+    let rep = req
+        .clone()
+        .with_return_documents(false)
+        .call(&client, None)
+        .await?;
+    assert_eq!(rep.results[0].document, None);
+    assert_eq!(server.paths(), ["/v1/rerank", "/v1/rerank"]);
+
+    Ok(())
+}
@@ -1,15 +1,98 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use serde_with::skip_serializing_none;
 use smart_default::SmartDefault;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct ModelListResponse {
     pub object: String,
     pub data: Vec<ModelInfo>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct ModelInfo {
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    /// some providers send it as string
+    #[serde(default, deserialize_with = "u64_or_string")]
     pub created: u64,
+    #[serde(default)]
     pub owned_by: String,
+    /// max context tokens, e.g. kimi
+    pub context_length: Option<u64>,
+    /// model capabilities, e.g. stepfun
+    pub capabilities: Option<Value>,
+    /// base model of a fine-tuned model, e.g. openai
+    pub parent: Option<String>,
+    pub root: Option<String>,
+    /// other provider specific fields
+    #[serde(flatten, default)]
+    pub extra: Map<String, Value>,
+}
+
+/// reply of delete endpoints, e.g. files, models
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeletionStatus {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    pub deleted: bool,
+}
+
+fn u64_or_string<'de, D>(de: D) -> std::result::Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum U64OrString {
+        U64(u64),
+        String(String),
+    }
+
+    match U64OrString::deserialize(de)? {
+        U64OrString::U64(n) => Ok(n),
+        U64OrString::String(s) => s.trim().parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_model_list_providers_ok() -> crate::error::Result<()> {
+    use crate::tests::*;
+
+    let rep: ModelListResponse = serde_json::from_str(OPENAI_MODELS_JSON)?;
+    let ft = &rep.data[1];
+    assert_eq!(ft.created, 1721172717);
+    assert_eq!(ft.parent.as_deref(), Some("gpt-4o-mini-2024-07-18"));
+    assert_eq!(ft.root.as_deref(), Some("gpt-4o-mini-2024-07-18"));
+
+    let rep: ModelListResponse = serde_json::from_str(KIMI_MODELS_JSON)?;
+    assert_eq!(rep.data[0].context_length, Some(8192));
+    assert_eq!(rep.data[0].extra["permission"][0]["allow_view"], true);
+
+    let rep: ModelListResponse = serde_json::from_str(STEPFUN_MODELS_JSON)?;
+    assert_eq!(rep.data[0].created, 1717603200);
+    assert!(rep.data[0].capabilities.is_some());
+
+    let res = serde_json::from_str::<ModelInfo>(r#"{"id":"m","created":"yesterday"}"#);
+    assert!(res.is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_model_list_serde_round_trip_ok() -> crate::error::Result<()> {
+    use crate::tests::*;
+
+    for json in [OPENAI_MODELS_JSON, KIMI_MODELS_JSON, STEPFUN_MODELS_JSON] {
+        let lhs: ModelListResponse = serde_json::from_str(json)?;
+        let rhs: ModelListResponse = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+        assert_eq!(lhs, rhs);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tracing::*;
+
+use crate::error::*;
+use crate::proto::chat::{Message, Role};
+
+/// message with `{{variable}}` placeholders, write `\{{` for a literal `{{`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTemplate {
+    pub role: Role,
+    pub template: String,
+}
+
+impl MessageTemplate {
+    pub fn new(role: Role, template: impl Into<String>) -> Self {
+        Self {
+            role,
+            template: template.into(),
+        }
+    }
+
+    /// fill placeholders with `vars`, missing variable is an error
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<Message> {
+        Ok(Message::builder()
+            .with_role(self.role.clone())
+            .with_content(render_str(&self.template, vars)?)
+            .build())
+    }
+}
+
+fn render_str(template: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find("{{") {
+        if rest[..pos].ends_with('\\') {
+            out.push_str(&rest[..pos - 1]);
+            out.push_str("{{");
+            rest = &rest[pos + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 2..];
+
+        let end = rest.find("}}").ok_or_else(|| {
+            error!("unclosed placeholder in template");
+            Error::TemplateSyntax(template.to_string())
+        })?;
+
+        let name = rest[..end].trim();
+        let value = vars.get(name).ok_or_else(|| {
+            error!(%name, "missing template variable");
+            Error::MissingTemplateVariable(name.to_string())
+        })?;
+
+        out.push_str(value);
+        rest = &rest[end + 2..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// named message templates, render a sequence of them into messages for `with_messages`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PromptSet {
+    pub templates: HashMap<String, MessageTemplate>,
+}
+
+impl PromptSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add an embedded template, e.g. from `include_str!`
+    pub fn with_template(
+        mut self,
+        name: impl Into<String>,
+        role: Role,
+        template: impl Into<String>,
+    ) -> Self {
+        self.templates
+            .insert(name.into(), MessageTemplate::new(role, template));
+        self
+    }
+
+    /// load every `<name>.<role>[.ext]` file in `dir`, e.g. `intro.system.txt`, other files are skipped
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let mut set = Self::new();
+
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let Some(file_name) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let mut parts = file_name.splitn(3, '.');
+            let (Some(name), Some(role)) = (parts.next(), parts.next()) else {
+                trace!(?path, "skip file without role");
+                continue;
+            };
+
+            let Ok(role) = role.parse::<Role>() else {
+                trace!(?path, "skip file with unknown role");
+                continue;
+            };
+
+            let template = std::fs::read_to_string(&path)?;
+            trace!(%name, %role, ?path, "load template");
+            set = set.with_template(name, role, template);
+        }
+
+        Ok(set)
+    }
+
+    /// render templates `names` in order
+    pub fn render<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        vars: &HashMap<String, String>,
+    ) -> Result<Vec<Message>> {
+        names
+            .into_iter()
+            .map(|name| {
+                self.templates
+                    .get(name)
+                    .ok_or_else(|| Error::UnknownTemplate(name.to_string()))?
+                    .render(vars)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_message_template_render_ok() -> Result<()> {
+    use crate::proto::chat::Content;
+
+    let vars = HashMap::from([
+        ("lang".to_string(), "rust".to_string()),
+        ("question".to_string(), "what is a trait?".to_string()),
+    ]);
+
+    let message = MessageTemplate::new(
+        Role::user,
+        r#"answer in {{ lang }}: {{question}}, json like \{{"a": 1}}"#,
+    )
+    .render(&vars)?;
+    assert_eq!(message.role, Some(Role::user));
+    assert_eq!(
+        message.content,
+        Some(Content::Text(
+            r#"answer in rust: what is a trait?, json like {{"a": 1}}"#.into()
+        ))
+    );
+
+    let res = MessageTemplate::new(Role::user, "{{missing}}").render(&vars);
+    assert!(matches!(res, Err(Error::MissingTemplateVariable(ref n)) if n == "missing"));
+
+    let res = MessageTemplate::new(Role::user, "{{lang").render(&vars);
+    assert!(matches!(res, Err(Error::TemplateSyntax(_))));
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_prompt_set_from_dir_ok() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("openai-ng-prompts-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("intro.system.txt"), "you are a {{lang}} expert")?;
+    std::fs::write(dir.join("ask.user"), "{{question}}")?;
+    std::fs::write(dir.join("README.md"), "not a template")?;
+
+    let set = PromptSet::from_dir(&dir)?.with_template("bye", Role::assistant, "bye");
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(set.templates.len(), 3);
+
+    let vars = HashMap::from([
+        ("lang".to_string(), "rust".to_string()),
+        ("question".to_string(), "what is a trait?".to_string()),
+    ]);
+
+    let messages = set.render(["intro", "ask"], &vars)?;
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].role, Some(Role::system));
+    assert_eq!(messages[1].role, Some(Role::user));
+
+    let req = crate::proto::chat::ChatCompletionRequest::builder()
+        .with_model("m")
+        .with_messages(messages)
+        .build()?;
+    assert_eq!(req.messages.len(), 2);
+
+    let res = set.render(["nope"], &vars);
+    assert!(matches!(res, Err(Error::UnknownTemplate(ref n)) if n == "nope"));
+
+    Ok(())
+}
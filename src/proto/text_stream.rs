@@ -0,0 +1,338 @@
+use tokio::sync::mpsc::Receiver;
+use tracing::*;
+
+use crate::{error::*, proto::chat::ChatCompletionStreamData};
+
+/// text deltas of first choice, empty deltas skipped, a final chunk repeating
+/// the whole message only yields the part not sent yet, see `StreamChoice::full_message`
+pub fn text_stream(mut rx: Receiver<Result<ChatCompletionStreamData>>) -> Receiver<Result<String>> {
+    let (tx, out) = tokio::sync::mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut sent = String::new();
+
+        while let Some(data) = rx.recv().await {
+            let data = match data {
+                Ok(data) => data,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            };
+
+            let Some(choice) = data.choices.iter().find(|c| c.index == 0) else {
+                continue;
+            };
+            let Some(text) = choice.delta.content.as_ref().map(|c| c.to_text()) else {
+                continue;
+            };
+            let text = match text.strip_prefix(sent.as_str()) {
+                Some(rest) if choice.full_message => rest.to_string(),
+                _ => text,
+            };
+            if text.is_empty() {
+                continue;
+            }
+
+            sent.push_str(&text);
+            if tx.send(Ok(text)).await.is_err() {
+                debug!("text receiver dropped, stop text stream");
+                break;
+            }
+        }
+    });
+
+    out
+}
+
+/// complete sentences of a text stream, e.g. for tts, see `SentenceSplitter`,
+/// the remainder is flushed when `rx` ends, an error is forwarded after it
+pub fn sentence_stream(
+    mut rx: Receiver<Result<String>>,
+    min_len: usize,
+) -> Receiver<Result<String>> {
+    let (tx, out) = tokio::sync::mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut splitter = SentenceSplitter::new(min_len);
+        let mut error = None;
+
+        while let Some(text) = rx.recv().await {
+            let text = match text {
+                Ok(text) => text,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            };
+            for sentence in splitter.push(&text) {
+                if tx.send(Ok(sentence)).await.is_err() {
+                    debug!("sentence receiver dropped, stop sentence stream");
+                    return;
+                }
+            }
+        }
+
+        let rest = splitter.finish().map(Ok).into_iter();
+        for item in rest.chain(error.map(Err)) {
+            if tx.send(item).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    out
+}
+
+/// buffer text pieces and split them into sentences,
+/// ends on `.`, `!`, `?` followed by whitespace, on `。`, `！`, `？` and on a blank line,
+/// a period after a common abbreviation or an initial does not end a sentence
+#[derive(Debug, Clone, Default)]
+pub struct SentenceSplitter {
+    buf: String,
+    /// sentences shorter than `min_len` chars are joined with the next one
+    min_len: usize,
+}
+
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "inc", "ltd", "fig", "approx",
+];
+
+const CLOSERS: &[char] = &['"', '\'', ')', ']', '”', '’', '」', '』', '）', '》'];
+
+impl SentenceSplitter {
+    pub fn new(min_len: usize) -> Self {
+        Self {
+            buf: String::new(),
+            min_len,
+        }
+    }
+
+    /// append `text`, return sentences completed by it, trimmed
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buf.push_str(text);
+
+        let mut sentences = vec![];
+        let mut from = 0;
+        while let Some(end) = find_boundary(&self.buf, from) {
+            let sentence = self.buf[..end].trim();
+            if sentence.is_empty() {
+                self.buf.drain(..end);
+                from = 0;
+                continue;
+            }
+            if sentence.chars().count() < self.min_len {
+                from = end;
+                continue;
+            }
+            sentences.push(sentence.to_string());
+            self.buf.drain(..end);
+            from = 0;
+        }
+        sentences
+    }
+
+    /// text left in buffer, trimmed, `None` if blank
+    pub fn finish(self) -> Option<String> {
+        let rest = self.buf.trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    }
+}
+
+/// end of first sentence in `buf` after `from`, `None` if not sure yet,
+/// e.g. a terminator at the end of `buf` may be followed by more of it
+fn find_boundary(buf: &str, from: usize) -> Option<usize> {
+    let mut i = from;
+    while let Some(c) = buf[i..].chars().next() {
+        let next = i + c.len_utf8();
+        match c {
+            '\n' if buf[next..].starts_with('\n') => return Some(next + 1),
+            '.' | '!' | '?' | '。' | '！' | '？' => {
+                let end = skip_terminators(buf, i);
+                let follow = buf[end..].chars().next()?;
+                let cjk = matches!(c, '。' | '！' | '？');
+                if cjk || follow.is_whitespace() {
+                    let single_period = c == '.' && end == next;
+                    if !(single_period && is_abbreviation(&buf[..i])) {
+                        return Some(end);
+                    }
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i = next;
+    }
+    None
+}
+
+/// end of terminators run starting at `start`, closing quotes and brackets included
+fn skip_terminators(buf: &str, start: usize) -> usize {
+    let rest = &buf[start..];
+    let run = rest
+        .find(|c: char| !matches!(c, '.' | '!' | '?' | '。' | '！' | '？'))
+        .unwrap_or(rest.len());
+    let closers = rest[run..]
+        .find(|c: char| !CLOSERS.contains(&c))
+        .unwrap_or(rest.len() - run);
+    start + run + closers
+}
+
+/// last word of `head` is an abbreviation, an initial, or dotted like `e.g` or `U.S`
+fn is_abbreviation(head: &str) -> bool {
+    let word = head
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(['(', '"', '\'', '“']);
+    let lower = word.to_lowercase();
+    let mut chars = word.chars();
+    let initial = matches!((chars.next(), chars.next()), (Some(c), None) if c.is_alphabetic());
+    initial
+        || (word.contains('.') && !word.ends_with('.'))
+        || ABBREVIATIONS.contains(&lower.as_str())
+}
+
+#[cfg(test)]
+#[test]
+fn test_sentence_splitter_ok() {
+    let mut splitter = SentenceSplitter::new(0);
+    let pieces = [
+        "Hello wor",
+        "ld. Mr. Smi",
+        "th paid $3.",
+        "50 for it, e.g. a pen! Re",
+        "ally? Yes.",
+        "\n\nNew para",
+        "graph without end",
+    ];
+    let mut sentences = vec![];
+    for piece in pieces {
+        sentences.extend(splitter.push(piece));
+    }
+    assert_eq!(
+        sentences,
+        [
+            "Hello world.",
+            "Mr. Smith paid $3.50 for it, e.g. a pen!",
+            "Really?",
+            "Yes.",
+        ]
+    );
+    assert_eq!(
+        splitter.finish().as_deref(),
+        Some("New paragraph without end")
+    );
+
+    // short sentences are joined with the next one
+    let mut splitter = SentenceSplitter::new(8);
+    assert!(splitter.push("Hi. Ok. ").is_empty());
+    assert_eq!(splitter.push("Let us go. "), ["Hi. Ok. Let us go."]);
+    assert_eq!(
+        splitter.push("He said \"stop!\" and left"),
+        ["He said \"stop!\""]
+    );
+    assert_eq!(splitter.finish().as_deref(), Some("and left"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_sentence_splitter_cjk_ok() {
+    let mut splitter = SentenceSplitter::new(0);
+    let mut sentences = vec![];
+    for piece in [
+        "你好",
+        "。今天天气",
+        "很好！你",
+        "呢？",
+        "「好的。」",
+        "再见",
+    ] {
+        sentences.extend(splitter.push(piece));
+    }
+    assert_eq!(
+        sentences,
+        ["你好。", "今天天气很好！", "你呢？", "「好的。」"]
+    );
+    assert_eq!(splitter.finish().as_deref(), Some("再见"));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_sentence_stream_ok() -> Result<()> {
+    use crate::proto::chat::ChatCompletionRequest;
+    use crate::tests::MockServer;
+
+    let chunk = |text: &str| {
+        format!(
+            "data: {{\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{{\"index\":0,\"delta\":{{\"content\":{}}}}}]}}\n\n",
+            serde_json::to_string(text).unwrap()
+        )
+    };
+    let sse = ["", "It is sun", "ny. Dr. Li", " agrees! 天气", "很好。Bye"]
+        .map(chunk)
+        .concat()
+        + "data: [DONE]\n\n";
+
+    let server = MockServer::start(vec![(200, "text/event-stream", sse)]).await;
+    let client = server.client()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "weather?")?;
+    req.stream = Some(true);
+
+    let rx = req.call_stream(&client, None).await?;
+    let mut rx = sentence_stream(text_stream(rx), 0);
+    let mut sentences = vec![];
+    while let Some(sentence) = rx.recv().await {
+        sentences.push(sentence?);
+    }
+
+    assert_eq!(
+        sentences,
+        ["It is sunny.", "Dr. Li agrees!", "天气很好。", "Bye"]
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_text_stream_message_chunks_ok() -> Result<()> {
+    use crate::proto::chat::ChatCompletionRequest;
+    use crate::tests::*;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            "text/event-stream",
+            FASTCHAT_CHAT_STREAM_REPEAT_SSE.to_string(),
+        ),
+        (
+            200,
+            "text/event-stream",
+            OLLAMA_PROXY_CHAT_STREAM_SSE.to_string(),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+
+    let mut req = ChatCompletionRequest::simple("m", "hi")?;
+    req.stream = Some(true);
+
+    let mut texts = vec![];
+    for _ in 0..2 {
+        let mut rx = text_stream(req.call_stream(&client, None).await?);
+        let mut pieces = vec![];
+        while let Some(text) = rx.recv().await {
+            pieces.push(text?);
+        }
+        texts.push(pieces);
+    }
+
+    // repeated increments are kept, a final snapshot adds nothing already sent
+    assert_eq!(texts[0], ["ha", "ha", " ", " ", "ha", "!"]);
+    assert_eq!(texts[1], ["Hello", " world"]);
+
+    Ok(())
+}
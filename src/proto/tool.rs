@@ -1,20 +1,42 @@
 use crate::error::*;
 use serde_with::skip_serializing_none;
 use smart_default::SmartDefault;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::hash::Hash;
 
 #[skip_serializing_none]
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ToolCall {
+    /// position of tool call in stream delta, never sent, ignored by `==` and `hash`
+    #[serde(default, skip_serializing)]
+    pub index: Option<usize>,
     pub id: Option<String>,
     #[serde(rename = "type")]
     pub typ: Option<String>,
     pub function: Function,
 }
 
+// `index` only locates a fragment in stream, a merged tool call equals the non-streamed one
+impl PartialEq for ToolCall {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.typ == other.typ && self.function == other.function
+    }
+}
+
+impl Eq for ToolCall {}
+
+impl Hash for ToolCall {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.typ.hash(state);
+        self.function.hash(state);
+    }
+}
+
 impl From<Function> for ToolCall {
     fn from(f: Function) -> Self {
         ToolCall {
+            index: None,
             id: None,
             typ: Some("function".to_string()),
             function: f,
@@ -26,6 +48,40 @@ impl ToolCall {
     pub fn builder() -> ToolCallBuilder {
         ToolCallBuilder::default()
     }
+
+    /// merge a stream fragment of the same tool call, `id`, `type` and `name` are kept once set,
+    /// `arguments` are concatenated
+    pub fn merge(&mut self, rhs: ToolCall) {
+        let ToolCall {
+            index,
+            id,
+            typ,
+            function,
+        } = rhs;
+
+        if self.index.is_none() {
+            self.index = index;
+        }
+
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            self.id = Some(id);
+        }
+
+        if let Some(typ) = typ.filter(|typ| !typ.is_empty()) {
+            self.typ = Some(typ);
+        }
+
+        if let Some(name) = function.name.filter(|name| !name.is_empty()) {
+            self.function.name = Some(name);
+        }
+
+        if let Some(arguments) = function.arguments {
+            match self.function.arguments.as_mut() {
+                Some(lhs) => lhs.push_str(&arguments),
+                None => self.function.arguments = Some(arguments),
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, SmartDefault)]
@@ -37,6 +93,22 @@ pub struct ToolCallBuilder {
 }
 
 impl ToolCallBuilder {
+    /// declare a function tool in one expression, `parameters` fills an empty object schema, e.g.
+    /// `ToolCallBuilder::function("add", "add numbers", |p| p.add_param("a", ParameterType::number, "lhs", true))`
+    pub fn function(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: impl FnOnce(ParametersBuilder) -> ParametersBuilder,
+    ) -> Result<ToolCall> {
+        let function = Function::builder()
+            .with_name(name)
+            .with_description(description)
+            .with_parameters(parameters(Parameters::builder()).build()?)
+            .build()?;
+
+        ToolCall::builder().with_function(function).build()
+    }
+
     pub fn with_function(mut self, function: impl Into<Function>) -> Self {
         self.function = Some(function.into());
         self
@@ -47,6 +119,7 @@ impl ToolCallBuilder {
         let typ = typ.ok_or(Error::ToolCallBuild)?;
         let function = function.ok_or(Error::ToolCallBuild)?;
         Ok(ToolCall {
+            index: None,
             id,
             typ: Some(typ),
             function,
@@ -54,8 +127,67 @@ impl ToolCallBuilder {
     }
 }
 
+/// `tool_choice` of request, `"none"`, `"auto"`, `"required"`
+/// or `{"type": "function", "function": {"name": "..."}}` on the wire
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "ToolChoiceWire", into = "ToolChoiceWire")]
+pub enum ToolChoice {
+    None,
+    Auto,
+    /// model must call at least one tool
+    Required,
+    /// model must call function of name
+    Function(String),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum ToolChoiceWire {
+    Mode(String),
+    Function {
+        #[serde(rename = "type")]
+        typ: String,
+        function: ToolChoiceFunction,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ToolChoiceFunction {
+    name: String,
+}
+
+impl TryFrom<ToolChoiceWire> for ToolChoice {
+    type Error = Error;
+
+    fn try_from(value: ToolChoiceWire) -> Result<Self> {
+        match value {
+            ToolChoiceWire::Mode(mode) => match mode.as_str() {
+                "none" => Ok(ToolChoice::None),
+                "auto" => Ok(ToolChoice::Auto),
+                "required" => Ok(ToolChoice::Required),
+                _ => Err(Error::UnknownVariant("ToolChoice", mode)),
+            },
+            ToolChoiceWire::Function { function, .. } => Ok(ToolChoice::Function(function.name)),
+        }
+    }
+}
+
+impl From<ToolChoice> for ToolChoiceWire {
+    fn from(value: ToolChoice) -> Self {
+        match value {
+            ToolChoice::None => ToolChoiceWire::Mode("none".into()),
+            ToolChoice::Auto => ToolChoiceWire::Mode("auto".into()),
+            ToolChoice::Required => ToolChoiceWire::Mode("required".into()),
+            ToolChoice::Function(name) => ToolChoiceWire::Function {
+                typ: "function".into(),
+                function: ToolChoiceFunction { name },
+            },
+        }
+    }
+}
+
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Function {
     pub name: Option<String>,
     pub description: Option<String>,
@@ -94,7 +226,7 @@ impl Function {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, SmartDefault)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, SmartDefault)]
 pub struct FunctionBuilder {
     pub name: Option<String>,
     pub description: Option<String>,
@@ -138,7 +270,7 @@ impl FunctionBuilder {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[allow(non_camel_case_types)]
 #[serde(untagged)]
 pub enum Argument {
@@ -147,7 +279,7 @@ pub enum Argument {
     integer(i64),
     boolean(bool),
     array(Vec<Argument>),
-    object(HashMap<String, Argument>),
+    object(BTreeMap<String, Argument>),
 }
 
 macro_rules! impl_argument_as_value {
@@ -168,16 +300,20 @@ impl_argument_as_value!(as_number, number, f64);
 impl_argument_as_value!(as_integer, integer, i64);
 impl_argument_as_value!(as_boolean, boolean, bool);
 impl_argument_as_value!(as_array, array, Vec<Argument>);
-impl_argument_as_value!(as_object, object, HashMap<String, Argument>);
+impl_argument_as_value!(as_object, object, BTreeMap<String, Argument>);
 
+/// properties are kept sorted by name, so serialization is deterministic
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Parameters {
     #[serde(rename = "type")]
     pub typ: String,
-    pub properties: HashMap<String, ParameterProperty>,
+    pub properties: BTreeMap<String, ParameterProperty>,
     #[serde(default)]
     pub required: Vec<String>,
+    /// `false` for strict schemas
+    #[serde(rename = "additionalProperties")]
+    pub additional_properties: Option<bool>,
 }
 
 impl Parameters {
@@ -190,8 +326,9 @@ impl Parameters {
 pub struct ParametersBuilder {
     #[default(Some("object".to_string()))]
     typ: Option<String>,
-    properties: HashMap<String, ParameterProperty>,
+    properties: BTreeMap<String, ParameterProperty>,
     required: Vec<String>,
+    additional_properties: Option<bool>,
 }
 
 impl ParametersBuilder {
@@ -209,11 +346,38 @@ impl ParametersBuilder {
         self
     }
 
+    /// shortcut of `add_property` for a plain typed property
+    pub fn add_param(
+        self,
+        name: impl Into<String>,
+        typ: ParameterType,
+        description: impl Into<String>,
+        required: bool,
+    ) -> Self {
+        let property = ParameterProperty {
+            typ: Some(typ),
+            description: description.into(),
+            enum_values: None,
+            items: None,
+            properties: None,
+            required: None,
+            additional_properties: None,
+        };
+        self.add_property(name, property, required)
+    }
+
+    /// `false` to reject properties not listed, required by strict structured outputs
+    pub fn with_additional_properties(mut self, additional_properties: bool) -> Self {
+        self.additional_properties = Some(additional_properties);
+        self
+    }
+
     pub fn build(self) -> Result<Parameters> {
         let Self {
             typ,
             properties,
             required,
+            additional_properties,
         } = self;
 
         let typ = typ.ok_or(Error::ToolCallParametersBuild)?;
@@ -222,17 +386,28 @@ impl ParametersBuilder {
             typ,
             properties,
             required,
+            additional_properties,
         })
     }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct ParameterProperty {
     #[serde(rename = "type")]
     pub typ: Option<ParameterType>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
-    pub items: Option<HashMap<String, String>>,
+    #[serde(rename = "enum")]
+    pub enum_values: Option<Vec<String>>,
+    /// element of `array` property
+    pub items: Option<Box<ParameterProperty>>,
+    /// fields of `object` property
+    pub properties: Option<BTreeMap<String, ParameterProperty>>,
+    pub required: Option<Vec<String>>,
+    /// `false` for strict schemas
+    #[serde(rename = "additionalProperties")]
+    pub additional_properties: Option<bool>,
 }
 
 impl ParameterProperty {
@@ -245,7 +420,11 @@ impl ParameterProperty {
 pub struct ParameterPropertyBuilder {
     typ: Option<ParameterType>,
     description: Option<String>,
-    items: Option<HashMap<String, String>>,
+    enum_values: Option<Vec<String>>,
+    items: Option<ParameterProperty>,
+    properties: Option<BTreeMap<String, ParameterProperty>>,
+    required: Option<Vec<String>>,
+    additional_properties: Option<bool>,
 }
 
 impl ParameterPropertyBuilder {
@@ -259,15 +438,61 @@ impl ParameterPropertyBuilder {
         self
     }
 
+    pub fn with_enum(mut self, values: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.enum_values = Some(values.into_iter().map(|v| v.into()).collect());
+        self
+    }
+
+    /// element property of an `array` property
+    pub fn with_array_items(mut self, items: ParameterProperty) -> Self {
+        self.items = Some(items);
+        self
+    }
+
+    /// `items` as plain string map, only `type` and `description` keys are kept
+    #[deprecated(note = "use `with_array_items` with a nested `ParameterProperty`")]
     pub fn with_items(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        if self.items.is_none() {
-            self.items = Some(HashMap::new());
+        let (key, value) = (key.into(), value.into());
+        let items = self.items.get_or_insert(ParameterProperty {
+            typ: None,
+            description: String::new(),
+            enum_values: None,
+            items: None,
+            properties: None,
+            required: None,
+            additional_properties: None,
+        });
+        match key.as_str() {
+            "type" => match value.parse() {
+                Ok(typ) => items.typ = Some(typ),
+                Err(_) => tracing::warn!(%value, "unknown items type, ignored"),
+            },
+            "description" => items.description = value,
+            _ => tracing::warn!(%key, "unsupported items key, ignored"),
         }
+        self
+    }
 
-        self.items
-            .as_mut()
-            .unwrap()
-            .insert(key.into(), value.into());
+    /// field of an `object` property
+    pub fn add_property(
+        mut self,
+        name: impl Into<String>,
+        property: ParameterProperty,
+        required: bool,
+    ) -> Self {
+        let name = name.into();
+        self.properties
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.clone(), property);
+        if required {
+            self.required.get_or_insert_with(Vec::new).push(name);
+        }
+        self
+    }
+
+    /// `false` to reject fields not listed in an `object` property
+    pub fn with_additional_properties(mut self, additional_properties: bool) -> Self {
+        self.additional_properties = Some(additional_properties);
         self
     }
 
@@ -275,7 +500,11 @@ impl ParameterPropertyBuilder {
         let Self {
             typ,
             description,
+            enum_values,
             items,
+            properties,
+            required,
+            additional_properties,
         } = self;
 
         let typ = typ.ok_or(Error::ToolCallParametersBuild)?;
@@ -284,13 +513,17 @@ impl ParameterPropertyBuilder {
         Ok(ParameterProperty {
             typ: Some(typ),
             description,
-            items,
+            enum_values,
+            items: items.map(Box::new),
+            properties,
+            required,
+            additional_properties,
         })
     }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum ParameterType {
     string,
@@ -300,3 +533,225 @@ pub enum ParameterType {
     array,
     object,
 }
+
+impl_wire_str!(
+    ParameterType,
+    string => "string",
+    number => "number",
+    integer => "integer",
+    boolean => "boolean",
+    array => "array",
+    object => "object",
+);
+
+#[cfg(test)]
+#[test]
+fn test_tool_serde_round_trip_ok() -> Result<()> {
+    use crate::tests::*;
+
+    for json in [STEPFUN_CHAT_TOOLS_REQUEST_JSON, KIMI_CHAT_TOOL_JSON] {
+        let lhs: Vec<ToolCall> = serde_json::from_str(json)?;
+        let rhs: Vec<ToolCall> = serde_json::from_str(&serde_json::to_string(&lhs)?)?;
+        assert_eq!(lhs, rhs);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_tool_call_eq_ignores_index_ok() -> Result<()> {
+    let streamed: ToolCall = serde_json::from_str(
+        r#"{"index":0,"id":"call_1","type":"function","function":{"name":"f","arguments":"{}"}}"#,
+    )?;
+    let plain: ToolCall = serde_json::from_str(
+        r#"{"id":"call_1","type":"function","function":{"name":"f","arguments":"{}"}}"#,
+    )?;
+    assert_eq!(streamed.index, Some(0));
+    assert_eq!(streamed, plain);
+    assert_eq!(std::collections::HashSet::from([streamed, plain]).len(), 1);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_tool_call_closure_builder_ok() -> Result<()> {
+    let manual = ToolCall::builder()
+        .with_function(
+            Function::builder()
+                .with_name("add_number")
+                .with_description("add two numbers")
+                .with_parameters(
+                    Parameters::builder()
+                        .add_property(
+                            "a",
+                            ParameterProperty::builder()
+                                .with_description("number 1 in 2 numbers")
+                                .with_type(ParameterType::number)
+                                .build()?,
+                            true,
+                        )
+                        .add_property(
+                            "b",
+                            ParameterProperty::builder()
+                                .with_description("number 2 in 2 numbers")
+                                .with_type(ParameterType::number)
+                                .build()?,
+                            true,
+                        )
+                        .build()?,
+                )
+                .build()?,
+        )
+        .build()?;
+
+    let closure = ToolCallBuilder::function("add_number", "add two numbers", |p| {
+        p.add_param("a", ParameterType::number, "number 1 in 2 numbers", true)
+            .add_param("b", ParameterType::number, "number 2 in 2 numbers", true)
+    })?;
+
+    assert_eq!(closure, manual);
+    assert_eq!(
+        serde_json::to_value(&closure)?,
+        serde_json::to_value(&manual)?
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_parameter_type_str_ok() -> Result<()> {
+    use ParameterType::*;
+
+    for typ in [string, number, integer, boolean, array, object] {
+        assert_eq!(serde_json::to_value(&typ)?, typ.as_str());
+        assert_eq!(typ.to_string().parse::<ParameterType>()?, typ);
+    }
+
+    assert!("float".parse::<ParameterType>().is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_parameter_array_of_object_ok() -> Result<()> {
+    let item = ParameterProperty::builder()
+        .with_type(ParameterType::object)
+        .with_description("a line item")
+        .add_property(
+            "sku",
+            ParameterProperty::builder()
+                .with_type(ParameterType::string)
+                .with_description("product id")
+                .build()?,
+            true,
+        )
+        .add_property(
+            "unit",
+            ParameterProperty::builder()
+                .with_type(ParameterType::string)
+                .with_description("unit of quantity")
+                .with_enum(["kg", "piece"])
+                .build()?,
+            false,
+        )
+        .build()?;
+
+    let parameters = Parameters::builder()
+        .add_property(
+            "items",
+            ParameterProperty::builder()
+                .with_type(ParameterType::array)
+                .with_description("items of order")
+                .with_array_items(item)
+                .build()?,
+            true,
+        )
+        .build()?;
+
+    assert_eq!(
+        serde_json::to_value(&parameters)?,
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "items": {
+                    "type": "array",
+                    "description": "items of order",
+                    "items": {
+                        "type": "object",
+                        "description": "a line item",
+                        "properties": {
+                            "sku": {"type": "string", "description": "product id"},
+                            "unit": {"type": "string", "description": "unit of quantity", "enum": ["kg", "piece"]}
+                        },
+                        "required": ["sku"]
+                    }
+                }
+            },
+            "required": ["items"]
+        })
+    );
+
+    // legacy `items` map still works and parses
+    #[allow(deprecated)]
+    let legacy = ParameterProperty::builder()
+        .with_type(ParameterType::array)
+        .with_description("numbers")
+        .with_items("type", "number")
+        .build()?;
+    let json =
+        serde_json::json!({"type": "array", "description": "numbers", "items": {"type": "number"}});
+    assert_eq!(serde_json::to_value(&legacy)?, json);
+    assert_eq!(serde_json::from_value::<ParameterProperty>(json)?, legacy);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_parameters_strict_schema_ok() -> Result<()> {
+    let parameters = Parameters::builder()
+        .add_property(
+            "location",
+            ParameterProperty::builder()
+                .with_type(ParameterType::object)
+                .with_description("where")
+                .add_property(
+                    "city",
+                    ParameterProperty::builder()
+                        .with_type(ParameterType::string)
+                        .with_description("city name")
+                        .build()?,
+                    true,
+                )
+                .with_additional_properties(false)
+                .build()?,
+            true,
+        )
+        .with_additional_properties(false)
+        .build()?;
+
+    let json = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "location": {
+                "type": "object",
+                "description": "where",
+                "properties": {
+                    "city": {"type": "string", "description": "city name"}
+                },
+                "required": ["city"],
+                "additionalProperties": false
+            }
+        },
+        "required": ["location"],
+        "additionalProperties": false
+    });
+    assert_eq!(serde_json::to_value(&parameters)?, json);
+    assert_eq!(serde_json::from_value::<Parameters>(json)?, parameters);
+
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use http::Method;
+use reqwest::{Body, Response};
+use tokio_util::bytes::Bytes;
+use tracing::*;
+
+use crate::error::*;
+
+/// recorded request/response pairs, persisted as json
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Recording {
+    pub exchanges: Vec<RecordedExchange>,
+}
+
+/// a single call, matched on replay by `method`, `endpoint` and `request_hash`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedExchange {
+    pub method: String,
+    /// uri relative to base url, e.g. `chat/completions`
+    pub endpoint: String,
+    /// stable hash of request body, see `request_hash`
+    pub request_hash: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// response body chunks as received, replayed one by one
+    pub frames: Vec<RecordedFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedFrame {
+    /// elapsed since response headers received
+    pub offset_ms: u64,
+    /// raw bytes of chunk, base64 in json, so binary bodies and utf-8 split across chunks survive
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(&base64::engine::general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(de)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(s)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// fnv-1a of request body, stable across rust versions so recordings stay valid
+pub fn request_hash(body: &[u8]) -> String {
+    format!("{:016x}", crate::cache::fnv1a(body))
+}
+
+/// write every call of a client into a json file, see `ClientBuilder::with_recorder`
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    recording: Mutex<Recording>,
+}
+
+impl Recorder {
+    /// file at `path` is rewritten after each finished response
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            recording: Mutex::new(Recording::default()),
+        }
+    }
+
+    /// everything recorded so far
+    pub fn recording(&self) -> Recording {
+        self.recording.lock().expect("recorder lock").clone()
+    }
+
+    /// pass `rep` through, its body is recorded once fully read or dropped
+    pub(crate) fn record(
+        self: &Arc<Self>,
+        method: &Method,
+        endpoint: &str,
+        body: &[u8],
+        rep: Response,
+    ) -> Result<Response> {
+        let status = rep.status();
+        let headers = rep.headers().clone();
+
+        let exchange = RecordedExchange {
+            method: method.to_string(),
+            endpoint: endpoint.to_string(),
+            request_hash: request_hash(body),
+            status: status.as_u16(),
+            content_type: headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+            frames: vec![],
+        };
+
+        let stream = RecordingStream {
+            inner: Box::pin(rep.bytes_stream()),
+            started: Instant::now(),
+            exchange: Some(exchange),
+            recorder: self.clone(),
+        };
+
+        let mut builder = http::Response::builder().status(status);
+        for (k, v) in headers.iter() {
+            builder = builder.header(k, v);
+        }
+
+        Ok(builder
+            .body(Body::wrap_stream(stream))
+            .map_err(|_| Error::Record)?
+            .into())
+    }
+
+    fn finish(&self, exchange: RecordedExchange) {
+        let mut recording = self.recording.lock().expect("recorder lock");
+        trace!(endpoint = %exchange.endpoint, frames = %exchange.frames.len(), "exchange recorded");
+        recording.exchanges.push(exchange);
+
+        let res = serde_json::to_vec_pretty(&*recording)
+            .map_err(Error::from)
+            .and_then(|json| std::fs::write(&self.path, json).map_err(Error::from));
+
+        if let Err(e) = res {
+            error!(path = ?self.path, "failed to save recording: {:?}", e);
+        }
+    }
+}
+
+/// response body passing through, collecting frames
+struct RecordingStream {
+    inner: Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>,
+    started: Instant,
+    exchange: Option<RecordedExchange>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingStream {
+    fn finish(&mut self) {
+        if let Some(exchange) = self.exchange.take() {
+            self.recorder.finish(exchange);
+        }
+    }
+}
+
+impl Stream for RecordingStream {
+    type Item = reqwest::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let next = self.inner.poll_next_unpin(cx);
+        match &next {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let offset_ms = self.started.elapsed().as_millis() as u64;
+                if let Some(exchange) = self.exchange.as_mut() {
+                    exchange.frames.push(RecordedFrame {
+                        offset_ms,
+                        data: chunk.to_vec(),
+                    });
+                }
+            }
+            Poll::Ready(_) => self.finish(),
+            Poll::Pending => {}
+        }
+        next
+    }
+}
+
+impl Drop for RecordingStream {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// serve recorded responses instead of calling api, see `ClientBuilder::with_replayer`
+#[derive(Debug)]
+pub struct Replayer {
+    exchanges: Mutex<Vec<RecordedExchange>>,
+    timing: bool,
+}
+
+impl Replayer {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            exchanges: Mutex::new(recording.exchanges),
+            timing: false,
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let recording = crate::json::from_slice(&std::fs::read(path.as_ref())?)?;
+        Ok(Self::new(recording))
+    }
+
+    /// sleep between frames as recorded, default replays as fast as possible
+    pub fn with_timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// serve first unused exchange matching the call, `Error::ReplayUnmatched` if none
+    pub(crate) fn replay(&self, method: &Method, endpoint: &str, body: &[u8]) -> Result<Response> {
+        let hash = request_hash(body);
+
+        let exchange = {
+            let mut exchanges = self.exchanges.lock().expect("replayer lock");
+            let pos = exchanges.iter().position(|e| {
+                e.method == method.as_str() && e.endpoint == endpoint && e.request_hash == hash
+            });
+            match pos {
+                Some(pos) => exchanges.remove(pos),
+                None => {
+                    error!(%method, %endpoint, %hash, "no recorded exchange matched");
+                    return Err(Error::ReplayUnmatched(
+                        method.to_string(),
+                        endpoint.to_string(),
+                    ));
+                }
+            }
+        };
+
+        trace!(%method, %endpoint, frames = %exchange.frames.len(), "replay exchange");
+
+        let timing = self.timing;
+        let mut last = 0;
+        let frames = futures::stream::iter(exchange.frames).then(move |frame| {
+            let pause = frame.offset_ms.saturating_sub(last);
+            last = frame.offset_ms;
+            async move {
+                if timing && pause > 0 {
+                    tokio::time::sleep(Duration::from_millis(pause)).await;
+                }
+                Ok::<_, std::io::Error>(Bytes::from(frame.data))
+            }
+        });
+
+        let mut builder = http::Response::builder().status(exchange.status);
+        if let Some(content_type) = exchange.content_type.as_ref() {
+            builder = builder.header(http::header::CONTENT_TYPE, content_type);
+        }
+
+        Ok(builder
+            .body(Body::wrap_stream(frames))
+            .map_err(|_| Error::Record)?
+            .into())
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_record_replay_ok() -> Result<()> {
+    use crate::client::Client;
+    use crate::proto::chat::*;
+    use crate::tests::MockServer;
+
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}]}"#;
+    let sse = [
+        r#"data: {"id":"2","choices":[{"index":0,"delta":{"role":"assistant","content":"he"}}]}"#,
+        r#"data: {"choices":[{"index":0,"delta":{"content":"llo"},"finish_reason":"stop"}]}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .to_vec();
+
+    let server = MockServer::start(vec![(200, "application/json", chat.to_string())]).await;
+    let stream_server = MockServer::start_chunked("text/event-stream", sse).await;
+
+    let path =
+        std::env::temp_dir().join(format!("openai-ng-recording-{}.json", std::process::id()));
+
+    let req = ChatCompletionRequest::simple("m", "answer")?;
+    let mut stream_req = req.clone();
+    stream_req.stream = Some(true);
+
+    async fn collect(req: &ChatCompletionRequest, client: &Client) -> Result<String> {
+        let mut rx = req.call_stream(client, None).await?;
+        let mut rep = ChatCompletionResponse::default();
+        while let Some(data) = rx.recv().await {
+            rep.merge_delta(data?);
+        }
+        rep.text().ok_or(Error::NoContent)
+    }
+
+    let recorder = Arc::new(Recorder::new(&path));
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_recorder(recorder.clone())?
+        .build()?;
+    assert_eq!(
+        req.call_once(&client, None).await?.text().as_deref(),
+        Some("42")
+    );
+
+    let client = Client::builder()
+        .with_base_url(&stream_server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_recorder(recorder.clone())?
+        .build()?;
+    assert_eq!(collect(&stream_req, &client).await?, "hello");
+
+    let recording = recorder.recording();
+    assert_eq!(recording.exchanges.len(), 2);
+    assert!(recording.exchanges[1].frames.len() > 1);
+
+    let replayer = Replayer::from_file(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(replayer.exchanges.lock().expect("lock").len(), 2);
+
+    let client = Client::builder()
+        .with_base_url("http://127.0.0.1:9")?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_replayer(Arc::new(replayer))?
+        .build()?;
+
+    assert_eq!(collect(&stream_req, &client).await?, "hello");
+    assert_eq!(
+        req.call_once(&client, None).await?.text().as_deref(),
+        Some("42")
+    );
+
+    let res = req.call_once(&client, None).await;
+    assert!(
+        matches!(res, Err(Error::ReplayUnmatched(ref m, ref e)) if m == "POST" && e == "chat/completions")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_record_raw_frames_and_uploads_ok() -> Result<()> {
+    use crate::client::Client;
+    use crate::proto::file::*;
+    use crate::tests::MockServer;
+
+    // a cjk char split across chunks and a binary chunk are kept byte for byte
+    let path = std::env::temp_dir().join(format!(
+        "openai-ng-recording-raw-{}.json",
+        std::process::id()
+    ));
+    let recorder = Arc::new(Recorder::new(&path));
+    let text = "你好".as_bytes();
+    let chunks = vec![
+        Bytes::copy_from_slice(&text[..2]),
+        Bytes::copy_from_slice(&text[2..]),
+        Bytes::from_static(&[0xff, 0x00, 0xd8]),
+    ];
+    let stream = RecordingStream {
+        inner: Box::pin(futures::stream::iter(chunks.clone().into_iter().map(Ok))),
+        started: Instant::now(),
+        exchange: Some(RecordedExchange {
+            method: "POST".to_string(),
+            endpoint: "audio/speech".to_string(),
+            request_hash: request_hash(b""),
+            status: 200,
+            content_type: None,
+            frames: vec![],
+        }),
+        recorder: recorder.clone(),
+    };
+    let _ = stream.collect::<Vec<_>>().await;
+
+    let recording: Recording = serde_json::from_slice(&std::fs::read(&path)?)?;
+    std::fs::remove_file(&path)?;
+    let frames = recording.exchanges[0]
+        .frames
+        .iter()
+        .map(|f| Bytes::from(f.data.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(frames, chunks);
+
+    // uploads are keyed by form fields, not by an empty body
+    let file = std::env::temp_dir().join("openai-ng-record-upload.jsonl");
+    tokio::fs::write(&file, b"{}\n").await?;
+    let uploaded = |purpose: &str| {
+        format!(
+            r#"{{"id":"file-{}","object":"file","bytes":3,"created_at":1,"filename":"openai-ng-record-upload.jsonl","purpose":"{}"}}"#,
+            purpose, purpose
+        )
+    };
+    let server = MockServer::start(vec![
+        (200, "application/json", uploaded("batch")),
+        (200, "application/json", uploaded("fine-tune")),
+    ])
+    .await;
+    let upload = |purpose: FilePurpose| {
+        FileUploadRequest::builder()
+            .with_source(file.clone())
+            .with_purpose(purpose)
+            .build()
+    };
+
+    let recorder = Arc::new(Recorder::new(&path));
+    let client = Client::builder()
+        .with_base_url(&server.base_url)?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_recorder(recorder.clone())?
+        .build()?;
+    upload(FilePurpose::Batch)?.call(&client, None).await?;
+    upload(FilePurpose::FineTune)?.call(&client, None).await?;
+    std::fs::remove_file(&path)?;
+
+    let recording = recorder.recording();
+    assert_ne!(
+        recording.exchanges[0].request_hash,
+        recording.exchanges[1].request_hash
+    );
+
+    let client = Client::builder()
+        .with_base_url("http://127.0.0.1:9")?
+        .with_version("v1")?
+        .with_key("mock key")?
+        .with_replayer(Arc::new(Replayer::new(recording)))?
+        .build()?;
+    let rep = upload(FilePurpose::FineTune)?.call(&client, None).await?;
+    assert_eq!(rep.id, "file-fine-tune");
+    let rep = upload(FilePurpose::Batch)?.call(&client, None).await?;
+    assert_eq!(rep.id, "file-batch");
+
+    let _ = tokio::fs::remove_file(&file).await;
+
+    Ok(())
+}
@@ -1,9 +1,441 @@
-pub const STEPFUN_CHAT_COMPLETION_REQUEST_JSON: &'static str =
+pub const STEPFUN_CHAT_COMPLETION_REQUEST_JSON: &str =
     include_str!("stepfun-chat-completion-request.json");
 
-pub const STEPFUN_CHAT_TOOLS_REQUEST_JSON: &'static str = include_str!("stepfun-chat-tools.json");
+pub const STEPFUN_CHAT_TOOLS_REQUEST_JSON: &str = include_str!("stepfun-chat-tools.json");
 
-pub const STEPFUN_CHAT_TOOLS_RESPONSE_JSON: &'static str =
+pub const STEPFUN_CHAT_TOOLS_RESPONSE_JSON: &str =
     include_str!("stepfun-chat-completion-tools-reponse.json");
 
-pub const KIMI_CHAT_TOOL_JSON: &'static str = include_str!("kimi-tools.json");
+pub const STEPFUN_CHAT_STREAM_RESPONSE_JSON: &str =
+    include_str!("stepfun-chat-stream-response.json");
+
+pub const OPENROUTER_ERROR_STRING_JSON: &str = include_str!("openrouter-error-string.json");
+
+pub const OPENROUTER_ERROR_OBJECT_JSON: &str = include_str!("openrouter-error-object.json");
+
+pub const KIMI_CHAT_TOOL_JSON: &str = include_str!("kimi-tools.json");
+
+pub const OPENAI_MODELS_JSON: &str = include_str!("openai-models.json");
+
+pub const KIMI_MODELS_JSON: &str = include_str!("kimi-models.json");
+
+pub const STEPFUN_MODELS_JSON: &str = include_str!("stepfun-models.json");
+
+pub const OPENAI_FILES_JSON: &str = include_str!("openai-files.json");
+
+pub const KIMI_FILES_JSON: &str = include_str!("kimi-files.json");
+
+pub const OPENAI_IMAGE_GENERATION_JSON: &str = include_str!("openai-image-generation.json");
+
+pub const STEPFUN_IMAGE_GENERATION_JSON: &str = include_str!("stepfun-image-generation.json");
+
+pub const AZURE_CHAT_CONTENT_FILTER_JSON: &str = include_str!("azure-chat-content-filter.json");
+
+pub const AZURE_CHAT_CONTENT_FILTER_LEGACY_JSON: &str =
+    include_str!("azure-chat-content-filter-legacy.json");
+
+pub const AZURE_CHAT_CONTENT_FILTER_BLOCKLIST_JSON: &str =
+    include_str!("azure-chat-content-filter-blocklist.json");
+
+pub const FASTCHAT_CHAT_STREAM_SSE: &str = include_str!("fastchat-chat-stream.txt");
+
+pub const FASTCHAT_CHAT_STREAM_REPEAT_SSE: &str = include_str!("fastchat-chat-stream-repeat.txt");
+
+pub const OLLAMA_PROXY_CHAT_STREAM_SSE: &str = include_str!("ollama-proxy-chat-stream.txt");
+
+pub const OPENAI_CHAT_LOGPROBS_JSON: &str = include_str!("openai-chat-logprobs.json");
+
+pub const OPENAI_CHAT_LOGPROBS_STREAM_SSE: &str = include_str!("openai-chat-logprobs-stream.txt");
+
+pub const VLLM_CHAT_STOP_REASON_JSON: &str = include_str!("vllm-chat-stop-reason.json");
+
+use crate::client::Client;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// tests touching process env vars must hold this lock
+pub static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// save env vars on creation, restore them on drop, so live tests keep the developer's env
+pub struct EnvGuard(Vec<(&'static str, Option<String>)>);
+
+impl EnvGuard {
+    pub fn save(names: &[&'static str]) -> Self {
+        Self(
+            names
+                .iter()
+                .map(|name| (*name, std::env::var(name).ok()))
+                .collect(),
+        )
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (name, value) in self.0.iter() {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
+
+/// minimal http server replying canned responses in order, one per connection
+pub struct MockServer {
+    pub base_url: String,
+    pub hits: Arc<AtomicUsize>,
+    pub paths: Arc<Mutex<Vec<String>>>,
+    /// raw requests, head and body, in order
+    pub requests: Arc<Mutex<Vec<String>>>,
+    /// connections found closed by client while still sending
+    pub closed: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<(u16, &'static str, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+
+        tokio::spawn(async move {
+            for (status, content_type, body) in responses {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                hits_.fetch_add(1, Ordering::SeqCst);
+
+                let (path, request) = drain_request(&mut socket).await;
+                paths_.lock().expect("lock").push(path);
+                requests_.lock().expect("lock").push(request);
+
+                let head = format!(
+                    "HTTP/1.1 {} MOCK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    status,
+                    content_type,
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// reply a single chunked response which sends `body` then stalls for `pause`
+    pub async fn start_stalled(content_type: &'static str, body: String, pause: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            hits_.fetch_add(1, Ordering::SeqCst);
+
+            let (path, request) = drain_request(&mut socket).await;
+            paths_.lock().expect("lock").push(path);
+            requests_.lock().expect("lock").push(request);
+
+            let head = format!(
+                "HTTP/1.1 200 MOCK\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\n\r\n",
+                content_type
+            );
+            let chunk = format!("{:x}\r\n{}\r\n", body.len(), body);
+            let _ = socket.write_all(head.as_bytes()).await;
+            let _ = socket.write_all(chunk.as_bytes()).await;
+            let _ = socket.flush().await;
+            tokio::time::sleep(pause).await;
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+            let _ = socket.shutdown().await;
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// accept a single request and hold the connection open, never replying
+    pub async fn start_silent() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            hits_.fetch_add(1, Ordering::SeqCst);
+
+            let (path, request) = drain_request(&mut socket).await;
+            paths_.lock().expect("lock").push(path);
+            requests_.lock().expect("lock").push(request);
+
+            let mut buf = [0u8; 64];
+            while let Ok(n) = socket.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// reply a single chunked response, flushing each of `chunks` separately
+    pub async fn start_chunked(content_type: &'static str, chunks: Vec<String>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            hits_.fetch_add(1, Ordering::SeqCst);
+
+            let (path, request) = drain_request(&mut socket).await;
+            paths_.lock().expect("lock").push(path);
+            requests_.lock().expect("lock").push(request);
+
+            let head = format!(
+                "HTTP/1.1 200 MOCK\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\n\r\n",
+                content_type
+            );
+            let _ = socket.write_all(head.as_bytes()).await;
+            for chunk in chunks {
+                let chunk = format!("{:x}\r\n{}\r\n", chunk.len(), chunk);
+                let _ = socket.write_all(chunk.as_bytes()).await;
+                let _ = socket.flush().await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+            let _ = socket.shutdown().await;
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// reply a single chunked response repeating `chunk` every 10ms until client hangs up
+    pub async fn start_endless(content_type: &'static str, chunk: String) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+        let closed = Arc::new(AtomicUsize::new(0));
+        let closed_ = closed.clone();
+
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            hits_.fetch_add(1, Ordering::SeqCst);
+
+            let (path, request) = drain_request(&mut socket).await;
+            paths_.lock().expect("lock").push(path);
+            requests_.lock().expect("lock").push(request);
+
+            let head = format!(
+                "HTTP/1.1 200 MOCK\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\n\r\n",
+                content_type
+            );
+            let _ = socket.write_all(head.as_bytes()).await;
+            let chunk = format!("{:x}\r\n{}\r\n", chunk.len(), chunk);
+            loop {
+                let sent = socket.write_all(chunk.as_bytes()).await;
+                if sent.is_err() || socket.flush().await.is_err() {
+                    closed_.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed,
+        }
+    }
+
+    /// serve http/2 with prior knowledge (h2c) on a single connection, replying canned responses in order
+    pub async fn start_h2(responses: Vec<(u16, &'static str, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_ = hits.clone();
+        let paths = Arc::new(Mutex::new(vec![]));
+        let paths_ = paths.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_ = requests.clone();
+
+        tokio::spawn(async move {
+            let Ok((socket, _)) = listener.accept().await else {
+                return;
+            };
+            let Ok(mut conn) = h2::server::handshake(socket).await else {
+                return;
+            };
+            let mut responses = responses.into_iter();
+            while let Some(Ok((req, mut respond))) = conn.accept().await {
+                hits_.fetch_add(1, Ordering::SeqCst);
+                paths_
+                    .lock()
+                    .expect("lock")
+                    .push(req.uri().path().to_string());
+                let request = req
+                    .headers()
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}\r\n", k, v.to_str().unwrap_or_default()))
+                    .collect::<String>();
+                requests_.lock().expect("lock").push(request + "\r\n");
+
+                let Some((status, content_type, body)) = responses.next() else {
+                    break;
+                };
+                let rep = http::Response::builder()
+                    .status(status)
+                    .header("content-type", content_type)
+                    .body(())
+                    .expect("response");
+                if let Ok(mut send) = respond.send_response(rep, false) {
+                    let _ = send.send_data(body.into(), true);
+                }
+            }
+        });
+
+        Self {
+            base_url,
+            hits,
+            paths,
+            requests,
+            closed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn client(&self) -> crate::error::Result<Client> {
+        Client::builder()
+            .with_base_url(&self.base_url)?
+            .with_version("v1")?
+            .with_key("mock key")?
+            .build()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    pub fn closed(&self) -> usize {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// paths of received requests, in order
+    pub fn paths(&self) -> Vec<String> {
+        self.paths.lock().expect("lock").clone()
+    }
+
+    /// value of header `name` in request of `index`, case insensitive
+    pub fn header(&self, index: usize, name: &str) -> Option<String> {
+        let requests = self.requests.lock().expect("lock");
+        let (head, _) = requests.get(index)?.split_once("\r\n\r\n")?;
+        head.lines().find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            k.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| v.trim().to_string())
+        })
+    }
+
+    /// body of request of `index`, lossy utf-8
+    pub fn body(&self, index: usize) -> Option<String> {
+        let requests = self.requests.lock().expect("lock");
+        let (_, body) = requests.get(index)?.split_once("\r\n\r\n")?;
+        Some(body.to_string())
+    }
+}
+
+/// drain request head and body, return request path and the raw request
+async fn drain_request(socket: &mut TcpStream) -> (String, String) {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut req = vec![];
+    while let Ok(n) = socket.read(&mut buf).await {
+        req.extend_from_slice(&buf[..n]);
+        if n == 0 || request_complete(&req) {
+            break;
+        }
+    }
+    let req = String::from_utf8_lossy(&req).to_string();
+    let path = req
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    (path, req)
+}
+
+fn request_complete(req: &[u8]) -> bool {
+    let Some(pos) = req.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&req[..pos]).to_lowercase();
+    let length = head
+        .lines()
+        .find_map(|l| l.strip_prefix("content-length:"))
+        .and_then(|l| l.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    req.len() >= pos + 4 + length
+}
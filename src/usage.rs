@@ -0,0 +1,131 @@
+use std::sync::Mutex;
+
+use crate::error::*;
+use crate::proto::chat::ChatComplitionUsage;
+
+/// totals of a `UsageTracker` at some moment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageSnapshot {
+    /// sum of token usage reported by successful calls
+    pub usage: ChatComplitionUsage,
+    /// successful calls
+    pub requests: u64,
+    /// failed calls
+    pub errors: u64,
+}
+
+impl UsageSnapshot {
+    /// cost with prices per million prompt and completion tokens
+    pub fn cost(&self, prompt_per_million: f64, completion_per_million: f64) -> f64 {
+        (self.usage.prompt_tokens as f64 * prompt_per_million
+            + self.usage.completion_tokens as f64 * completion_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// thread-safe running totals of calls made by a client and its clones
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    snapshot: Mutex<UsageSnapshot>,
+}
+
+impl UsageTracker {
+    /// count a successful call with its usage, if reported
+    pub fn record(&self, usage: Option<&ChatComplitionUsage>) {
+        let mut snapshot = self.snapshot.lock().expect("usage lock");
+        snapshot.requests += 1;
+        if let Some(usage) = usage {
+            snapshot.usage += usage.clone();
+        }
+    }
+
+    /// count a failed call
+    pub fn record_error(&self) {
+        self.snapshot.lock().expect("usage lock").errors += 1;
+    }
+
+    /// count `res` as success or error
+    pub(crate) fn track<T>(
+        &self,
+        res: &Result<T>,
+        usage: impl FnOnce(&T) -> Option<&ChatComplitionUsage>,
+    ) {
+        match res {
+            Ok(r) => self.record(usage(r)),
+            Err(_) => self.record_error(),
+        }
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        self.snapshot.lock().expect("usage lock").clone()
+    }
+
+    /// clear totals, return the ones before
+    pub fn reset(&self) -> UsageSnapshot {
+        std::mem::take(&mut *self.snapshot.lock().expect("usage lock"))
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_usage_tracker_ok() -> Result<()> {
+    use crate::proto::chat::*;
+    use crate::tests::MockServer;
+
+    let lhs = ChatComplitionUsage::new(10, 5);
+    let rhs = ChatComplitionUsage {
+        cached_tokens: Some(4),
+        ..ChatComplitionUsage::new(20, 10)
+    };
+    let sum = lhs + rhs;
+    assert_eq!(sum.total_tokens, 45);
+    assert_eq!(sum.cached_tokens, Some(4));
+
+    let chat = r#"{"id":"1","object":"chat.completion","created":1,"model":"m","choices":[{"index":0,"message":{"role":"assistant","content":"42"},"finish_reason":"stop"}],"usage":{"prompt_tokens":10,"completion_tokens":2,"total_tokens":12}}"#;
+    let sse = [
+        r#"data: {"id":"2","choices":[{"index":0,"delta":{"role":"assistant","content":"hi"},"finish_reason":"stop"}]}"#,
+        r#"data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":1,"total_tokens":4}}"#,
+        "data: [DONE]",
+    ]
+    .map(|l| format!("{}\n\n", l))
+    .concat();
+
+    let server = MockServer::start(vec![
+        (200, "application/json", chat.to_string()),
+        (500, "application/json", r#"{"error":"boom"}"#.to_string()),
+        (200, "text/event-stream", sse),
+        (
+            200,
+            "text/event-stream",
+            "data: {\"choices\":[\n\n".to_string(),
+        ),
+    ])
+    .await;
+    let client = server.client()?;
+    let clone = client.clone();
+
+    let req = ChatCompletionRequest::simple("m", "answer")?;
+    req.call_once(&client, None).await?;
+    assert!(clone.chat("m", "answer", None).await.is_err());
+
+    let mut stream_req = req.clone();
+    stream_req.stream = Some(true);
+    let mut rx = stream_req.call_stream(&clone, None).await?;
+    while rx.recv().await.is_some() {}
+
+    // an error in the middle of a stream counts the call as failed
+    let mut rx = stream_req.call_stream(&clone, None).await?;
+    assert!(matches!(rx.recv().await, Some(Err(_))));
+    while rx.recv().await.is_some() {}
+
+    let snapshot = client.usage_snapshot();
+    assert_eq!(snapshot.requests, 2);
+    assert_eq!(snapshot.errors, 2);
+    assert_eq!(snapshot.usage, ChatComplitionUsage::new(13, 3));
+    assert_eq!(snapshot.cost(1.0, 2.0), 19.0 / 1_000_000.0);
+
+    assert_eq!(client.usage_tracker().reset(), snapshot);
+    assert_eq!(clone.usage_snapshot(), UsageSnapshot::default());
+
+    Ok(())
+}